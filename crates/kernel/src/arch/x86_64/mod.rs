@@ -9,22 +9,30 @@ use x86_64::{
 };
 
 pub(crate) mod acpi;
-pub(crate) mod lapic;
 mod interrupts;
+pub(crate) mod lapic;
 mod paging;
+mod pat;
 pub(crate) mod timer;
 mod unwind;
 
 pub use interrupts::{InterruptState, InterruptVector};
+#[cfg(feature = "verify-demand-paging")]
+pub use paging::reserve_lazy;
+pub use paging::{
+    handle_page_fault, init_paging_mode, map_write_combining, page_table_frames, translate,
+};
+#[cfg(feature = "unmap-freed-heap")]
+pub use paging::{remap_direct_mapped, unmap_direct_mapped};
 pub use timer::{set_oneshot, set_periodic};
 pub use unwind::UnwindState;
 
 /// Returns true if the given address is in user space (lower half).
 ///
-/// On x86_64, user space occupies the lower half of the virtual address space
-/// (addresses below 0x8000_0000_0000_0000).
+/// On x86_64, user space occupies the canonical lower half of the virtual address space:
+/// addresses below 0x0000_8000_0000_0000, or below 0x0100_0000_0000_0000 with 5-level paging.
 pub fn is_user_space(addr: usize) -> bool {
-    addr < 0x8000_0000_0000_0000
+    addr < 1 << (pmm::virtual_bits() - 1)
 }
 
 static TSS: spin::Once<TaskStateSegment> = spin::Once::new();
@@ -81,6 +89,8 @@ struct Selectors {
 }
 
 pub fn init() {
+    paging::log_paging_mode();
+    pat::init();
     let (gdt, selectors) = gdt();
     gdt.load();
     unsafe {
@@ -1,24 +1,171 @@
-use pmm::{AddressTranslator, PageDirectory, PageFlags, PhysicalAddress, VirtualAddress};
+use alloc::vec::Vec;
+use limine::{paging::PagingMode, request::PagingModeRequest};
+use pmm::{
+    AddressSpace, AddressTranslator, Backing, Mapping, MemoryType, PageDirectory, PageFlagBits,
+    PageFlags, PhysicalAddress, Protection, TlbFlush, VirtualAddress,
+};
+use x86_64::{
+    registers::control::{Cr4, Cr4Flags},
+    structures::idt::PageFaultErrorCode,
+};
+
+use crate::mem::KernelFrames;
+
+/// First address of the window in which kernel virtual memory areas are placed.
+///
+/// This sits above the HHDM, which Limine places at the start of the higher half, and well
+/// below the kernel image at the top of the address space.
+const KERNEL_SPACE_START: usize = 0xffff_c000_0000_0000;
+
+/// One past the last address of the kernel virtual memory area window.
+const KERNEL_SPACE_END: usize = 0xffff_e000_0000_0000;
+
+/// Asks Limine for 5-level paging when the CPU supports it, falling back to 4-level paging.
+#[used]
+#[unsafe(link_section = ".requests")]
+static PAGING_MODE_REQUEST: PagingModeRequest = PagingModeRequest::PREFER_MAXIMUM;
 
 /// The kernel's view of the active (Limine-set-up) page tables.
 ///
-/// Initialized lazily on first call to `map_mmio()`. The `PageDirectory` wraps the existing
-/// PML4 non-owingly — it will not free the underlying Limine page tables on drop.
-static KERNEL_PAGE_DIR: spin::Once<spin::Mutex<PageDirectory>> = spin::Once::new();
+/// Initialized lazily on first use. The `PageDirectory` wraps the existing PML4 non-owingly —
+/// it will not free the underlying Limine page tables on drop. Areas reserved through the
+/// address space live in `[KERNEL_SPACE_START, KERNEL_SPACE_END)`.
+static KERNEL_SPACE: spin::Once<spin::Mutex<AddressSpace>> = spin::Once::new();
 
-fn kernel_page_dir() -> &'static spin::Mutex<PageDirectory> {
-    KERNEL_PAGE_DIR.call_once(|| {
+fn kernel_space() -> &'static spin::Mutex<AddressSpace> {
+    KERNEL_SPACE.call_once(|| {
         // SAFETY: Called after `mem::use_pmm()`, which sets up the `AddressTranslator`.
         // The active page tables set up by Limine are valid and HHDM-accessible.
-        spin::Mutex::new(unsafe { PageDirectory::from_active_tables() })
+        let directory = unsafe { PageDirectory::from_active_tables() };
+        spin::Mutex::new(AddressSpace::with_window(
+            directory,
+            VirtualAddress::new(KERNEL_SPACE_START),
+            VirtualAddress::new(KERNEL_SPACE_END),
+        ))
     })
 }
 
+/// Tells the physical memory manager whether the bootloader enabled 4-level or 5-level paging.
+///
+/// `CR4.LA57` is the authority on the active mode. This must run before any virtual address is
+/// created, since canonical address checks depend on the level count.
+pub fn init_paging_mode() {
+    let la57 = Cr4::read().contains(Cr4Flags::L5_PAGING);
+    pmm::set_page_table_levels(if la57 { 5 } else { 4 });
+}
+
+/// Logs the paging mode chosen by [`init_paging_mode`], warning if Limine reported another one.
+pub(super) fn log_paging_mode() {
+    let levels = pmm::page_table_levels();
+    log::debug!(
+        "paging: {}-level, {}-bit virtual addresses",
+        levels,
+        pmm::virtual_bits()
+    );
+
+    let reported = PAGING_MODE_REQUEST.response().map(|response| response.mode);
+    let expected = if levels == 5 {
+        PagingMode::X86_64_5LVL
+    } else {
+        PagingMode::X86_64_4LVL
+    };
+    if reported.is_some_and(|mode| mode != expected) {
+        log::warn!(
+            "paging: Limine reported {:?}, but CR4.LA57 says {}-level",
+            reported,
+            levels
+        );
+    }
+}
+
+/// Returns the physical frames holding the active page tables.
+pub fn page_table_frames() -> Vec<PhysicalAddress> {
+    kernel_space().lock().directory().table_frames()
+}
+
+/// Returns the physical address `virt` is mapped to in the active page tables, if any.
+pub fn translate(virt: VirtualAddress) -> Option<PhysicalAddress> {
+    kernel_space()
+        .lock()
+        .directory()
+        .translate(virt)
+        .map(|(phys, _, _)| phys)
+}
+
+/// Reserves `size` bytes of kernel virtual memory that are backed on demand.
+///
+/// Nothing is allocated up front: the first access to each page faults, and the page-fault
+/// handler maps a freshly zeroed frame with the given protection before resuming. Returns
+/// `None` if no free range is large enough.
+#[cfg(feature = "verify-demand-paging")]
+pub fn reserve_lazy(
+    size: usize,
+    protection: Protection,
+    name: &'static str,
+) -> Option<VirtualAddress> {
+    let size = (size + 4095) & !(4096 - 1);
+    let mut space = kernel_space().lock();
+    let start = space.find_free(size, 4096)?;
+    match space.map(
+        &mut KernelFrames,
+        start,
+        size,
+        protection,
+        Backing::Lazy,
+        name,
+    ) {
+        Ok(flush) => flush.ignore(),
+        Err(err) => {
+            log::warn!("paging: lazy range {} not reserved: {:?}", name, err);
+            return None;
+        }
+    }
+
+    log::debug!(
+        "paging: reserved lazy range {} at {} ({} bytes)",
+        name,
+        start,
+        size
+    );
+    Some(start)
+}
+
+/// Tries to resolve a page fault at `addr` by populating a lazily-backed page.
+///
+/// Returns true if the faulting instruction can be resumed.
+pub fn handle_page_fault(addr: VirtualAddress, error_code: u64) -> bool {
+    let error_code = PageFaultErrorCode::from_bits_truncate(error_code);
+    let access = Protection {
+        write: error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE),
+        execute: error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH),
+        user: error_code.contains(PageFaultErrorCode::USER_MODE),
+    };
+
+    // A fault while the address space is locked comes from the paging code itself, and
+    // can't be resolved without deadlocking.
+    let Some(mut space) = KERNEL_SPACE.get().and_then(|space| space.try_lock()) else {
+        return false;
+    };
+
+    match space.handle_fault(&mut KernelFrames, addr, access) {
+        Ok(flush) => {
+            flush.flush();
+            true
+        }
+        Err(err) => {
+            log::error!("paging: page fault at {} not resolved: {:?}", addr, err);
+            false
+        }
+    }
+}
+
 /// Maps `[phys_base, phys_base + size)` into the active page tables at the corresponding
 /// HHDM virtual addresses.
 ///
-/// Pages are mapped with cache-disable flags (PWT+PCD, UC- memory type) for MMIO access.
-/// After all pages are mapped, the full TLB is flushed.
+/// Pages are mapped uncached (PWT+PCD, UC memory type) for MMIO access,
+/// using 2 MiB or 1 GiB pages where the region's alignment allows. The TLB is then flushed
+/// page by page, or in full for large regions. Ranges that are already (partly) mapped are
+/// left untouched and logged.
 ///
 /// # Safety
 /// Must be called after `mem::use_pmm()` so that the global allocator is available for
@@ -28,16 +175,176 @@ pub(crate) unsafe fn map_mmio(phys_base: usize, size: usize) {
     let start = phys_base & !(4096 - 1);
     let end = (phys_base + size + 4095) & !(4096 - 1);
 
-    let mut dir = kernel_page_dir().lock();
-    for phys in (start..end).step_by(4096) {
-        let virt = VirtualAddress::new(translator.phys_to_virt(phys));
-        let mut flags = PageFlags::empty();
-        flags.set_writable(true);
-        flags.set_write_through(true);
-        flags.set_cache_disable(true);
-        flags.set_no_execute(true);
-        dir.map(virt, PhysicalAddress::new(phys), flags);
+    let mut flags = PageFlags::empty();
+    flags.set_writable(true);
+    flags.set_memory_type(MemoryType::Uncached);
+    flags.set_no_execute(true);
+
+    let mut space = kernel_space().lock();
+    let dir = space.directory_mut();
+    let virt = VirtualAddress::new(translator.phys_to_virt(start));
+    match dir.map_range(virt, PhysicalAddress::new(start), end - start, flags) {
+        Ok(flush) => flush.flush(),
+        Err(err) => {
+            log::warn!(
+                "paging: MMIO range {:#x}..{:#x} not mapped: {:?}",
+                start,
+                end,
+                err
+            );
+            return;
+        }
+    }
+
+    if let Some((phys, flags, size)) = dir.translate(virt) {
+        log::trace!(
+            "paging: mapped MMIO {} -> {} ({:?}, {:?})",
+            virt,
+            phys,
+            size,
+            flags
+        );
+    }
+}
+
+/// Makes `[phys_base, phys_base + size)` write-combining, returning the kernel virtual address
+/// `phys_base` can be accessed through.
+///
+/// Mapping memory with different memory types at different addresses is undefined behaviour
+/// on x86_64, so memory the HHDM already covers is not mapped a second time: its HHDM pages
+/// are switched to write-combining in place, keeping their permissions and splitting any huge
+/// pages at either end of the range first, and the HHDM address is returned. Only memory outside the HHDM, such as MMIO
+/// above the end of RAM, gets a fresh range of kernel virtual memory. Returns `None` if no
+/// free range is large enough, the range is empty, or the pages could not be mapped.
+///
+/// The range should cover whole pages of device memory: any ordinary memory sharing its first
+/// or last page is made write-combining with it.
+///
+/// # Safety
+/// Must be called after `mem::use_pmm()` so that the global allocator is available for
+/// allocating any intermediate page table pages.
+pub unsafe fn map_write_combining(
+    phys_base: usize,
+    size: usize,
+    name: &'static str,
+) -> Option<VirtualAddress> {
+    if size == 0 {
+        return None;
     }
+    let start = phys_base & !(4096 - 1);
+    let end = (phys_base + size + 4095) & !(4096 - 1);
 
-    x86_64::instructions::tlb::flush_all();
+    let len = end - start;
+
+    let mut space = kernel_space().lock();
+    let direct = VirtualAddress::direct_mapped(PhysicalAddress::new(start));
+    let (virt, mapped) = if space.directory().translate(direct).is_some() {
+        let dir = space.directory_mut();
+        dir.split_huge_page(direct).flush();
+        dir.split_huge_page(direct + (len - 4096)).flush();
+        // Only the memory type changes: every page keeps its own permissions.
+        let direct_end = direct + len;
+        let mappings: Vec<Mapping> = dir
+            .mappings()
+            .filter(|m| m.virt < direct_end && m.virt + m.size > direct)
+            .collect();
+        let retyped = mappings.into_iter().try_for_each(|mapping| {
+            let from = mapping.virt.max(direct);
+            let to = (mapping.virt + mapping.size).min(direct_end);
+            let mut flags = mapping.flags;
+            flags.set_memory_type(MemoryType::WriteCombining);
+            dir.protect_range(from, to - from, flags)
+                .map(TlbFlush::flush)
+        });
+        (direct, retyped.map_err(Into::into))
+    } else {
+        let mut flags = Protection::READ_WRITE.page_flags();
+        flags.set_memory_type(MemoryType::WriteCombining);
+
+        let virt = space.find_free(len, 4096)?;
+        let mapped = space
+            .map(
+                &mut KernelFrames,
+                virt,
+                len,
+                Protection::READ_WRITE,
+                Backing::Physical(PhysicalAddress::new(start)),
+                name,
+            )
+            .and_then(|flush| {
+                // Nothing has touched the new pages yet; the flush below covers both changes.
+                flush.ignore();
+                Ok(space.directory_mut().protect_range(virt, len, flags)?)
+            })
+            .map(TlbFlush::flush);
+        (virt, mapped)
+    };
+    if let Err(err) = mapped {
+        log::warn!(
+            "paging: write-combining range {} ({:#x}..{:#x}) not mapped: {:?}",
+            name,
+            start,
+            end,
+            err
+        );
+        return None;
+    }
+
+    log::debug!(
+        "paging: mapped {} write-combining at {} ({} bytes)",
+        name,
+        virt,
+        len
+    );
+    Some(virt + (phys_base - start))
+}
+
+/// Unmaps the HHDM pages of `size` bytes of freed heap memory at `phys`, so that stale
+/// accesses to them fault. Returns the flags they were mapped with, for
+/// [`remap_direct_mapped`].
+///
+/// Huge HHDM pages at either end of the range are split first. Returns `None`, leaving the
+/// range mapped, if the address space is in use, since the heap is also freed from within the
+/// paging code. Only the TLB of the current CPU is flushed.
+#[cfg(feature = "unmap-freed-heap")]
+pub fn unmap_direct_mapped(phys: PhysicalAddress, size: usize) -> Option<PageFlags> {
+    let virt = VirtualAddress::direct_mapped(phys);
+    let mut space = KERNEL_SPACE.get()?.try_lock()?;
+    let dir = space.directory_mut();
+    let (_, flags, _) = dir.translate(virt)?;
+
+    dir.split_huge_page(virt).flush();
+    dir.split_huge_page(virt + (size - 4096)).flush();
+    match dir.unmap_range(virt, size) {
+        Ok(flush) => {
+            flush.flush();
+            Some(flags)
+        }
+        Err(err) => {
+            log::warn!("paging: freed heap at {} not unmapped: {:?}", phys, err);
+            None
+        }
+    }
+}
+
+/// Maps back the HHDM pages unmapped by [`unmap_direct_mapped`] with their original flags.
+///
+/// Returns false, leaving the range unmapped, if the address space is in use or the pages
+/// could not be mapped.
+#[cfg(feature = "unmap-freed-heap")]
+pub fn remap_direct_mapped(phys: PhysicalAddress, size: usize, flags: PageFlags) -> bool {
+    let Some(mut space) = KERNEL_SPACE.get().and_then(|space| space.try_lock()) else {
+        return false;
+    };
+    let virt = VirtualAddress::direct_mapped(phys);
+    match space.directory_mut().map_range(virt, phys, size, flags) {
+        Ok(flush) => {
+            flush.flush();
+            true
+        }
+        Err(err) => {
+            log::error!("paging: freed heap at {} not remapped: {:?}", phys, err);
+            false
+        }
+    }
 }
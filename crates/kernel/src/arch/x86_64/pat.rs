@@ -0,0 +1,22 @@
+//! Page Attribute Table setup.
+
+use pmm::PAT_LAYOUT;
+use x86_64::{instructions::tlb, registers::model_specific::Msr};
+
+const IA32_PAT_MSR: u32 = 0x277;
+
+/// Programs `IA32_PAT` with [`PAT_LAYOUT`], the layout `PageFlags::set_memory_type` encodes
+/// memory types against.
+///
+/// The layout keeps the entries that the power-on default and Limine both rely on, so mappings
+/// made before this runs keep their memory types.
+pub fn init() {
+    // SAFETY: Writing back the caches first and flushing the TLB afterwards keeps stale lines
+    // and translations of the old memory types from being used.
+    unsafe {
+        core::arch::asm!("wbinvd", options(nostack, preserves_flags));
+        Msr::new(IA32_PAT_MSR).write(PAT_LAYOUT);
+    }
+    tlb::flush_all();
+    log::trace!("PAT programmed: {:#018x}", PAT_LAYOUT);
+}
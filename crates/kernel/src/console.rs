@@ -58,6 +58,15 @@ impl Console {
         self.has_output
             .store(true, core::sync::atomic::Ordering::SeqCst);
     }
+
+    /// Runs `f` on the attached framebuffer writer, if any.
+    ///
+    /// The console is locked while `f` runs, so `f` must not log.
+    pub fn with_framebuffer(&self, f: impl FnOnce(&mut FrameBufferWriter)) {
+        if let Some(fb) = &mut *self.framebuffer.lock() {
+            f(fb);
+        }
+    }
 }
 
 impl log::Log for Console {
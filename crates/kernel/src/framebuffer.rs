@@ -85,6 +85,17 @@ impl FrameBufferWriter {
         logger
     }
 
+    /// Moves the writer to another mapping of the same framebuffer.
+    ///
+    /// # Safety
+    /// `address` must map the whole framebuffer, readable and writable, for the rest of the
+    /// kernel's lifetime.
+    pub unsafe fn relocate(&mut self, address: *mut u8) {
+        let len = self.framebuffer.len();
+        // SAFETY: The caller guarantees that `address` maps the framebuffer's `len` bytes.
+        self.framebuffer = unsafe { core::slice::from_raw_parts_mut(address, len) };
+    }
+
     fn newline(&mut self) {
         self.y_pos += font_constants::CHAR_RASTER_HEIGHT.val() + LINE_SPACING;
         self.carriage_return()
@@ -185,3 +196,37 @@ pub fn init(console: &Console) {
     let fb_writer = FrameBufferWriter::new(framebuffer);
     console.attach_framebuffer(fb_writer);
 }
+
+/// Makes the console's framebuffer write-combining.
+///
+/// Limine's mapping leaves the framebuffer in whatever memory type the bootloader chose; with
+/// write-combining, the stores made while rendering are merged into bursts instead of reaching
+/// the device one by one. A framebuffer inside the HHDM keeps its address and only changes
+/// type; one outside it is moved to a new mapping. The console keeps the old mapping if the
+/// framebuffer can't be remapped.
+///
+/// Must be called after `mem::use_pmm()`, since remapping may need page tables.
+pub fn use_write_combining(console: &Console) {
+    let Some(framebuffer) = FRAMEBUFFER_REQUEST
+        .response()
+        .and_then(|fb_info| fb_info.framebuffers().get(0).copied())
+    else {
+        return;
+    };
+    let virt = VirtualAddress::from_ptr(framebuffer.address());
+    let Some(phys) = crate::arch::translate(virt) else {
+        log::warn!("framebuffer at {} is not mapped", virt);
+        return;
+    };
+
+    let size = (framebuffer.pitch * framebuffer.height) as usize;
+    // SAFETY: The physical memory manager is in use, as required by the caller.
+    let Some(mapped) =
+        (unsafe { crate::arch::map_write_combining(phys.as_usize(), size, "framebuffer") })
+    else {
+        return;
+    };
+    // SAFETY: The write-combining mapping covers the framebuffer and is never unmapped.
+    console.with_framebuffer(|writer| unsafe { writer.relocate(mapped.as_mut_ptr()) });
+    log::debug!("framebuffer remapped write-combining at {}", mapped);
+}
@@ -70,6 +70,16 @@ pub fn take_current_interrupt_context() -> Option<InterruptContext> {
 }
 
 pub fn interrupt_was_received(context: InterruptContext) {
+    // Page faults on lazily-backed memory are resolved in place, and the faulting instruction
+    // is resumed.
+    if let InterruptKind::PageFault {
+        faulting_address: Some(addr),
+    } = context.kind()
+        && arch::handle_page_fault(*addr, context.error_code().unwrap_or(0))
+    {
+        return;
+    }
+
     if crate::mem::can_allocate() {
         // Store the interrupt context in a global, chained with the previous one.
         INTERRUPT_CONTEXT_CHAIN.lock().push_front(context.clone());
@@ -77,6 +87,29 @@ pub fn interrupt_was_received(context: InterruptContext) {
 
     log::trace!("interrupt received: {:?}", context);
 
+    if let InterruptKind::PageFault { faulting_address } = context.kind() {
+        // Freed heap blocks are unmapped for a while, so a fault on one is a use after free.
+        #[cfg(feature = "unmap-freed-heap")]
+        if let Some(addr) = faulting_address
+            && let Some((phys, order)) = crate::mem::quarantined_heap_block(*addr)
+        {
+            panic!(
+                "use after free: page fault at {} (ip {}, error code {:?}) in freed order-{} heap block at {}",
+                addr,
+                context.instruction_pointer(),
+                context.error_code(),
+                order,
+                phys
+            );
+        }
+        panic!(
+            "unhandled page fault at {:?} (ip {}, error code {:?})",
+            faulting_address,
+            context.instruction_pointer(),
+            context.error_code()
+        );
+    }
+
     panic!("interrupt handling not yet implemented");
     // let state = UnwindState::for_interrupt(context);
     // crate::unwind::unwind_stack(state);
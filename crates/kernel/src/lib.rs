@@ -26,6 +26,8 @@ static BASE_REVISION: BaseRevision = BaseRevision::with_revision(6);
 
 pub fn kernel_main(stack_start: usize) -> ! {
     assert!(BASE_REVISION.is_supported());
+    // Canonical address checks depend on the paging mode, so settle it before anything else.
+    arch::init_paging_mode();
 
     // SAFETY: We're only calling this once, before any other CPUs are running.
     unsafe {
@@ -44,9 +46,30 @@ pub fn kernel_main(stack_start: usize) -> ! {
     mem::use_pmm(pmm);
     log::debug!("Physical Memory Manager initialized and in use");
 
+    framebuffer::use_write_combining(console);
+
+    #[cfg(feature = "verify-demand-paging")]
+    {
+        let page = arch::reserve_lazy(4096, pmm::Protection::READ_WRITE, "demand paging check")
+            .expect("failed to reserve a lazily-backed page");
+        let ptr = page.as_mut_ptr::<u64>();
+        // SAFETY: The page was just reserved; the first access faults in a zeroed frame.
+        unsafe {
+            assert_eq!(ptr.read_volatile(), 0, "lazily-backed page is not zeroed");
+            ptr.write_volatile(0x5ca7_c4);
+            assert_eq!(ptr.read_volatile(), 0x5ca7_c4);
+        }
+        log::debug!("Demand paging verified at {}", page);
+    }
+
     arch::init_timers();
     log::debug!("Timer subsystem initialized");
 
+    // ACPI tables have been parsed and Limine responses copied, so boot memory can go.
+    modules::init();
+    mem::reclaim_boot_memory();
+    log::debug!("Boot memory reclaimed");
+
     arch::set_periodic(1000, || log::info!("Timer tick"));
     arch::set_oneshot(5000, || log::info!("One-shot triggered"));
 
@@ -1,11 +1,15 @@
 // cSpell:ignore Hhdm
 
+use alloc::vec::Vec;
 use core::ptr::NonNull;
 use limine::{
     memmap::{self, Entry},
     request::{HhdmRequest, MemmapRequest, StackSizeRequest},
 };
-use pmm::{BlockAllocator, BootMemoryRegion, MemoryMap, PhysicalAddress, VirtualAddress};
+use pmm::{
+    BlockAllocator, BootMemoryRegion, FrameAllocator, HumanSize, MemoryMap, PhysicalAddress,
+    SlabAllocator, VirtualAddress,
+};
 
 use crate::image::LinkerSection;
 
@@ -111,6 +115,13 @@ impl BootMemoryRegion for LimineMemoryRegion<'_> {
     fn is_usable(&self) -> bool {
         self.0.type_ == memmap::MEMMAP_USABLE
     }
+
+    fn is_reclaimable(&self) -> bool {
+        matches!(
+            self.0.type_,
+            memmap::MEMMAP_BOOTLOADER_RECLAIMABLE | memmap::MEMMAP_ACPI_RECLAIMABLE
+        )
+    }
 }
 
 /// Initializes the block allocator by scanning the memory map.
@@ -150,14 +161,18 @@ pub fn init_allocator() {
 }
 
 /// Switches the kernel allocator to use the physical memory manager.
+///
+/// The PMM takes over the block allocator's memory, including everything it has handed out
+/// so far, so heap allocations made during early boot can still be freed afterwards.
 pub fn use_pmm(pmm: pmm::PhysicalMemoryManager) {
     KERNEL_ALLOCATOR.use_pmm(pmm);
+    pmm::set_page_table_allocator(&KernelPageTables);
 }
 
 /// Initializes the physical memory manager.
 ///
-/// Returns a PhysicalMemoryManager with all non-usable regions marked as reserved
-/// and all usable regions freed into the allocator.
+/// Returns a PhysicalMemoryManager with all non-usable regions marked as reserved. Usable
+/// memory is added once the PMM takes over from the block allocator in [`use_pmm`].
 pub fn init_pmm() -> pmm::PhysicalMemoryManager {
     let boot_memmap = MEMORY_MAP_REQUEST
         .response()
@@ -187,74 +202,234 @@ pub fn init_pmm() -> pmm::PhysicalMemoryManager {
 
     let mut pmm = pmm::PhysicalMemoryManager::new(memory_map);
 
-    // Free usable memory into the PMM
-    // We deallocate at the maximum order (MAX_ORDER) to minimize fragmentation
-    const MAX_ORDER: usize = 11;
-    const PAGE_SIZE: u64 = 4096;
-    let max_block_size = (1u64 << MAX_ORDER) * PAGE_SIZE;
-
+    // Make sure the kernel image can never be handed out, even if it shares a section with
+    // usable memory
     for entry in boot_memmap.iter() {
-        if entry.type_ == memmap::MEMMAP_USABLE {
-            let mut addr = entry.base;
-            let end = entry.base + entry.length;
+        if entry.type_ == memmap::MEMMAP_EXECUTABLE_AND_MODULES {
+            pmm.mark_kernel_image(
+                PhysicalAddress::new(entry.base as usize),
+                entry.length as usize,
+            );
+        }
+    }
 
-            // Align start address up to page boundary
-            if addr % PAGE_SIZE != 0 {
-                addr = (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
-            }
+    #[cfg(any(debug_assertions, feature = "verify-pmm"))]
+    if let Err(error) = pmm.verify() {
+        panic!("physical memory manager is inconsistent after init: {}", error);
+    }
 
-            // Free memory in the largest possible blocks
-            while addr + max_block_size <= end {
-                pmm.deallocate(PhysicalAddress::new(addr as usize), MAX_ORDER);
-                addr += max_block_size;
-            }
+    pmm
+}
 
-            // Free remaining memory in smaller blocks
-            for order in (0..MAX_ORDER).rev() {
-                let block_size = (1u64 << order) * PAGE_SIZE;
-                while addr + block_size <= end {
-                    pmm.deallocate(PhysicalAddress::new(addr as usize), order);
-                    addr += block_size;
+/// Frees bootloader-reclaimable and ACPI-reclaimable memory into the PMM.
+///
+/// Must only be called once the ACPI tables have been parsed and no Limine responses will be
+/// read again (see `modules::init`). Frames that are still in use even though they live in
+/// reclaimable memory, namely the active page tables and the boot stack, are kept reserved.
+pub fn reclaim_boot_memory() {
+    // The memory map response itself lives in bootloader-reclaimable memory, so copy out the
+    // ranges before any of it is freed.
+    let regions: Vec<(PhysicalAddress, usize, u64)> = MEMORY_MAP_REQUEST
+        .response()
+        .expect("Memory map request should have been answered")
+        .entries()
+        .iter()
+        .filter(|entry| LimineMemoryRegion(entry).is_reclaimable())
+        .map(|entry| {
+            (
+                PhysicalAddress::new(entry.base as usize),
+                entry.length as usize,
+                entry.type_,
+            )
+        })
+        .collect();
+
+    let mut in_use = crate::arch::page_table_frames();
+    // SAFETY: Stack bounds are set once during initialization, before this is called.
+    let (stack_start, stack_end) = unsafe { (STACK_START, STACK_END) };
+    // The bootloader's stack grows down from the address recorded at entry, so keep a full
+    // stack's worth of pages on both sides of it.
+    let stack_bottom = stack_start.saturating_sub(STACK_SIZE) & !(4096 - 1);
+    in_use.extend(
+        (stack_bottom..stack_end)
+            .step_by(4096)
+            .filter_map(|page| crate::arch::translate(VirtualAddress::new(page))),
+    );
+    in_use.sort_unstable();
+    in_use.dedup();
+
+    // Nothing may be logged while the allocator is locked for writing: logging can allocate,
+    // which would spin on the lock forever. Room for the per-region counts is made up front.
+    let mut reclaimed = alloc::vec![0; regions.len()];
+    let stats = {
+        let mut inner = KERNEL_ALLOCATOR.inner.write();
+        match &mut *inner {
+            InnerAllocator::PhysicalMemoryManager { pmm, .. } => {
+                for (count, &(base, size, _)) in reclaimed.iter_mut().zip(&regions) {
+                    *count = pmm
+                        .reclaim_region(base, size, |frame| in_use.binary_search(&frame).is_ok());
                 }
+                Some(pmm.stats())
             }
+            _ => None,
         }
-    }
+    };
+    let Some(stats) = stats else {
+        log::warn!("mem: boot memory not reclaimed, the PMM is not in use");
+        return;
+    };
 
-    pmm
+    for (&count, &(base, size, type_)) in reclaimed.iter().zip(&regions) {
+        log::debug!(
+            "mem: reclaimed {} of {} at {} ({})",
+            HumanSize::from(count * 4096),
+            HumanSize::from(size),
+            base,
+            type_name(type_)
+        );
+    }
+    log::info!(
+        "mem: reclaimed {} of boot memory, {} frames free",
+        HumanSize::from(reclaimed.iter().sum::<usize>() * 4096),
+        stats.free_frames
+    );
+    log::debug!("mem: pmm stats after reclaim:\n{}", stats);
 }
 
 pub fn can_allocate() -> bool {
     KERNEL_ALLOCATOR.can_allocate()
 }
 
+/// Frame allocator backed by the kernel allocator's physical memory manager.
+///
+/// The kernel allocator is only read-locked for the duration of each call, so page tables can
+/// still be allocated from the heap while an address space is being populated.
+pub struct KernelFrames;
+
+impl FrameAllocator for KernelFrames {
+    fn allocate_frame(&mut self) -> Option<PhysicalAddress> {
+        match &*KERNEL_ALLOCATOR.inner.read() {
+            InnerAllocator::PhysicalMemoryManager { pmm, .. } => pmm.allocate(0).ok(),
+            _ => None,
+        }
+    }
+
+    fn release_frame(&mut self, frame: PhysicalAddress) {
+        match &*KERNEL_ALLOCATOR.inner.read() {
+            InnerAllocator::PhysicalMemoryManager { pmm, .. } => {
+                pmm.put_frame(frame);
+            }
+            _ => log::error!("mem: frame {} released without a PMM", frame),
+        }
+    }
+
+    fn share_frame(&mut self, frame: PhysicalAddress) {
+        match &*KERNEL_ALLOCATOR.inner.read() {
+            InnerAllocator::PhysicalMemoryManager { pmm, .. } => {
+                pmm.get_frame(frame);
+            }
+            _ => log::error!("mem: frame {} shared without a PMM", frame),
+        }
+    }
+
+    fn frame_references(&mut self, frame: PhysicalAddress) -> u32 {
+        match &*KERNEL_ALLOCATOR.inner.read() {
+            InnerAllocator::PhysicalMemoryManager { pmm, .. } => pmm.frame_references(frame),
+            _ => 0,
+        }
+    }
+}
+
+/// Page table allocator backed by the kernel allocator's physical memory manager.
+///
+/// Tables are tagged as page tables in the PMM's frame metadata. They are freed through the
+/// heap, which also takes back the tables allocated from it before the PMM took over.
+struct KernelPageTables;
+
+impl pmm::PageTableAllocator for KernelPageTables {
+    fn allocate_table(&self) -> Option<PhysicalAddress> {
+        match &*KERNEL_ALLOCATOR.inner.read() {
+            InnerAllocator::PhysicalMemoryManager { pmm, .. } => pmm.allocate_page_table().ok(),
+            _ => None,
+        }
+    }
+
+    fn free_table(&self, frame: PhysicalAddress) {
+        let layout = alloc::alloc::Layout::from_size_align(4096, 4096).unwrap();
+        // SAFETY: Page tables are single direct-mapped frames, allocated either by the PMM or
+        // by the heap with this layout, and the page directory no longer refers to this one.
+        unsafe {
+            alloc::alloc::dealloc(VirtualAddress::direct_mapped(frame).as_mut_ptr(), layout);
+        }
+    }
+}
+
 #[global_allocator]
 static KERNEL_ALLOCATOR: KernelAllocator = KernelAllocator {
-    inner: spin::Mutex::new(InnerAllocator::None),
+    inner: spin::RwLock::new(InnerAllocator::None),
 };
 
+/// The kernel heap.
+///
+/// Allocations only take the lock for reading: the PMM can be used from any number of CPUs at
+/// once, and the slab allocator has a lock of its own. The lock is only taken for writing to
+/// switch allocators or reclaim memory.
 struct KernelAllocator {
-    inner: spin::Mutex<InnerAllocator>,
+    inner: spin::RwLock<InnerAllocator>,
 }
 
 enum InnerAllocator {
     None,
-    BlockAllocator(BlockAllocator),
-    PhysicalMemoryManager(pmm::PhysicalMemoryManager),
+    BlockAllocator(spin::Mutex<BlockAllocator>),
+    PhysicalMemoryManager {
+        pmm: pmm::PhysicalMemoryManager,
+        slabs: spin::Mutex<SlabAllocator>,
+    },
 }
 
 impl KernelAllocator {
     pub fn use_block_allocator(&self, allocator: BlockAllocator) {
-        let mut inner = self.inner.lock();
-        *inner = InnerAllocator::BlockAllocator(allocator);
+        let mut inner = self.inner.write();
+        *inner = InnerAllocator::BlockAllocator(spin::Mutex::new(allocator));
     }
 
-    pub fn use_pmm(&self, pmm: pmm::PhysicalMemoryManager) {
-        let mut inner = self.inner.lock();
-        *inner = InnerAllocator::PhysicalMemoryManager(pmm);
+    pub fn use_pmm(&self, mut pmm: pmm::PhysicalMemoryManager) {
+        let mut inner = self.inner.write();
+
+        // Import the block allocator in the same critical section, so nothing can be allocated
+        // from it after its allocations have been recorded. Importing does not allocate.
+        match core::mem::replace(&mut *inner, InnerAllocator::None) {
+            InnerAllocator::BlockAllocator(allocator) => {
+                pmm.import_block_allocator(allocator.into_inner())
+            }
+            InnerAllocator::None => {}
+            InnerAllocator::PhysicalMemoryManager { .. } => {
+                panic!("mem: the PMM is already in use")
+            }
+        }
+
+        for zone in pmm::Zone::ALL {
+            log::info!(
+                "zone {:?}: {} frames managed, {} free, watermarks {:?}",
+                zone,
+                pmm.zone_managed_frames(zone),
+                pmm.zone_free_frames(zone),
+                pmm.watermarks(zone)
+            );
+        }
+        log::info!(
+            "mem: {} frames still allocated from early boot",
+            pmm.allocated_frames()
+        );
+
+        *inner = InnerAllocator::PhysicalMemoryManager {
+            pmm,
+            slabs: spin::Mutex::new(SlabAllocator::new()),
+        };
     }
 
     pub fn can_allocate(&self) -> bool {
-        match &*self.inner.lock() {
+        match &*self.inner.read() {
             InnerAllocator::None => false,
             _ => true,
         }
@@ -265,14 +440,24 @@ unsafe impl alloc::alloc::GlobalAlloc for KernelAllocator {
     unsafe fn alloc(&self, layout: alloc::alloc::Layout) -> *mut u8 {
         use alloc::alloc::Allocator;
 
-        match &mut *self.inner.lock() {
+        match &*self.inner.read() {
             InnerAllocator::None => core::ptr::null_mut(),
             InnerAllocator::BlockAllocator(allocator) => allocator
+                .lock()
                 .allocate(layout)
                 .map(|pa| pa.cast().as_ptr())
                 .inspect_err(|e| log::error!("block allocator error: {:?}", e))
                 .unwrap_or(core::ptr::null_mut()),
-            InnerAllocator::PhysicalMemoryManager(pmm) => {
+            InnerAllocator::PhysicalMemoryManager { pmm, slabs }
+                if SlabAllocator::handles(layout) =>
+            {
+                slabs
+                    .lock()
+                    .allocate(pmm, layout)
+                    .map(|ptr| ptr.as_ptr())
+                    .unwrap_or(core::ptr::null_mut())
+            }
+            InnerAllocator::PhysicalMemoryManager { pmm, .. } => {
                 // Calculate the order needed for this allocation
                 let size = layout.size().max(layout.align());
                 let pages = (size + 4095) / 4096; // Round up to pages
@@ -293,12 +478,27 @@ unsafe impl alloc::alloc::GlobalAlloc for KernelAllocator {
             return;
         };
 
-        match &mut *self.inner.lock() {
+        match &*self.inner.read() {
             InnerAllocator::None => {}
             InnerAllocator::BlockAllocator(allocator) => unsafe {
-                allocator.deallocate(ptr_nn, layout);
+                allocator.lock().deallocate(ptr_nn, layout);
             },
-            InnerAllocator::PhysicalMemoryManager(pmm) => {
+            // Memory allocated before the PMM took over was not sized by the slab or buddy
+            // allocators, whatever the layout says.
+            InnerAllocator::PhysicalMemoryManager { pmm, .. }
+                if pmm.is_boot_allocation(PhysicalAddress::from_direct_mapped(
+                    VirtualAddress::from_ptr(ptr),
+                )) =>
+            {
+                let phys = PhysicalAddress::from_direct_mapped(VirtualAddress::from_ptr(ptr));
+                pmm.deallocate_boot(phys, layout.size());
+            }
+            InnerAllocator::PhysicalMemoryManager { pmm, slabs }
+                if SlabAllocator::handles(layout) =>
+            unsafe {
+                slabs.lock().deallocate(pmm, ptr_nn, layout);
+            },
+            InnerAllocator::PhysicalMemoryManager { pmm, .. } => {
                 // Calculate the order for this deallocation
                 let size = layout.size().max(layout.align());
                 let pages = (size + 4095) / 4096;
@@ -307,12 +507,101 @@ unsafe impl alloc::alloc::GlobalAlloc for KernelAllocator {
                 // Convert virtual address back to physical
                 let virt = pmm::VirtualAddress::from_ptr(ptr);
                 let phys = pmm::PhysicalAddress::from_direct_mapped(virt);
+                #[cfg(feature = "unmap-freed-heap")]
+                if quarantine(pmm, phys, order) {
+                    return;
+                }
                 pmm.deallocate(phys, order);
             }
         }
     }
 }
 
+/// Number of freed heap blocks kept unmapped from the HHDM before they go back to the PMM.
+#[cfg(feature = "unmap-freed-heap")]
+const QUARANTINE_SIZE: usize = 64;
+
+/// A freed heap block that is unmapped from the HHDM.
+#[cfg(feature = "unmap-freed-heap")]
+#[derive(Clone, Copy)]
+struct QuarantinedBlock {
+    phys: PhysicalAddress,
+    order: usize,
+    /// The flags the block was mapped with in the HHDM.
+    flags: pmm::PageFlags,
+}
+
+#[cfg(feature = "unmap-freed-heap")]
+impl QuarantinedBlock {
+    fn size(&self) -> usize {
+        4096 << self.order
+    }
+
+    fn contains(&self, addr: VirtualAddress) -> bool {
+        let start = VirtualAddress::direct_mapped(self.phys).as_usize();
+        (start..start + self.size()).contains(&addr.as_usize())
+    }
+}
+
+/// Ring of the most recently freed heap blocks, oldest first from `next`.
+///
+/// Buddy-allocated heap blocks are unmapped from the HHDM when freed, so a use after free
+/// faults instead of reading or corrupting whatever reuses the memory. Each block stays
+/// unmapped until [`QUARANTINE_SIZE`] more blocks have been freed. Slab objects share their
+/// pages with live objects, so they are never unmapped.
+#[cfg(feature = "unmap-freed-heap")]
+struct Quarantine {
+    blocks: [Option<QuarantinedBlock>; QUARANTINE_SIZE],
+    next: usize,
+}
+
+#[cfg(feature = "unmap-freed-heap")]
+static QUARANTINE: spin::Mutex<Quarantine> = spin::Mutex::new(Quarantine {
+    blocks: [None; QUARANTINE_SIZE],
+    next: 0,
+});
+
+/// Unmaps a freed heap block and holds it back from the PMM, releasing the oldest block held
+/// back if the quarantine is full. Returns false if the block should be freed right away.
+///
+/// The quarantine and the kernel address space are only ever tried, never waited for: the
+/// heap is freed from within the paging code, and from within this function when unmapping
+/// frees a page table.
+#[cfg(feature = "unmap-freed-heap")]
+fn quarantine(pmm: &pmm::PhysicalMemoryManager, phys: PhysicalAddress, order: usize) -> bool {
+    let Some(mut quarantine) = QUARANTINE.try_lock() else {
+        return false;
+    };
+    let next = quarantine.next;
+    if let Some(oldest) = quarantine.blocks[next] {
+        if !crate::arch::remap_direct_mapped(oldest.phys, oldest.size(), oldest.flags) {
+            return false;
+        }
+        quarantine.blocks[next] = None;
+        pmm.deallocate(oldest.phys, oldest.order);
+    }
+
+    let Some(flags) = crate::arch::unmap_direct_mapped(phys, 4096 << order) else {
+        return false;
+    };
+    quarantine.blocks[next] = Some(QuarantinedBlock { phys, order, flags });
+    quarantine.next = (next + 1) % QUARANTINE_SIZE;
+    true
+}
+
+/// Returns the physical address and order of the freed heap block whose HHDM pages contain
+/// `addr`, if it is still unmapped.
+#[cfg(feature = "unmap-freed-heap")]
+pub fn quarantined_heap_block(addr: VirtualAddress) -> Option<(PhysicalAddress, usize)> {
+    QUARANTINE
+        .try_lock()?
+        .blocks
+        .iter()
+        .flatten()
+        .find(|block| block.contains(addr))
+        .map(|block| (block.phys, block.order))
+}
+
 static mut STACK_START: usize = 0;
 static mut STACK_END: usize = 0;
 
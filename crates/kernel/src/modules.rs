@@ -1,3 +1,4 @@
+use alloc::{boxed::Box, vec::Vec};
 use core::ffi::CStr;
 
 use limine::request::ModulesRequest;
@@ -43,13 +44,44 @@ impl ModuleName {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct Module {
     pub data: &'static [u8],
     pub name: ModuleName,
 }
 
+/// Modules copied out of the Limine response, which lives in bootloader-reclaimable memory.
+static LOADED_MODULES: spin::Once<Vec<Module>> = spin::Once::new();
+
+/// Copies the module list out of the Limine response.
+///
+/// Module contents live in executable-and-modules memory, which is never reclaimed, but the
+/// response and the module paths do not. This must run before `mem::reclaim_boot_memory`.
+pub fn init() {
+    LOADED_MODULES.call_once(|| {
+        let Some(response) = MODULES.response() else {
+            return Vec::new();
+        };
+        response
+            .modules()
+            .iter()
+            .map(|module| Module {
+                data: module.data(),
+                name: ModuleName::new(Box::leak(Box::<str>::from(module.path()))),
+            })
+            .collect()
+    });
+}
+
 impl Module {
     pub fn get(module_name: ModuleName) -> Option<Module> {
+        if let Some(modules) = LOADED_MODULES.get() {
+            return modules
+                .iter()
+                .find(|module| module.name == module_name)
+                .copied();
+        }
+
         let modules = MODULES.response()?.modules();
         for module in modules {
             let name = ModuleName::new(module.path());
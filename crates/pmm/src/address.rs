@@ -137,6 +137,15 @@ impl AddressTranslator {
         self.virt_to_phys(ptr as usize)
     }
 
+    /// Returns the emulated memory behind this translator, if it is an emulated one.
+    #[cfg(any(test, feature = "software-emulation"))]
+    pub fn emulated_memory(&self) -> Option<&EmulatedMemory> {
+        match self {
+            Self::Hardware { .. } => None,
+            Self::Emulated(mem) => Some(mem),
+        }
+    }
+
     /// Allocates memory from the emulated space (test mode only).
     ///
     /// Returns the physical address of the allocated block, or None if
@@ -369,7 +378,7 @@ impl VirtualAddress {
     ///
     /// Panics if the address is not canonical for the architecture.
     #[inline]
-    pub const fn new(addr: usize) -> Self {
+    pub fn new(addr: usize) -> Self {
         assert!(
             crate::arch::validate_virtual(addr),
             "address is not canonical"
@@ -2,33 +2,1792 @@
 //!
 //! This module provides architecture-independent types for managing virtual address spaces,
 //! which may belong to the kernel, user processes, or other contexts.
+//!
+//! An address space is made of virtual memory areas ([`Vma`]s): non-overlapping, page-aligned
+//! ranges that record what the range is for and how it is backed. All changes to the page
+//! tables go through the address space's [`PageDirectory`].
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::ptr;
+
+use crate::page_directory::{MapError, Mapping, TlbFlush, address_space_size};
+use crate::{
+    FrameAllocator, PageDirectory, PageFlagBits, PageFlags, PhysicalAddress, VirtualAddress, arch,
+};
+
+/// Errors returned by [`AddressSpace`] operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaError {
+    /// The range is empty, not page-aligned, or outside the address space's window.
+    InvalidRange,
+    /// The range overlaps an existing area.
+    Overlap,
+    /// Part of the range is not covered by a mapped area.
+    NotMapped,
+    /// There is no free gap large enough.
+    NoSpace,
+    /// Physical memory ran out while populating the range.
+    OutOfMemory,
+    /// The access is not permitted by the area's protection.
+    ProtectionViolation,
+    /// The page directory rejected the change.
+    Map(MapError),
+}
+
+impl From<MapError> for VmaError {
+    fn from(err: MapError) -> Self {
+        VmaError::Map(err)
+    }
+}
+
+/// Access permissions of a virtual memory area.
+///
+/// Mapped memory is always readable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Protection {
+    /// The area may be written to.
+    pub write: bool,
+    /// Code in the area may be executed.
+    pub execute: bool,
+    /// The area is accessible from user mode.
+    pub user: bool,
+}
+
+impl Protection {
+    /// Read-only, non-executable kernel memory.
+    pub const READ: Self = Self {
+        write: false,
+        execute: false,
+        user: false,
+    };
 
-use crate::arch::PageTable;
+    /// Writable, non-executable kernel memory.
+    pub const READ_WRITE: Self = Self {
+        write: true,
+        execute: false,
+        user: false,
+    };
+
+    /// Read-only, executable kernel memory.
+    pub const READ_EXECUTE: Self = Self {
+        write: false,
+        execute: true,
+        user: false,
+    };
+
+    /// Returns these permissions accessible from user mode.
+    pub const fn with_user(self) -> Self {
+        Self { user: true, ..self }
+    }
+
+    /// Returns true if these permissions allow every kind of access in `access`.
+    pub fn permits(self, access: Protection) -> bool {
+        (self.write || !access.write)
+            && (self.execute || !access.execute)
+            && (self.user || !access.user)
+    }
+
+    /// Returns the page flags implementing these permissions.
+    pub fn page_flags(self) -> PageFlags {
+        self.apply_to(PageFlags::empty())
+    }
+
+    /// Returns `flags` with its permission bits replaced by these permissions, keeping every
+    /// other bit, such as the memory type.
+    pub fn apply_to(self, mut flags: PageFlags) -> PageFlags {
+        flags.set_present(true);
+        flags.set_writable(self.write);
+        flags.set_no_execute(!self.execute);
+        flags.set_user(self.user);
+        flags
+    }
+}
+
+/// What provides the memory behind a virtual memory area.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    /// Nothing: the range is only reserved, and nothing is mapped into it.
+    Reserved,
+    /// Zero-filled memory allocated from the physical memory manager.
+    Anonymous,
+    /// Zero-filled memory like [`Backing::Anonymous`], but each page is only allocated when it
+    /// is first accessed, through [`AddressSpace::handle_fault`].
+    Lazy,
+    /// A fixed range of physical memory, such as an MMIO region, starting at the given address.
+    Physical(PhysicalAddress),
+    /// A file mapping.
+    ///
+    /// This is a placeholder: the area is recorded, but no pages are populated for it yet.
+    File,
+}
+
+/// A virtual memory area: a page-aligned range of an address space with uniform protection
+/// and backing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vma {
+    start: VirtualAddress,
+    end: VirtualAddress,
+    protection: Protection,
+    backing: Backing,
+    name: &'static str,
+}
+
+impl Vma {
+    /// Returns the first address of the area.
+    pub fn start(&self) -> VirtualAddress {
+        self.start
+    }
+
+    /// Returns the address one past the end of the area.
+    pub fn end(&self) -> VirtualAddress {
+        self.end
+    }
+
+    /// Returns the size of the area in bytes.
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Returns true if the area contains the given address.
+    pub fn contains(&self, addr: VirtualAddress) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// Returns the access permissions of the area.
+    pub fn protection(&self) -> Protection {
+        self.protection
+    }
+
+    /// Returns what backs the area.
+    pub fn backing(&self) -> Backing {
+        self.backing
+    }
+
+    /// Returns the name of the area, for diagnostics.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Splits the area at `addr`, which must lie strictly inside it.
+    fn split_at(self, addr: VirtualAddress) -> (Vma, Vma) {
+        debug_assert!(self.start < addr && addr < self.end);
+        let backing = match self.backing {
+            Backing::Physical(phys) => Backing::Physical(phys + (addr - self.start)),
+            backing => backing,
+        };
+        (
+            Vma { end: addr, ..self },
+            Vma {
+                start: addr,
+                backing,
+                ..self
+            },
+        )
+    }
+}
 
 /// An address space is an architecture-independent representation of a virtual address space.
 ///
-/// Each address space owns a page table that maps virtual addresses to physical addresses.
+/// Each address space owns a page directory that maps virtual addresses to physical addresses,
+/// and a sorted map of the virtual memory areas within its window of the virtual address space.
 /// Address spaces can belong to the kernel, user processes, or other contexts.
+///
+/// Dropping an address space frees its page tables, but not the frames behind its anonymous
+/// and lazy areas, since that takes a frame allocator: use [`destroy`](Self::destroy) instead.
 pub struct AddressSpace {
-    /// The architecture-dependent page table for this address space.
-    page_table: PageTable,
+    /// The page directory for this address space.
+    directory: PageDirectory,
+    /// Virtual memory areas, keyed by their start address.
+    areas: BTreeMap<VirtualAddress, Vma>,
+    /// First address areas may be placed at.
+    window_start: VirtualAddress,
+    /// One past the last address areas may be placed at.
+    window_end: VirtualAddress,
 }
 
 impl AddressSpace {
-    /// Creates a new address space with a default page table.
+    /// Creates a new address space with an empty page directory.
+    ///
+    /// Areas may be placed anywhere in the lower half of the virtual address space except its
+    /// first and last pages. The first page is kept unmapped to catch null pointer
+    /// dereferences, and the last keeps every area's end address canonical.
     pub fn new() -> Self {
+        Self::with_window(
+            PageDirectory::new(),
+            VirtualAddress::new(arch::PAGE_SIZE),
+            VirtualAddress::new(address_space_size() / 2 - arch::PAGE_SIZE),
+        )
+    }
+
+    /// Creates an address space managing `[start, end)` of the given page directory.
+    ///
+    /// # Panics
+    /// Panics if the window is empty or not page-aligned.
+    pub fn with_window(
+        directory: PageDirectory,
+        start: VirtualAddress,
+        end: VirtualAddress,
+    ) -> Self {
+        assert!(
+            start.is_aligned(arch::PAGE_SIZE) && end.is_aligned(arch::PAGE_SIZE) && start < end,
+            "address space window must be a non-empty, page-aligned range"
+        );
         Self {
-            page_table: PageTable::default(),
+            directory,
+            areas: BTreeMap::new(),
+            window_start: start,
+            window_end: end,
+        }
+    }
+
+    /// Returns the page directory for this address space.
+    pub fn directory(&self) -> &PageDirectory {
+        &self.directory
+    }
+
+    /// Returns a mutable reference to the page directory for this address space.
+    pub fn directory_mut(&mut self) -> &mut PageDirectory {
+        &mut self.directory
+    }
+
+    /// Returns the area containing `addr`, if any.
+    pub fn find(&self, addr: VirtualAddress) -> Option<&Vma> {
+        self.areas
+            .range(..=addr)
+            .next_back()
+            .map(|(_, vma)| vma)
+            .filter(|vma| vma.contains(addr))
+    }
+
+    /// Returns an iterator over the areas of this address space, in address order.
+    pub fn areas(&self) -> impl Iterator<Item = &Vma> {
+        self.areas.values()
+    }
+
+    /// Finds the lowest free gap of `size` bytes whose start is aligned to `align`.
+    ///
+    /// `align` must be a power of two; it is raised to the page size if smaller.
+    pub fn find_free(&self, size: usize, align: usize) -> Option<VirtualAddress> {
+        if size == 0 {
+            return None;
+        }
+        let align = align.max(arch::PAGE_SIZE);
+
+        let fits = |cursor: VirtualAddress, limit: VirtualAddress| {
+            let candidate = cursor.align_up(align);
+            (candidate >= cursor && limit.as_usize().checked_sub(candidate.as_usize())? >= size)
+                .then_some(candidate)
+        };
+
+        let mut cursor = self.window_start;
+        for vma in self.areas.values() {
+            if let Some(candidate) = fits(cursor, vma.start) {
+                return Some(candidate);
+            }
+            cursor = cursor.max(vma.end);
+        }
+        fits(cursor, self.window_end)
+    }
+
+    /// Reserves `[start, start + size)` so that nothing else is placed there.
+    ///
+    /// Nothing is mapped into a reserved range; [`map`](Self::map) may later claim it.
+    pub fn reserve(
+        &mut self,
+        start: VirtualAddress,
+        size: usize,
+        name: &'static str,
+    ) -> Result<(), VmaError> {
+        let end = self.check_range(start, size)?;
+        if self.overlapping(start, end).next().is_some() {
+            return Err(VmaError::Overlap);
+        }
+
+        self.insert(Vma {
+            start,
+            end,
+            protection: Protection::default(),
+            backing: Backing::Reserved,
+            name,
+        });
+        Ok(())
+    }
+
+    /// Creates an area covering `[start, start + size)` and maps it.
+    ///
+    /// The range must be free or reserved. Anonymous areas are populated with zeroed frames
+    /// from `frames`, physical areas are mapped directly, and lazy and file areas are only
+    /// recorded.
+    pub fn map(
+        &mut self,
+        frames: &mut impl FrameAllocator,
+        start: VirtualAddress,
+        size: usize,
+        protection: Protection,
+        backing: Backing,
+        name: &'static str,
+    ) -> Result<TlbFlush, VmaError> {
+        let end = self.check_range(start, size)?;
+        if self
+            .overlapping(start, end)
+            .any(|vma| vma.backing != Backing::Reserved)
+        {
+            return Err(VmaError::Overlap);
+        }
+
+        let flags = protection.page_flags();
+        match backing {
+            Backing::Reserved | Backing::Lazy | Backing::File => {}
+            Backing::Physical(phys) => self.directory.map_range(start, phys, size, flags)?.ignore(),
+            Backing::Anonymous => self.populate_anonymous(frames, start, size, flags)?,
         }
+
+        self.remove_areas(start, end);
+        self.insert(Vma {
+            start,
+            end,
+            protection,
+            backing,
+            name,
+        });
+        Ok(TlbFlush::new(start, size))
+    }
+
+    /// Changes the protection of `[start, start + size)`.
+    ///
+    /// The range must be entirely covered by mapped (not merely reserved) areas, which are
+    /// split as needed. Only the permission bits of the pages change; each keeps its memory
+    /// type and other attributes. Pages of anonymous and lazy areas whose frames `frames`
+    /// reports as shared with another address space stay read-only in the page tables, so that
+    /// the first write to each of them still copies it through
+    /// [`handle_fault`](Self::handle_fault).
+    pub fn protect(
+        &mut self,
+        frames: &mut impl FrameAllocator,
+        start: VirtualAddress,
+        size: usize,
+        protection: Protection,
+    ) -> Result<TlbFlush, VmaError> {
+        let end = self.check_range(start, size)?;
+
+        let mut cursor = start;
+        for vma in self
+            .overlapping(start, end)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+        {
+            if vma.start > cursor || vma.backing == Backing::Reserved {
+                return Err(VmaError::NotMapped);
+            }
+            cursor = vma.end;
+        }
+        if cursor < end {
+            return Err(VmaError::NotMapped);
+        }
+
+        // Validate the range before splitting areas, so that a failure changes nothing.
+        self.directory.check_range(start, size)?;
+
+        self.split(start);
+        self.split(end);
+        let mut anonymous = Vec::new();
+        for (_, vma) in self.areas.range_mut(start..end) {
+            vma.protection = protection;
+            if matches!(vma.backing, Backing::Anonymous | Backing::Lazy) {
+                anonymous.push((vma.start, vma.end));
+            }
+        }
+
+        let mappings: Vec<Mapping> = self
+            .directory
+            .mappings()
+            .filter(|m| m.virt < end && m.virt + m.size > start)
+            .collect();
+        for mapping in mappings {
+            let from = mapping.virt.max(start);
+            let to = (mapping.virt + mapping.size).min(end);
+            self.directory
+                .protect_range(from, to - from, protection.apply_to(mapping.flags))?
+                .ignore();
+        }
+
+        if protection.write {
+            for (from, to) in anonymous {
+                for (page, frame) in self.populated_pages(from, to) {
+                    if frames.frame_references(frame) > 1 {
+                        let (_, mut flags, _) = self
+                            .directory
+                            .translate(page)
+                            .expect("populated pages are mapped");
+                        flags.set_writable(false);
+                        self.directory
+                            .protect_range(page, arch::PAGE_SIZE, flags)?
+                            .ignore();
+                    }
+                }
+            }
+        }
+        Ok(TlbFlush::new(start, size))
     }
 
-    /// Returns a reference to the page table for this address space.
-    pub fn page_table(&self) -> &PageTable {
-        &self.page_table
+    /// Removes every area in `[start, start + size)` and unmaps the range.
+    ///
+    /// Areas straddling the range boundaries are split. Frames backing anonymous and lazy areas
+    /// are released to `frames`; physical and file backings are left alone.
+    pub fn unmap(
+        &mut self,
+        frames: &mut impl FrameAllocator,
+        start: VirtualAddress,
+        size: usize,
+    ) -> Result<TlbFlush, VmaError> {
+        let end = self.check_range(start, size)?;
+
+        let anonymous: Vec<_> = self
+            .overlapping(start, end)
+            .filter(|vma| matches!(vma.backing, Backing::Anonymous | Backing::Lazy))
+            .map(|vma| (vma.start.max(start), vma.end.min(end)))
+            .collect();
+
+        let owned: Vec<_> = anonymous
+            .into_iter()
+            .flat_map(|(from, to)| (from.as_usize()..to.as_usize()).step_by(arch::PAGE_SIZE))
+            .filter_map(|page| self.directory.translate(VirtualAddress::new(page)))
+            .map(|(phys, _, _)| phys)
+            .collect();
+
+        let flush = self.directory.unmap_range(start, size)?;
+        for phys in owned {
+            frames.release_frame(phys);
+        }
+
+        self.remove_areas(start, end);
+        Ok(flush)
     }
 
-    /// Returns a mutable reference to the page table for this address space.
-    pub fn page_table_mut(&mut self) -> &mut PageTable {
-        &mut self.page_table
+    /// Resolves a page fault at `addr` caused by an access of kind `access`.
+    ///
+    /// A fault on an unpopulated page of a lazy area maps a freshly zeroed frame from `frames`
+    /// with the area's protection. A write to a read-only page of a writable anonymous or lazy
+    /// area copies the page into a new frame if its frame is shared, or just makes it writable
+    /// if it isn't. A fault on a page that is already mapped with sufficient permissions (for
+    /// example, after another CPU populated it) only needs a TLB flush. In all cases the
+    /// faulting access can be retried once the returned flush is applied.
+    ///
+    /// Fails with [`VmaError::NotMapped`] if no area can back the address, and with
+    /// [`VmaError::ProtectionViolation`] if the area doesn't permit the access.
+    pub fn handle_fault(
+        &mut self,
+        frames: &mut impl FrameAllocator,
+        addr: VirtualAddress,
+        access: Protection,
+    ) -> Result<TlbFlush, VmaError> {
+        let vma = *self.find(addr).ok_or(VmaError::NotMapped)?;
+        if vma.backing == Backing::Reserved {
+            return Err(VmaError::NotMapped);
+        }
+        if !vma.protection.permits(access) {
+            return Err(VmaError::ProtectionViolation);
+        }
+
+        let page = addr.align_down(arch::PAGE_SIZE);
+        let flags = vma.protection.page_flags();
+        match self.directory.translate(page) {
+            None if vma.backing == Backing::Lazy => self.populate_page(frames, page, flags)?,
+            None => return Err(VmaError::NotMapped),
+            Some((phys, current, _))
+                if access.write
+                    && !current.is_writable()
+                    && matches!(vma.backing, Backing::Anonymous | Backing::Lazy) =>
+            {
+                self.copy_on_write(frames, page, phys, flags)?
+            }
+            Some(_) => {}
+        }
+        Ok(TlbFlush::new(page, arch::PAGE_SIZE))
+    }
+
+    /// Creates a copy of this address space for a new process.
+    ///
+    /// The child gets a new page directory with the same areas. Physical areas map the same
+    /// memory with the same flags, memory type included, and lazy pages that were never
+    /// touched stay unpopulated in both. Populated anonymous pages are shared: each frame
+    /// gains a reference, and pages of writable areas become read-only in both address spaces,
+    /// so that the first write to either side copies the page (see
+    /// [`handle_fault`](Self::handle_fault)).
+    ///
+    /// Mappings outside of any area are not copied. Returns the child together with the TLB
+    /// flush that makes the parent's newly read-only pages visible. On failure, the references
+    /// taken are dropped again and the parent's pages get their flags back.
+    pub fn fork(
+        &mut self,
+        frames: &mut impl FrameAllocator,
+    ) -> Result<(AddressSpace, TlbFlush), VmaError> {
+        let mut child =
+            AddressSpace::with_window(PageDirectory::new(), self.window_start, self.window_end);
+        let mut shared = Vec::new();
+        let mut downgraded = Vec::new();
+
+        if let Err(err) = self.fork_into(&mut child, frames, &mut shared, &mut downgraded) {
+            // Nothing has been flushed, so the TLB still holds the original flags.
+            for mapping in downgraded {
+                self.directory
+                    .protect_range(mapping.virt, mapping.size, mapping.flags)
+                    .expect("the range was protected a moment ago")
+                    .ignore();
+            }
+            for frame in shared {
+                frames.release_frame(frame);
+            }
+            return Err(err);
+        }
+
+        let flush = match (downgraded.first(), downgraded.last()) {
+            (Some(first), Some(last)) => {
+                TlbFlush::new(first.virt, last.virt + last.size - first.virt)
+            }
+            _ => TlbFlush::new(self.window_start, 0),
+        };
+        Ok((child, flush))
+    }
+
+    /// Releases every frame mapped by the anonymous and lazy areas of this address space, then
+    /// drops it, freeing its page tables.
+    ///
+    /// Frames shared with a forked address space only lose this address space's reference.
+    /// Physical and file backings are left alone.
+    pub fn destroy(self, frames: &mut impl FrameAllocator) {
+        let anonymous = self
+            .areas
+            .values()
+            .filter(|vma| matches!(vma.backing, Backing::Anonymous | Backing::Lazy));
+        for vma in anonymous {
+            for (_, frame) in self.populated_pages(vma.start, vma.end) {
+                frames.release_frame(frame);
+            }
+        }
+    }
+
+    /// Copies the areas of this address space into `child`, for [`fork`](Self::fork).
+    ///
+    /// Every frame that gains a reference is pushed to `shared`, and every parent mapping
+    /// made read-only is pushed to `downgraded` with its original flags, so that the caller
+    /// can undo both if this fails.
+    fn fork_into(
+        &mut self,
+        child: &mut AddressSpace,
+        frames: &mut impl FrameAllocator,
+        shared: &mut Vec<PhysicalAddress>,
+        downgraded: &mut Vec<Mapping>,
+    ) -> Result<(), VmaError> {
+        let mappings: Vec<_> = self.directory.mappings().collect();
+
+        for vma in self.areas.values() {
+            child.insert(*vma);
+            if matches!(vma.backing, Backing::Reserved | Backing::File) {
+                continue;
+            }
+
+            let populated = mappings
+                .iter()
+                .filter(|m| m.virt < vma.end && m.virt + m.size > vma.start);
+            for mapping in populated {
+                let from = mapping.virt.max(vma.start);
+                let to = (mapping.virt + mapping.size).min(vma.end);
+                let phys = mapping.phys + (from - mapping.virt);
+
+                if let Backing::Physical(_) = vma.backing {
+                    child
+                        .directory
+                        .map_range(from, phys, to - from, mapping.flags)?
+                        .ignore();
+                    continue;
+                }
+
+                let mut flags = mapping.flags;
+                flags.set_writable(false);
+                for offset in (0..to - from).step_by(arch::PAGE_SIZE) {
+                    frames.share_frame(phys + offset);
+                    shared.push(phys + offset);
+                    child
+                        .directory
+                        .map_range(from + offset, phys + offset, arch::PAGE_SIZE, flags)?
+                        .ignore();
+                }
+
+                if mapping.flags.is_writable() {
+                    self.directory
+                        .protect_range(from, to - from, flags)?
+                        .ignore();
+                    downgraded.push(Mapping {
+                        virt: from,
+                        phys,
+                        size: to - from,
+                        flags: mapping.flags,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates a range against the window, returning its end.
+    fn check_range(&self, start: VirtualAddress, size: usize) -> Result<VirtualAddress, VmaError> {
+        if size == 0 || !start.is_aligned(arch::PAGE_SIZE) || !size.is_multiple_of(arch::PAGE_SIZE)
+        {
+            return Err(VmaError::InvalidRange);
+        }
+
+        let end = start
+            .as_usize()
+            .checked_add(size)
+            .ok_or(VmaError::InvalidRange)?;
+        if start < self.window_start || end > self.window_end.as_usize() {
+            return Err(VmaError::InvalidRange);
+        }
+        Ok(VirtualAddress::new(end))
+    }
+
+    /// Returns the areas overlapping `[start, end)`, from the highest address down.
+    fn overlapping(
+        &self,
+        start: VirtualAddress,
+        end: VirtualAddress,
+    ) -> impl Iterator<Item = &Vma> {
+        // Areas don't overlap, so their ends are sorted like their starts.
+        self.areas
+            .range(..end)
+            .rev()
+            .map(|(_, vma)| vma)
+            .take_while(move |vma| vma.end > start)
+    }
+
+    /// Returns every populated page in `[start, end)`, together with the frame it maps.
+    fn populated_pages(
+        &self,
+        start: VirtualAddress,
+        end: VirtualAddress,
+    ) -> Vec<(VirtualAddress, PhysicalAddress)> {
+        self.directory
+            .mappings()
+            .filter(|m| m.virt < end && m.virt + m.size > start)
+            .flat_map(|m| {
+                let from = m.virt.max(start).as_usize();
+                let to = (m.virt + m.size).min(end).as_usize();
+                (from..to).step_by(arch::PAGE_SIZE).map(move |virt| {
+                    (
+                        VirtualAddress::new(virt),
+                        m.phys + (virt - m.virt.as_usize()),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Adds an area, which must not overlap any existing one.
+    fn insert(&mut self, vma: Vma) {
+        debug_assert!(self.overlapping(vma.start, vma.end).next().is_none());
+        self.areas.insert(vma.start, vma);
+    }
+
+    /// Splits the area containing `addr` in two, if `addr` lies strictly inside it.
+    fn split(&mut self, addr: VirtualAddress) {
+        let Some(&vma) = self.find(addr) else {
+            return;
+        };
+        if vma.start == addr {
+            return;
+        }
+
+        let (lower, upper) = vma.split_at(addr);
+        self.areas.insert(lower.start, lower);
+        self.areas.insert(upper.start, upper);
+    }
+
+    /// Removes all areas within `[start, end)`, splitting those that straddle its boundaries.
+    fn remove_areas(&mut self, start: VirtualAddress, end: VirtualAddress) {
+        self.split(start);
+        self.split(end);
+        let keys: Vec<_> = self.areas.range(start..end).map(|(&key, _)| key).collect();
+        for key in keys {
+            self.areas.remove(&key);
+        }
+    }
+
+    /// Maps `[start, start + size)` to freshly allocated, zeroed frames.
+    ///
+    /// On failure, everything mapped so far is unmapped and released again.
+    fn populate_anonymous(
+        &mut self,
+        frames: &mut impl FrameAllocator,
+        start: VirtualAddress,
+        size: usize,
+        flags: PageFlags,
+    ) -> Result<(), VmaError> {
+        for offset in (0..size).step_by(arch::PAGE_SIZE) {
+            if let Err(err) = self.populate_page(frames, start + offset, flags) {
+                for populated in (0..offset).step_by(arch::PAGE_SIZE) {
+                    if let Some(frame) = self.directory.unmap(start + populated) {
+                        frames.release_frame(frame);
+                    }
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves a write to the read-only page at `page`, which is mapped to `frame`.
+    ///
+    /// If the frame is still shared with another address space, its contents are copied into
+    /// a new frame that replaces it here. Otherwise the page is simply made writable.
+    fn copy_on_write(
+        &mut self,
+        frames: &mut impl FrameAllocator,
+        page: VirtualAddress,
+        frame: PhysicalAddress,
+        flags: PageFlags,
+    ) -> Result<(), VmaError> {
+        if frames.frame_references(frame) <= 1 {
+            self.directory
+                .protect_range(page, arch::PAGE_SIZE, flags)?
+                .ignore();
+            return Ok(());
+        }
+
+        let copy = frames.allocate_frame().ok_or(VmaError::OutOfMemory)?;
+        // SAFETY: `copy` was just allocated, so nothing else references it, and `frame` is
+        // mapped read-only everywhere it is shared, so it can't change while being copied.
+        unsafe {
+            ptr::copy_nonoverlapping(
+                VirtualAddress::direct_mapped(frame).as_ptr::<u8>(),
+                VirtualAddress::direct_mapped(copy).as_mut_ptr::<u8>(),
+                arch::PAGE_SIZE,
+            );
+        }
+
+        self.directory.unmap(page);
+        self.directory
+            .map_range(page, copy, arch::PAGE_SIZE, flags)?
+            .ignore();
+        frames.release_frame(frame);
+        Ok(())
+    }
+
+    /// Maps the page at `page` to a freshly allocated, zeroed frame.
+    fn populate_page(
+        &mut self,
+        frames: &mut impl FrameAllocator,
+        page: VirtualAddress,
+        flags: PageFlags,
+    ) -> Result<(), VmaError> {
+        let frame = frames.allocate_frame().ok_or(VmaError::OutOfMemory)?;
+        // SAFETY: The frame was just allocated, so nothing else references it.
+        unsafe {
+            ptr::write_bytes(
+                VirtualAddress::direct_mapped(frame).as_mut_ptr::<u8>(),
+                0,
+                arch::PAGE_SIZE,
+            );
+        }
+
+        match self
+            .directory
+            .map_range(page, frame, arch::PAGE_SIZE, flags)
+        {
+            Ok(flush) => {
+                flush.ignore();
+                Ok(())
+            }
+            Err(err) => {
+                frames.release_frame(frame);
+                Err(err.into())
+            }
+        }
+    }
+}
+
+impl Default for AddressSpace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AddressTranslator, BootMemoryRegion, MemoryMap, PhysicalMemoryManager};
+
+    /// Test implementation of BootMemoryRegion.
+    struct TestRegion {
+        base: PhysicalAddress,
+        size: usize,
+    }
+
+    impl BootMemoryRegion for TestRegion {
+        fn base(&self) -> PhysicalAddress {
+            self.base
+        }
+
+        fn size(&self) -> usize {
+            self.size
+        }
+
+        fn is_usable(&self) -> bool {
+            true
+        }
+    }
+
+    const MEMORY_SIZE: usize = 64 * 1024;
+
+    /// Creates a PMM managing `num_frames` frames of emulated memory.
+    ///
+    /// The frames are carved out of the emulated memory's own allocator so that they don't
+    /// collide with page tables, which are allocated from the same memory.
+    fn setup_pmm(num_frames: usize) -> PhysicalMemoryManager {
+        if AddressTranslator::try_current().is_none() {
+            AddressTranslator::set_current(AddressTranslator::emulated(MEMORY_SIZE));
+        }
+
+        let boot_map = [TestRegion {
+            base: PhysicalAddress::new(0),
+            size: MEMORY_SIZE,
+        }];
+        let mut pmm = PhysicalMemoryManager::new(MemoryMap::from_boot_map(&boot_map));
+
+        let size = num_frames * arch::PAGE_SIZE;
+        let base = AddressTranslator::current()
+            .allocate(size, size.next_power_of_two())
+            .expect("emulated memory exhausted");
+        pmm.add_region(PhysicalAddress::new(base), size);
+        pmm
+    }
+
+    fn pages(n: usize) -> usize {
+        n * arch::PAGE_SIZE
+    }
+
+    fn addr(addr: usize) -> VirtualAddress {
+        VirtualAddress::new(addr)
+    }
+
+    #[test]
+    fn map_anonymous_populates_zeroed_frames() {
+        let mut pmm = setup_pmm(16);
+        let mut space = AddressSpace::new();
+        let free = pmm.free_frames();
+
+        space
+            .map(
+                &mut pmm,
+                addr(0x1000),
+                pages(4),
+                Protection::READ_WRITE,
+                Backing::Anonymous,
+                "heap",
+            )
+            .unwrap()
+            .ignore();
+
+        assert_eq!(pmm.free_frames(), free - 4);
+        for page in 0..4 {
+            let (phys, flags, _) = space
+                .directory()
+                .translate(addr(0x1000 + pages(page)))
+                .unwrap();
+            assert!(flags.is_writable());
+            assert!(flags.is_no_execute());
+            let bytes = VirtualAddress::direct_mapped(phys).as_ptr::<[u8; arch::PAGE_SIZE]>();
+            // SAFETY: The frame is mapped and was zeroed when it was populated.
+            assert_eq!(unsafe { *bytes }, [0; arch::PAGE_SIZE]);
+        }
+
+        let vma = space.find(addr(0x1010)).unwrap();
+        assert_eq!(
+            (vma.start(), vma.end()),
+            (addr(0x1000), addr(0x1000 + pages(4)))
+        );
+        assert_eq!(vma.name(), "heap");
+    }
+
+    #[test]
+    fn map_anonymous_rolls_back_when_out_of_memory() {
+        let mut pmm = setup_pmm(4);
+        let mut space = AddressSpace::new();
+
+        let result = space.map(
+            &mut pmm,
+            addr(0x1000),
+            pages(8),
+            Protection::READ_WRITE,
+            Backing::Anonymous,
+            "heap",
+        );
+
+        assert_eq!(result.unwrap_err(), VmaError::OutOfMemory);
+        assert_eq!(pmm.free_frames(), 4);
+        assert_eq!(space.directory().mappings().count(), 0);
+        assert_eq!(space.areas().count(), 0);
+    }
+
+    #[test]
+    fn map_physical_maps_fixed_range() {
+        let mut pmm = setup_pmm(1);
+        let mut space = AddressSpace::new();
+
+        space
+            .map(
+                &mut pmm,
+                addr(0x2000),
+                pages(2),
+                Protection::READ_WRITE,
+                Backing::Physical(PhysicalAddress::new(0x8000)),
+                "mmio",
+            )
+            .unwrap()
+            .ignore();
+
+        let (phys, _, _) = space.directory().translate(addr(0x2010)).unwrap();
+        assert_eq!(phys, PhysicalAddress::new(0x8010));
+    }
+
+    #[test]
+    fn map_file_only_records_area() {
+        let mut pmm = setup_pmm(1);
+        let mut space = AddressSpace::new();
+
+        space
+            .map(
+                &mut pmm,
+                addr(0x2000),
+                pages(2),
+                Protection::READ,
+                Backing::File,
+                "file",
+            )
+            .unwrap()
+            .ignore();
+
+        assert_eq!(space.find(addr(0x2000)).unwrap().backing(), Backing::File);
+        assert!(space.directory().translate(addr(0x2000)).is_none());
+    }
+
+    #[test]
+    fn map_rejects_overlap_and_invalid_ranges() {
+        let mut pmm = setup_pmm(8);
+        let mut space = AddressSpace::new();
+        space
+            .map(
+                &mut pmm,
+                addr(0x1000),
+                pages(4),
+                Protection::READ_WRITE,
+                Backing::Anonymous,
+                "heap",
+            )
+            .unwrap()
+            .ignore();
+
+        let mut map = |start, size| {
+            space.map(
+                &mut pmm,
+                addr(start),
+                size,
+                Protection::READ,
+                Backing::File,
+                "file",
+            )
+        };
+        assert_eq!(
+            map(0x1000 - pages(1), pages(2)).unwrap_err(),
+            VmaError::Overlap
+        );
+        assert_eq!(
+            map(0x1000 + pages(3), pages(1)).unwrap_err(),
+            VmaError::Overlap
+        );
+        assert_eq!(map(0x3000, 0).unwrap_err(), VmaError::InvalidRange);
+        assert_eq!(map(0x3001, pages(1)).unwrap_err(), VmaError::InvalidRange);
+        assert_eq!(map(0, pages(1)).unwrap_err(), VmaError::InvalidRange);
+        assert_eq!(
+            map(address_space_size() / 2 - pages(2), pages(2)).unwrap_err(),
+            VmaError::InvalidRange
+        );
+    }
+
+    #[test]
+    fn map_claims_reserved_range() {
+        let mut pmm = setup_pmm(4);
+        let mut space = AddressSpace::new();
+        space.reserve(addr(0x1000), pages(8), "stack").unwrap();
+
+        assert_eq!(
+            space.reserve(addr(0x1000 + pages(4)), pages(8), "other"),
+            Err(VmaError::Overlap)
+        );
+
+        space
+            .map(
+                &mut pmm,
+                addr(0x1000 + pages(6)),
+                pages(2),
+                Protection::READ_WRITE,
+                Backing::Anonymous,
+                "stack",
+            )
+            .unwrap()
+            .ignore();
+
+        let areas: Vec<_> = space
+            .areas()
+            .map(|vma| (vma.start().as_usize(), vma.size(), vma.backing()))
+            .collect();
+        assert_eq!(
+            areas,
+            [
+                (0x1000, pages(6), Backing::Reserved),
+                (0x1000 + pages(6), pages(2), Backing::Anonymous),
+            ]
+        );
+        assert!(space.directory().translate(addr(0x1000)).is_none());
+    }
+
+    #[test]
+    fn protect_splits_areas() {
+        let mut pmm = setup_pmm(8);
+        let mut space = AddressSpace::new();
+        space
+            .map(
+                &mut pmm,
+                addr(0x1000),
+                pages(4),
+                Protection::READ_WRITE,
+                Backing::Anonymous,
+                "heap",
+            )
+            .unwrap()
+            .ignore();
+
+        space
+            .protect(
+                &mut pmm,
+                addr(0x1000 + pages(1)),
+                pages(2),
+                Protection::READ,
+            )
+            .unwrap()
+            .ignore();
+
+        let areas: Vec<_> = space
+            .areas()
+            .map(|vma| (vma.start().as_usize(), vma.size(), vma.protection()))
+            .collect();
+        assert_eq!(
+            areas,
+            [
+                (0x1000, pages(1), Protection::READ_WRITE),
+                (0x1000 + pages(1), pages(2), Protection::READ),
+                (0x1000 + pages(3), pages(1), Protection::READ_WRITE),
+            ]
+        );
+        let (_, flags, _) = space
+            .directory()
+            .translate(addr(0x1000 + pages(1)))
+            .unwrap();
+        assert!(!flags.is_writable());
+        let (_, flags, _) = space
+            .directory()
+            .translate(addr(0x1000 + pages(3)))
+            .unwrap();
+        assert!(flags.is_writable());
+    }
+
+    #[test]
+    fn protect_requires_mapped_coverage() {
+        let mut pmm = setup_pmm(8);
+        let mut space = AddressSpace::new();
+        space.reserve(addr(0x2000), pages(2), "guard").unwrap();
+        space
+            .map(
+                &mut pmm,
+                addr(0x1000),
+                pages(2),
+                Protection::READ_WRITE,
+                Backing::Anonymous,
+                "heap",
+            )
+            .unwrap()
+            .ignore();
+
+        assert_eq!(
+            space
+                .protect(&mut pmm, addr(0x1000), pages(3), Protection::READ)
+                .unwrap_err(),
+            VmaError::NotMapped
+        );
+        assert_eq!(
+            space
+                .protect(&mut pmm, addr(0x2000), pages(1), Protection::READ)
+                .unwrap_err(),
+            VmaError::NotMapped
+        );
+        assert_eq!(
+            space.find(addr(0x1000)).unwrap().protection(),
+            Protection::READ_WRITE
+        );
+    }
+
+    #[test]
+    fn unmap_releases_anonymous_frames_and_splits() {
+        let mut pmm = setup_pmm(8);
+        let mut space = AddressSpace::new();
+        let free = pmm.free_frames();
+        space
+            .map(
+                &mut pmm,
+                addr(0x1000),
+                pages(4),
+                Protection::READ_WRITE,
+                Backing::Anonymous,
+                "heap",
+            )
+            .unwrap()
+            .ignore();
+
+        space
+            .unmap(&mut pmm, addr(0x1000 + pages(1)), pages(2))
+            .unwrap()
+            .ignore();
+
+        assert_eq!(pmm.free_frames(), free - 2);
+        let areas: Vec<_> = space
+            .areas()
+            .map(|vma| (vma.start().as_usize(), vma.size()))
+            .collect();
+        assert_eq!(areas, [(0x1000, pages(1)), (0x1000 + pages(3), pages(1))]);
+        assert!(
+            space
+                .directory()
+                .translate(addr(0x1000 + pages(1)))
+                .is_none()
+        );
+        assert!(
+            space
+                .directory()
+                .translate(addr(0x1000 + pages(3)))
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn unmap_splits_physical_backing() {
+        let mut pmm = setup_pmm(1);
+        let mut space = AddressSpace::new();
+        space
+            .map(
+                &mut pmm,
+                addr(0x2000),
+                pages(4),
+                Protection::READ_WRITE,
+                Backing::Physical(PhysicalAddress::new(0x8000)),
+                "mmio",
+            )
+            .unwrap()
+            .ignore();
+
+        space
+            .unmap(&mut pmm, addr(0x2000), pages(1))
+            .unwrap()
+            .ignore();
+
+        let vma = space.find(addr(0x2000 + pages(1))).unwrap();
+        assert_eq!(vma.start(), addr(0x2000 + pages(1)));
+        assert_eq!(
+            vma.backing(),
+            Backing::Physical(PhysicalAddress::new(0x8000 + pages(1)))
+        );
+    }
+
+    #[test]
+    fn lazy_area_is_populated_on_fault() {
+        let mut pmm = setup_pmm(8);
+        let mut space = AddressSpace::new();
+        let free = pmm.free_frames();
+        space
+            .map(
+                &mut pmm,
+                addr(0x1000),
+                pages(4),
+                Protection::READ_WRITE,
+                Backing::Lazy,
+                "lazy",
+            )
+            .unwrap()
+            .ignore();
+
+        assert_eq!(pmm.free_frames(), free);
+        assert!(space.directory().translate(addr(0x1000)).is_none());
+
+        let write = Protection::READ_WRITE;
+        space
+            .handle_fault(&mut pmm, addr(0x1000 + pages(2) + 3), write)
+            .unwrap()
+            .ignore();
+
+        assert_eq!(pmm.free_frames(), free - 1);
+        let (phys, flags, _) = space
+            .directory()
+            .translate(addr(0x1000 + pages(2)))
+            .unwrap();
+        assert!(flags.is_writable());
+        let bytes = VirtualAddress::direct_mapped(phys).as_ptr::<[u8; arch::PAGE_SIZE]>();
+        // SAFETY: The frame is mapped and was zeroed when it was populated.
+        assert_eq!(unsafe { *bytes }, [0; arch::PAGE_SIZE]);
+        assert!(
+            space
+                .directory()
+                .translate(addr(0x1000 + pages(1)))
+                .is_none()
+        );
+
+        // A repeated fault on a populated page only flushes the TLB.
+        space
+            .handle_fault(&mut pmm, addr(0x1000 + pages(2)), write)
+            .unwrap()
+            .ignore();
+        assert_eq!(pmm.free_frames(), free - 1);
+
+        space
+            .unmap(&mut pmm, addr(0x1000), pages(4))
+            .unwrap()
+            .ignore();
+        assert_eq!(pmm.free_frames(), free);
+    }
+
+    #[test]
+    fn fault_outside_lazy_area_is_rejected() {
+        let mut pmm = setup_pmm(8);
+        let mut space = AddressSpace::new();
+        space.reserve(addr(0x2000), pages(1), "guard").unwrap();
+        space
+            .map(
+                &mut pmm,
+                addr(0x1000),
+                pages(1),
+                Protection::READ,
+                Backing::Lazy,
+                "lazy",
+            )
+            .unwrap()
+            .ignore();
+        space
+            .map(
+                &mut pmm,
+                addr(0x3000),
+                pages(1),
+                Protection::READ,
+                Backing::File,
+                "file",
+            )
+            .unwrap()
+            .ignore();
+        let free = pmm.free_frames();
+
+        let mut fault = |at, access| space.handle_fault(&mut pmm, addr(at), access).unwrap_err();
+        assert_eq!(
+            fault(0x1000, Protection::READ_WRITE),
+            VmaError::ProtectionViolation
+        );
+        assert_eq!(
+            fault(0x1000, Protection::READ.with_user()),
+            VmaError::ProtectionViolation
+        );
+        assert_eq!(
+            fault(0x1000, Protection::READ_EXECUTE),
+            VmaError::ProtectionViolation
+        );
+        assert_eq!(
+            fault(0x1000 + pages(1), Protection::READ),
+            VmaError::NotMapped
+        );
+        assert_eq!(fault(0x2000, Protection::READ), VmaError::NotMapped);
+        assert_eq!(fault(0x3000, Protection::READ), VmaError::NotMapped);
+        assert_eq!(pmm.free_frames(), free);
+    }
+
+    #[test]
+    fn fault_on_lazy_area_reports_out_of_memory() {
+        let mut pmm = setup_pmm(1);
+        let mut space = AddressSpace::new();
+        space
+            .map(
+                &mut pmm,
+                addr(0x1000),
+                pages(2),
+                Protection::READ,
+                Backing::Lazy,
+                "lazy",
+            )
+            .unwrap()
+            .ignore();
+
+        space
+            .handle_fault(&mut pmm, addr(0x1000), Protection::READ)
+            .unwrap()
+            .ignore();
+        assert_eq!(
+            space
+                .handle_fault(&mut pmm, addr(0x1000 + pages(1)), Protection::READ)
+                .unwrap_err(),
+            VmaError::OutOfMemory
+        );
+        assert!(
+            space
+                .directory()
+                .translate(addr(0x1000 + pages(1)))
+                .is_none()
+        );
+    }
+
+    /// Writes `value` to the first byte of the page mapped at `virt`.
+    fn poke(space: &AddressSpace, virt: VirtualAddress, value: u8) {
+        let (phys, _, _) = space.directory().translate(virt).unwrap();
+        // SAFETY: The page is mapped, so its frame belongs to the test.
+        unsafe { *VirtualAddress::direct_mapped(phys).as_mut_ptr::<u8>() = value };
+    }
+
+    /// Reads the first byte of the page mapped at `virt`.
+    fn peek(space: &AddressSpace, virt: VirtualAddress) -> u8 {
+        let (phys, _, _) = space.directory().translate(virt).unwrap();
+        // SAFETY: The page is mapped, so its frame belongs to the test.
+        unsafe { *VirtualAddress::direct_mapped(phys).as_ptr::<u8>() }
+    }
+
+    #[test]
+    fn fork_shares_anonymous_pages_read_only() {
+        let mut pmm = setup_pmm(8);
+        let mut parent = AddressSpace::new();
+        parent
+            .map(
+                &mut pmm,
+                addr(0x1000),
+                pages(2),
+                Protection::READ_WRITE,
+                Backing::Anonymous,
+                "heap",
+            )
+            .unwrap()
+            .ignore();
+        parent
+            .map(
+                &mut pmm,
+                addr(0x2000),
+                pages(2),
+                Protection::READ_WRITE,
+                Backing::Lazy,
+                "lazy",
+            )
+            .unwrap()
+            .ignore();
+        parent
+            .map(
+                &mut pmm,
+                addr(0x3000),
+                pages(1),
+                Protection::READ_WRITE,
+                Backing::Physical(PhysicalAddress::new(0x8000)),
+                "mmio",
+            )
+            .unwrap()
+            .ignore();
+        parent
+            .handle_fault(&mut pmm, addr(0x2000), Protection::READ)
+            .unwrap()
+            .ignore();
+        poke(&parent, addr(0x1000), 42);
+        let free = pmm.free_frames();
+
+        let (child, flush) = parent.fork(&mut pmm).unwrap();
+        flush.ignore();
+
+        assert_eq!(pmm.free_frames(), free);
+        assert!(parent.areas().eq(child.areas()));
+        for page in [0x1000, 0x1000 + pages(1), 0x2000] {
+            let (phys, flags, _) = parent.directory().translate(addr(page)).unwrap();
+            let (child_phys, child_flags, _) = child.directory().translate(addr(page)).unwrap();
+            assert_eq!(phys, child_phys);
+            assert!(!flags.is_writable());
+            assert!(!child_flags.is_writable());
+            assert_eq!(pmm.frame_references(phys), 2);
+        }
+        assert!(
+            child
+                .directory()
+                .translate(addr(0x2000 + pages(1)))
+                .is_none()
+        );
+        assert_eq!(peek(&child, addr(0x1000)), 42);
+
+        let (phys, flags, _) = child.directory().translate(addr(0x3000)).unwrap();
+        assert_eq!(phys, PhysicalAddress::new(0x8000));
+        assert!(flags.is_writable());
+    }
+
+    #[test]
+    fn write_after_fork_copies_the_page() {
+        let mut pmm = setup_pmm(8);
+        let mut parent = AddressSpace::new();
+        parent
+            .map(
+                &mut pmm,
+                addr(0x1000),
+                pages(1),
+                Protection::READ_WRITE,
+                Backing::Anonymous,
+                "heap",
+            )
+            .unwrap()
+            .ignore();
+        poke(&parent, addr(0x1000), 42);
+        let (shared, _, _) = parent.directory().translate(addr(0x1000)).unwrap();
+
+        let (mut child, flush) = parent.fork(&mut pmm).unwrap();
+        flush.ignore();
+        let free = pmm.free_frames();
+
+        // The first write copies the page for the writer...
+        child
+            .handle_fault(&mut pmm, addr(0x1000), Protection::READ_WRITE)
+            .unwrap()
+            .ignore();
+        let (copy, flags, _) = child.directory().translate(addr(0x1000)).unwrap();
+        assert_ne!(copy, shared);
+        assert!(flags.is_writable());
+        assert_eq!(peek(&child, addr(0x1000)), 42);
+        assert_eq!(pmm.free_frames(), free - 1);
+        assert_eq!(pmm.frame_references(shared), 1);
+
+        poke(&child, addr(0x1000), 7);
+        assert_eq!(peek(&parent, addr(0x1000)), 42);
+
+        // ...and the last sharer takes the original back without copying.
+        parent
+            .handle_fault(&mut pmm, addr(0x1000), Protection::READ_WRITE)
+            .unwrap()
+            .ignore();
+        let (phys, flags, _) = parent.directory().translate(addr(0x1000)).unwrap();
+        assert_eq!(phys, shared);
+        assert!(flags.is_writable());
+        assert_eq!(pmm.free_frames(), free - 1);
+    }
+
+    #[test]
+    fn fork_keeps_the_flags_of_physical_mappings() {
+        let mut pmm = setup_pmm(8);
+        let mut parent = AddressSpace::new();
+        parent
+            .map(
+                &mut pmm,
+                addr(0x3000),
+                pages(2),
+                Protection::READ_WRITE,
+                Backing::Physical(PhysicalAddress::new(0x8000)),
+                "mmio",
+            )
+            .unwrap()
+            .ignore();
+        let mut uncached = Protection::READ_WRITE.page_flags();
+        uncached.set_cache_disable(true);
+        parent
+            .directory_mut()
+            .protect_range(addr(0x3000), pages(2), uncached)
+            .unwrap()
+            .ignore();
+
+        let (child, flush) = parent.fork(&mut pmm).unwrap();
+        flush.ignore();
+
+        for page in [0x3000, 0x3000 + pages(1)] {
+            let mapped = parent.directory().translate(addr(page));
+            assert_eq!(child.directory().translate(addr(page)), mapped);
+            assert!(mapped.unwrap().1.is_cache_disable());
+        }
+    }
+
+    #[test]
+    fn failed_fork_leaves_the_parent_unchanged() {
+        let mut pmm = setup_pmm(8);
+        let mut parent = AddressSpace::new();
+        let large = arch::level_size(1);
+        for (start, size, backing, name) in [
+            (0x1000, pages(1), Backing::Anonymous, "heap"),
+            (0x1000 + large, large / 2, Backing::Lazy, "lower"),
+            (
+                0x1000 + large + large / 2,
+                large / 2,
+                Backing::Lazy,
+                "upper",
+            ),
+        ] {
+            parent
+                .map(
+                    &mut pmm,
+                    addr(start),
+                    size,
+                    Protection::READ_WRITE,
+                    backing,
+                    name,
+                )
+                .unwrap()
+                .ignore();
+        }
+        // A large page straddling both lazy areas can't be made read-only for one of them.
+        let phys = AddressTranslator::current().allocate(large, large).unwrap();
+        parent
+            .directory_mut()
+            .map_range(
+                addr(0x1000 + large),
+                PhysicalAddress::new(phys),
+                large,
+                Protection::READ_WRITE.page_flags(),
+            )
+            .unwrap()
+            .ignore();
+        let (heap, _, _) = parent.directory().translate(addr(0x1000)).unwrap();
+        let free = pmm.free_frames();
+
+        assert!(matches!(
+            parent.fork(&mut pmm),
+            Err(VmaError::Map(MapError::PartialHugePage(_)))
+        ));
+
+        let (phys, flags, _) = parent.directory().translate(addr(0x1000)).unwrap();
+        assert_eq!(phys, heap);
+        assert!(flags.is_writable());
+        assert_eq!(pmm.frame_references(heap), 1);
+        assert_eq!(pmm.free_frames(), free);
+    }
+
+    #[test]
+    fn destroying_forked_spaces_releases_every_frame() {
+        let mut pmm = setup_pmm(8);
+        let free = pmm.free_frames();
+        let mut parent = AddressSpace::new();
+        parent
+            .map(
+                &mut pmm,
+                addr(0x1000),
+                pages(2),
+                Protection::READ_WRITE,
+                Backing::Anonymous,
+                "heap",
+            )
+            .unwrap()
+            .ignore();
+        parent
+            .map(
+                &mut pmm,
+                addr(0x2000),
+                pages(2),
+                Protection::READ_WRITE,
+                Backing::Lazy,
+                "lazy",
+            )
+            .unwrap()
+            .ignore();
+        parent
+            .handle_fault(&mut pmm, addr(0x2000), Protection::READ)
+            .unwrap()
+            .ignore();
+
+        let (mut child, flush) = parent.fork(&mut pmm).unwrap();
+        flush.ignore();
+        child
+            .handle_fault(&mut pmm, addr(0x1000), Protection::READ_WRITE)
+            .unwrap()
+            .ignore();
+        assert_eq!(pmm.free_frames(), free - 4);
+
+        parent.destroy(&mut pmm);
+        assert_eq!(pmm.free_frames(), free - 3);
+        child.destroy(&mut pmm);
+        assert_eq!(pmm.free_frames(), free);
+    }
+
+    #[test]
+    fn fork_leaves_read_only_areas_read_only() {
+        let mut pmm = setup_pmm(8);
+        let mut parent = AddressSpace::new();
+        parent
+            .map(
+                &mut pmm,
+                addr(0x1000),
+                pages(1),
+                Protection::READ,
+                Backing::Anonymous,
+                "rodata",
+            )
+            .unwrap()
+            .ignore();
+
+        let (mut child, flush) = parent.fork(&mut pmm).unwrap();
+        flush.ignore();
+
+        assert_eq!(
+            child
+                .handle_fault(&mut pmm, addr(0x1000), Protection::READ_WRITE)
+                .unwrap_err(),
+            VmaError::ProtectionViolation
+        );
+        let (phys, _, _) = parent.directory().translate(addr(0x1000)).unwrap();
+        assert_eq!(pmm.frame_references(phys), 2);
+    }
+
+    #[test]
+    fn protect_keeps_shared_pages_copy_on_write() {
+        let mut pmm = setup_pmm(8);
+        let mut parent = AddressSpace::new();
+        parent
+            .map(
+                &mut pmm,
+                addr(0x1000),
+                pages(1),
+                Protection::READ_WRITE,
+                Backing::Anonymous,
+                "heap",
+            )
+            .unwrap()
+            .ignore();
+        let (_child, flush) = parent.fork(&mut pmm).unwrap();
+        flush.ignore();
+
+        parent
+            .protect(&mut pmm, addr(0x1000), pages(1), Protection::READ_WRITE)
+            .unwrap()
+            .ignore();
+
+        let (_, flags, _) = parent.directory().translate(addr(0x1000)).unwrap();
+        assert!(!flags.is_writable());
+    }
+
+    #[test]
+    fn protect_keeps_the_memory_type_of_physical_mappings() {
+        let mut pmm = setup_pmm(8);
+        let mut space = AddressSpace::new();
+        space
+            .map(
+                &mut pmm,
+                addr(0x3000),
+                pages(2),
+                Protection::READ_WRITE,
+                Backing::Physical(PhysicalAddress::new(0x8000)),
+                "mmio",
+            )
+            .unwrap()
+            .ignore();
+        let mut uncached = Protection::READ_WRITE.page_flags();
+        uncached.set_cache_disable(true);
+        uncached.set_write_through(true);
+        space
+            .directory_mut()
+            .protect_range(addr(0x3000), pages(2), uncached)
+            .unwrap()
+            .ignore();
+
+        space
+            .protect(&mut pmm, addr(0x3000), pages(2), Protection::READ)
+            .unwrap()
+            .ignore();
+
+        for page in [0x3000, 0x3000 + pages(1)] {
+            let (_, flags, _) = space.directory().translate(addr(page)).unwrap();
+            assert!(!flags.is_writable());
+            assert!(flags.is_cache_disable());
+            assert!(flags.is_write_through());
+        }
+    }
+
+    #[test]
+    fn protect_makes_unshared_pages_writable() {
+        let mut pmm = setup_pmm(8);
+        let mut space = AddressSpace::new();
+        space
+            .map(
+                &mut pmm,
+                addr(0x1000),
+                pages(2),
+                Protection::READ,
+                Backing::Anonymous,
+                "heap",
+            )
+            .unwrap()
+            .ignore();
+
+        space
+            .protect(&mut pmm, addr(0x1000), pages(2), Protection::READ_WRITE)
+            .unwrap()
+            .ignore();
+
+        for page in [0x1000, 0x1000 + pages(1)] {
+            let (_, flags, _) = space.directory().translate(addr(page)).unwrap();
+            assert!(flags.is_writable());
+        }
+    }
+
+    #[test]
+    fn unmap_after_fork_drops_one_reference() {
+        let mut pmm = setup_pmm(8);
+        let mut parent = AddressSpace::new();
+        parent
+            .map(
+                &mut pmm,
+                addr(0x1000),
+                pages(2),
+                Protection::READ_WRITE,
+                Backing::Anonymous,
+                "heap",
+            )
+            .unwrap()
+            .ignore();
+        let free = pmm.free_frames();
+        let (mut child, flush) = parent.fork(&mut pmm).unwrap();
+        flush.ignore();
+
+        parent
+            .unmap(&mut pmm, addr(0x1000), pages(2))
+            .unwrap()
+            .ignore();
+        assert_eq!(pmm.free_frames(), free);
+
+        child
+            .unmap(&mut pmm, addr(0x1000), pages(2))
+            .unwrap()
+            .ignore();
+        assert_eq!(pmm.free_frames(), free + 2);
+    }
+
+    #[test]
+    fn find_free_skips_areas_and_honors_alignment() {
+        if AddressTranslator::try_current().is_none() {
+            AddressTranslator::set_current(AddressTranslator::emulated(MEMORY_SIZE));
+        }
+        let mut space = AddressSpace::with_window(PageDirectory::new(), addr(0x1000), addr(0x2000));
+
+        assert_eq!(space.find_free(pages(2), 0), Some(addr(0x1000)));
+
+        space.reserve(addr(0x1000), pages(2), "a").unwrap();
+        space
+            .reserve(addr(0x1000 + pages(3)), pages(1), "b")
+            .unwrap();
+
+        assert_eq!(space.find_free(pages(1), 0), Some(addr(0x1000 + pages(2))));
+        assert_eq!(space.find_free(pages(2), 0), Some(addr(0x1000 + pages(4))));
+        assert_eq!(space.find_free(pages(1), 0x100), Some(addr(0x1100)));
+        assert_eq!(space.find_free(0x1000, 0), None);
+        assert_eq!(
+            space.find_free(0x1000 - pages(4), 0),
+            Some(addr(0x1000 + pages(4)))
+        );
     }
 }
@@ -3,6 +3,9 @@
 //! This module conditionally imports either hardware-specific implementations
 //! or software emulation based on the target architecture and features.
 
+mod page_flags;
+pub use page_flags::PageFlagBits;
+
 // Use x86_64 hardware implementation when we're on x86_64 and not testing or emulating.
 // NOTE: We DO include the module even during tests so that rust-analyzer can see it.
 #[cfg(all(target_arch = "x86_64"))]
@@ -10,6 +13,14 @@ mod x86_64;
 #[cfg(all(target_arch = "x86_64", not(test), not(feature = "software-emulation")))]
 pub use x86_64::*;
 
+// Use the RISC-V Sv39/Sv48 implementation on riscv64. Like x86_64, the module is also built
+// for tests, where its entry encoding is checked on the host.
+#[cfg(any(target_arch = "riscv64", test))]
+#[cfg_attr(test, allow(dead_code, unused_imports))]
+mod riscv64;
+#[cfg(all(target_arch = "riscv64", not(test), not(feature = "software-emulation")))]
+pub use riscv64::*;
+
 // Use software emulation ONLY when:
 // - Running tests, OR
 // - software-emulation feature is explicitly enabled
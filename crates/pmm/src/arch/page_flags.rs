@@ -0,0 +1,163 @@
+//! The flag bits shared by every architecture's page table entries.
+
+/// Reading and changing the bits of page table entry flags.
+///
+/// Every architecture's [`PageFlags`](super::PageFlags) implements this trait, so code written
+/// against it compiles for every target. Architecture-specific bits, such as the x86_64 memory
+/// type, stay inherent methods of the architecture's `PageFlags`.
+pub trait PageFlagBits: Copy + Default + Eq + core::fmt::Debug {
+    /// Returns whether the present bit is set.
+    fn is_present(self) -> bool;
+
+    /// Sets or clears the present bit.
+    fn set_present(&mut self, present: bool);
+
+    /// Returns whether the writable bit is set.
+    fn is_writable(self) -> bool;
+
+    /// Sets or clears the writable bit.
+    fn set_writable(&mut self, writable: bool);
+
+    /// Returns whether the user-accessible bit is set.
+    fn is_user(self) -> bool;
+
+    /// Sets or clears the user-accessible bit.
+    fn set_user(&mut self, user: bool);
+
+    /// Returns whether the global bit is set.
+    ///
+    /// Global pages are kept in the TLB across address space switches.
+    fn is_global(self) -> bool;
+
+    /// Sets or clears the global bit.
+    fn set_global(&mut self, global: bool);
+
+    /// Returns whether the accessed bit is set.
+    ///
+    /// The MMU sets this bit the first time an entry is used for a translation.
+    fn is_accessed(self) -> bool;
+
+    /// Sets or clears the accessed bit.
+    fn set_accessed(&mut self, accessed: bool);
+
+    /// Returns whether the dirty bit is set.
+    ///
+    /// The MMU sets this bit the first time a page is written through a leaf entry.
+    fn is_dirty(self) -> bool;
+
+    /// Sets or clears the dirty bit.
+    fn set_dirty(&mut self, dirty: bool);
+
+    /// Returns whether the write-through bit is set.
+    fn is_write_through(self) -> bool;
+
+    /// Sets or clears the write-through bit.
+    fn set_write_through(&mut self, write_through: bool);
+
+    /// Returns whether the cache-disable bit is set.
+    fn is_cache_disable(self) -> bool;
+
+    /// Sets or clears the cache-disable bit.
+    fn set_cache_disable(&mut self, cache_disable: bool);
+
+    /// Returns whether the no-execute bit is set.
+    fn is_no_execute(self) -> bool;
+
+    /// Sets or clears the no-execute bit.
+    fn set_no_execute(&mut self, no_execute: bool);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PageFlagBits;
+    use crate::PhysicalAddress;
+
+    type Getter<F> = fn(F) -> bool;
+    type Setter<F> = fn(&mut F, bool);
+
+    /// Every bit of the common API, by name, so failures say which one drifted.
+    fn bits<F: PageFlagBits>() -> [(&'static str, Getter<F>, Setter<F>); 9] {
+        [
+            ("present", F::is_present, F::set_present),
+            ("writable", F::is_writable, F::set_writable),
+            ("user", F::is_user, F::set_user),
+            ("global", F::is_global, F::set_global),
+            ("accessed", F::is_accessed, F::set_accessed),
+            ("dirty", F::is_dirty, F::set_dirty),
+            ("write-through", F::is_write_through, F::set_write_through),
+            ("cache-disable", F::is_cache_disable, F::set_cache_disable),
+            ("no-execute", F::is_no_execute, F::set_no_execute),
+        ]
+    }
+
+    /// Checks that every bit reads back as it was set, can be set and cleared on its own, and
+    /// survives being stored in a page table entry by `round_trip`.
+    fn check_bits<F: PageFlagBits>(round_trip: impl Fn(F) -> F) {
+        assert_eq!(F::default(), round_trip(F::default()));
+
+        for (name, get, set) in bits::<F>() {
+            let mut flags = F::default();
+            set(&mut flags, true);
+            assert!(get(flags), "{name} reads back clear after being set");
+            for (other, other_get, _) in bits::<F>() {
+                assert_eq!(
+                    other_get(flags),
+                    other == name,
+                    "setting {name} changed {other}"
+                );
+            }
+            assert_eq!(
+                round_trip(flags),
+                flags,
+                "{name} lost in a page table entry"
+            );
+            assert!(get(round_trip(flags)), "{name} lost in a page table entry");
+
+            set(&mut flags, false);
+            assert!(!get(flags), "{name} reads back set after being cleared");
+            assert_eq!(flags, F::default(), "clearing {name} left bits behind");
+        }
+
+        let mut all = F::default();
+        for (_, _, set) in bits::<F>() {
+            set(&mut all, true);
+        }
+        assert_eq!(round_trip(all), all);
+        for (name, get, set) in bits::<F>() {
+            assert!(get(all), "{name} reads back clear with every bit set");
+
+            let mut flags = all;
+            set(&mut flags, false);
+            assert!(!get(flags), "{name} reads back set after being cleared");
+            assert!(!get(round_trip(flags)), "{name} set by a page table entry");
+        }
+    }
+
+    #[test]
+    fn software_flags_round_trip_every_bit() {
+        use super::super::software::{PageEntry, PageFlags};
+
+        check_bits::<PageFlags>(|flags| {
+            PageEntry::new(PhysicalAddress::new(0x1230), flags).flags_at(0)
+        });
+    }
+
+    #[test]
+    fn riscv64_flags_round_trip_every_bit() {
+        use super::super::riscv64::{PageEntry, PageFlags};
+
+        check_bits::<PageFlags>(|flags| {
+            PageEntry::new(PhysicalAddress::new(0x1000), flags).flags_at(0)
+        });
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn x86_64_flags_round_trip_every_bit() {
+        use super::super::x86_64::{PageEntry, PageFlags};
+
+        check_bits::<PageFlags>(|flags| {
+            PageEntry::new(PhysicalAddress::new(0x1000), flags).flags_at(0)
+        });
+    }
+}
@@ -0,0 +1,261 @@
+//! Page table entry for RISC-V.
+
+use crate::PhysicalAddress;
+
+use super::flags::PageFlags;
+use crate::arch::PageFlagBits;
+
+/// A single Sv39/Sv48 page table entry.
+///
+/// The entry format:
+/// - Bits 0-7: Flags (V, R, W, X, U, G, A, D)
+/// - Bits 8-9: Reserved for software (owned table, write-through)
+/// - Bits 10-53: Physical page number
+/// - Bits 54-60: Reserved
+/// - Bits 61-62: Svpbmt memory type (cache-disable selects `IO`)
+/// - Bit 63: Reserved (Svnapot)
+///
+/// Unlike x86_64, RISC-V has no page size bit: an entry with any of R, W or X set is a leaf at
+/// whatever level it is found, and an entry with none of them points at the next table. Every
+/// entry created with [`new`](Self::new) or [`new_huge`](Self::new_huge) is a leaf;
+/// [`set_owned_table`](Self::set_owned_table) turns it into a pointer to a page table.
+///
+/// Harts without the Svadu extension fault on leaves whose A bit is clear, or whose D bit is
+/// clear on a write, instead of setting those bits, so such mappings should be created with
+/// them set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(transparent)]
+pub struct PageEntry(usize);
+
+impl PageEntry {
+    /// Physical page number mask (bits 10-53).
+    const PPN_MASK: usize = 0x003F_FFFF_FFFF_FC00;
+
+    /// Shift from the page number field to a physical address.
+    const PPN_SHIFT: usize = 10;
+
+    /// Executable bit (bit 3), stored as the inverse of the no-execute flag.
+    const EXECUTABLE_BIT: usize = PageFlags::NO_EXECUTE;
+
+    /// The R, W and X bits, any of which makes an entry a leaf.
+    const LEAF_BITS: usize = PageFlags::READABLE | PageFlags::WRITABLE | Self::EXECUTABLE_BIT;
+
+    /// Bits that must be clear in entries pointing at a page table: R, W, X, U, A and D.
+    const NON_TABLE_BITS: usize =
+        Self::LEAF_BITS | PageFlags::USER | PageFlags::ACCESSED | PageFlags::DIRTY;
+
+    /// Owned table bit (bit 8, one of the bits available to software).
+    ///
+    /// Marks entries pointing at page tables allocated by `PageDirectory`, which may be freed
+    /// once empty. Tables set up by the bootloader never carry this bit.
+    const OWNED_TABLE_BIT: usize = 1 << 8;
+
+    /// Creates a new leaf entry.
+    ///
+    /// The physical address must be page-aligned (lowest 12 bits must be zero). The R bit is
+    /// set if `flags` is present, and the X bit unless `flags` is no-execute.
+    pub fn new(address: PhysicalAddress, flags: PageFlags) -> Self {
+        debug_assert!(
+            address.as_usize() & 0xFFF == 0,
+            "physical address must be page-aligned"
+        );
+
+        let ppn_bits = (address.as_usize() >> 12 << Self::PPN_SHIFT) & Self::PPN_MASK;
+        Self(ppn_bits | Self::encode(flags))
+    }
+
+    /// Creates a new leaf entry mapping a megapage at level 1, a gigapage at level 2 or a
+    /// terapage at level 3.
+    ///
+    /// The physical address must be aligned to the size of the page mapped at that level.
+    /// Leaves look the same at every level on RISC-V, so this is the same as [`new`](Self::new).
+    pub fn new_huge(address: PhysicalAddress, flags: PageFlags) -> Self {
+        Self::new(address, flags)
+    }
+
+    /// Returns the physical address stored in this entry.
+    ///
+    /// Returns None if the entry is not present.
+    pub fn address(self) -> Option<PhysicalAddress> {
+        self.is_present()
+            .then(|| PhysicalAddress::new((self.0 & Self::PPN_MASK) >> Self::PPN_SHIFT << 12))
+    }
+
+    /// Returns the physical address stored in this entry, which lives at `level`.
+    ///
+    /// The RISC-V format reads the same at every level, so this is the same as
+    /// [`address`](Self::address).
+    pub fn address_at(self, _level: usize) -> Option<PhysicalAddress> {
+        self.address()
+    }
+
+    /// Returns the flags for this entry.
+    ///
+    /// The X bit is reported as the no-execute flag, so entries pointing at page tables, which
+    /// have X clear, report no-execute.
+    pub fn flags(self) -> PageFlags {
+        PageFlags::from((self.0 ^ Self::EXECUTABLE_BIT) & PageFlags::ALL)
+    }
+
+    /// Returns the flags for this entry, which lives at `level`.
+    pub fn flags_at(self, _level: usize) -> PageFlags {
+        self.flags()
+    }
+
+    /// Sets the flags for this entry, preserving the address.
+    ///
+    /// An entry pointing at a page table stays one: the R, W, X, U, A and D bits implied by
+    /// `flags` are dropped.
+    pub fn set_flags(&mut self, flags: PageFlags) {
+        let mut flag_bits = Self::encode(flags);
+        if self.is_present() && !self.is_leaf() {
+            flag_bits &= !Self::NON_TABLE_BITS;
+        }
+        self.0 = (self.0 & (Self::PPN_MASK | Self::OWNED_TABLE_BIT)) | flag_bits;
+    }
+
+    /// Sets the flags for this entry, which lives at `level`, preserving the address.
+    pub fn set_flags_at(&mut self, _level: usize, flags: PageFlags) {
+        self.set_flags(flags);
+    }
+
+    /// Returns whether this entry is present (valid).
+    pub fn is_present(self) -> bool {
+        self.flags().is_present()
+    }
+
+    /// Returns whether this entry is a leaf entry (maps a page directly).
+    ///
+    /// For RISC-V, this is determined by the R, W and X bits. At level 0 (the lowest level),
+    /// all present entries are leaf entries.
+    pub fn is_leaf(self) -> bool {
+        self.is_present() && (self.0 & Self::LEAF_BITS != 0)
+    }
+
+    /// Returns whether this entry points at a page table allocated by `PageDirectory`.
+    pub fn is_owned_table(self) -> bool {
+        self.is_present() && !self.is_leaf() && (self.0 & Self::OWNED_TABLE_BIT != 0)
+    }
+
+    /// Marks this entry as pointing at a page table allocated by `PageDirectory`.
+    ///
+    /// This also clears the R, W, X, U, A and D bits, which RISC-V requires to be clear in
+    /// entries pointing at a page table. Permissions are only checked at the leaf, so the
+    /// writable and user flags `PageDirectory` gives its tables are not needed.
+    pub fn set_owned_table(&mut self) {
+        self.0 = (self.0 & !Self::NON_TABLE_BITS) | Self::OWNED_TABLE_BIT;
+    }
+
+    /// Clears this entry (sets it to zero).
+    pub fn clear(&mut self) {
+        self.0 = 0;
+    }
+
+    /// Returns the raw usize value of this entry.
+    pub const fn as_usize(self) -> usize {
+        self.0
+    }
+
+    /// Encodes `flags` as the flag bits of a leaf entry.
+    fn encode(flags: PageFlags) -> usize {
+        let mut bits = (flags.as_usize() & PageFlags::ALL) ^ Self::EXECUTABLE_BIT;
+        if flags.is_present() {
+            bits |= PageFlags::READABLE;
+        }
+        bits
+    }
+}
+
+impl From<usize> for PageEntry {
+    fn from(value: usize) -> Self {
+        Self(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! Host tests validate physical addresses against the software model, so the addresses
+    //! used here stay below 64 KiB.
+
+    use super::*;
+
+    const V: usize = 1 << 0;
+    const R: usize = 1 << 1;
+    const W: usize = 1 << 2;
+    const X: usize = 1 << 3;
+    const U: usize = 1 << 4;
+
+    fn flags(writable: bool, user: bool, no_execute: bool) -> PageFlags {
+        let mut flags = PageFlags::empty();
+        flags.set_present(true);
+        flags.set_writable(writable);
+        flags.set_user(user);
+        flags.set_no_execute(no_execute);
+        flags
+    }
+
+    #[test]
+    fn leaves_are_readable_and_executable_unless_no_execute() {
+        let phys = PhysicalAddress::new(0x5000);
+
+        let entry = PageEntry::new(phys, flags(true, true, false));
+        assert_eq!(entry.as_usize(), 0x5 << 10 | V | R | W | X | U);
+        assert!(entry.is_leaf());
+
+        let entry = PageEntry::new(phys, flags(false, false, true));
+        assert_eq!(entry.as_usize(), 0x5 << 10 | V | R);
+        assert!(entry.is_leaf());
+        assert!(entry.flags().is_no_execute());
+        assert_eq!(entry.address(), Some(phys));
+    }
+
+    #[test]
+    fn huge_pages_are_encoded_like_small_pages() {
+        let phys = PhysicalAddress::new(0xe000);
+
+        assert_eq!(
+            PageEntry::new_huge(phys, flags(true, false, true)),
+            PageEntry::new(phys, flags(true, false, true))
+        );
+    }
+
+    #[test]
+    fn owned_tables_are_pointers_without_permissions() {
+        let phys = PhysicalAddress::new(0x3000);
+        let mut entry = PageEntry::new(phys, flags(true, true, false));
+        entry.set_owned_table();
+
+        assert_eq!(entry.as_usize() & (R | W | X | U), 0);
+        assert!(entry.is_present());
+        assert!(!entry.is_leaf());
+        assert!(entry.is_owned_table());
+        assert_eq!(entry.address(), Some(phys));
+
+        entry.set_flags(flags(true, true, false));
+        assert!(!entry.is_leaf());
+        assert!(entry.is_owned_table());
+        assert_eq!(entry.address(), Some(phys));
+    }
+
+    #[test]
+    fn hardware_table_pointers_are_not_leaves() {
+        // A pointer to the table at 0x4000, as firmware or a bootloader would write it.
+        let entry = PageEntry::from(0x4 << 10 | V);
+
+        assert!(entry.is_present());
+        assert!(!entry.is_leaf());
+        assert!(!entry.is_owned_table());
+        assert_eq!(entry.address(), Some(PhysicalAddress::new(0x4000)));
+    }
+
+    #[test]
+    fn cache_disable_selects_the_io_memory_type() {
+        let mut uncached = flags(true, false, true);
+        uncached.set_cache_disable(true);
+        let entry = PageEntry::new(PhysicalAddress::new(0x1000), uncached);
+
+        // Svpbmt: 0 = PMA, 1 = NC, 2 = IO.
+        assert_eq!((entry.as_usize() >> 61) & 0b11, 2);
+        assert_eq!(entry.address(), Some(PhysicalAddress::new(0x1000)));
+    }
+}
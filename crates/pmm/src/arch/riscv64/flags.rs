@@ -0,0 +1,170 @@
+//! Page table entry flags for RISC-V.
+
+use crate::arch::PageFlagBits;
+
+/// Page table entry flags for RISC-V.
+///
+/// Flags are stored as a raw usize with each bit at its position in a Sv39/Sv48
+/// [`PageEntry`](super::PageEntry), with two exceptions that let the flags share the meaning of
+/// every other architecture's:
+/// - Bit 3 is the no-execute bit rather than the X (executable) bit;
+///   [`PageEntry`](super::PageEntry) inverts it.
+/// - There is no R (readable) bit. Every present leaf is readable, so the entry sets R
+///   whenever the present (V) bit is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFlags(usize);
+
+impl PageFlags {
+    /// Valid bit (bit 0), the present bit.
+    pub(super) const VALID: usize = 1 << 0;
+
+    /// Readable bit (bit 1). Only found in entries, never in flags.
+    pub(super) const READABLE: usize = 1 << 1;
+
+    /// Writable bit (bit 2).
+    pub(super) const WRITABLE: usize = 1 << 2;
+
+    /// No-execute bit (bit 3), stored inverted in entries as the X bit.
+    pub(super) const NO_EXECUTE: usize = 1 << 3;
+
+    /// User-accessible bit (bit 4).
+    pub(super) const USER: usize = 1 << 4;
+
+    /// Global bit (bit 5).
+    const GLOBAL: usize = 1 << 5;
+
+    /// Accessed bit (bit 6).
+    pub(super) const ACCESSED: usize = 1 << 6;
+
+    /// Dirty bit (bit 7).
+    pub(super) const DIRTY: usize = 1 << 7;
+
+    /// Write-through bit (bit 9, one of the bits available to software).
+    ///
+    /// RISC-V has no write-through memory type, so this bit is only kept so that it reads
+    /// back; it does not change how the page is cached.
+    const WRITE_THROUGH: usize = 1 << 9;
+
+    /// Cache-disable bit (bit 62), the Svpbmt `IO` memory type: non-cacheable and strongly
+    /// ordered.
+    const CACHE_DISABLE: usize = 1 << 62;
+
+    /// Every bit these flags can hold.
+    pub(super) const ALL: usize = Self::VALID
+        | Self::WRITABLE
+        | Self::NO_EXECUTE
+        | Self::USER
+        | Self::GLOBAL
+        | Self::ACCESSED
+        | Self::DIRTY
+        | Self::WRITE_THROUGH
+        | Self::CACHE_DISABLE;
+
+    /// Creates empty page flags (page not present).
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Returns the raw usize value of these flags.
+    pub const fn as_usize(self) -> usize {
+        self.0
+    }
+
+    fn contains(self, bit: usize) -> bool {
+        (self.0 & bit) != 0
+    }
+
+    fn set(&mut self, bit: usize, value: bool) {
+        if value {
+            self.0 |= bit;
+        } else {
+            self.0 &= !bit;
+        }
+    }
+}
+
+impl From<usize> for PageFlags {
+    fn from(value: usize) -> Self {
+        Self(value & Self::ALL)
+    }
+}
+
+impl PageFlagBits for PageFlags {
+    fn is_present(self) -> bool {
+        self.contains(Self::VALID)
+    }
+
+    fn set_present(&mut self, present: bool) {
+        self.set(Self::VALID, present);
+    }
+
+    fn is_writable(self) -> bool {
+        self.contains(Self::WRITABLE)
+    }
+
+    fn set_writable(&mut self, writable: bool) {
+        self.set(Self::WRITABLE, writable);
+    }
+
+    fn is_user(self) -> bool {
+        self.contains(Self::USER)
+    }
+
+    fn set_user(&mut self, user: bool) {
+        self.set(Self::USER, user);
+    }
+
+    fn is_global(self) -> bool {
+        self.contains(Self::GLOBAL)
+    }
+
+    fn set_global(&mut self, global: bool) {
+        self.set(Self::GLOBAL, global);
+    }
+
+    fn is_accessed(self) -> bool {
+        self.contains(Self::ACCESSED)
+    }
+
+    fn set_accessed(&mut self, accessed: bool) {
+        self.set(Self::ACCESSED, accessed);
+    }
+
+    fn is_dirty(self) -> bool {
+        self.contains(Self::DIRTY)
+    }
+
+    fn set_dirty(&mut self, dirty: bool) {
+        self.set(Self::DIRTY, dirty);
+    }
+
+    fn is_write_through(self) -> bool {
+        self.contains(Self::WRITE_THROUGH)
+    }
+
+    fn set_write_through(&mut self, write_through: bool) {
+        self.set(Self::WRITE_THROUGH, write_through);
+    }
+
+    fn is_cache_disable(self) -> bool {
+        self.contains(Self::CACHE_DISABLE)
+    }
+
+    fn set_cache_disable(&mut self, cache_disable: bool) {
+        self.set(Self::CACHE_DISABLE, cache_disable);
+    }
+
+    fn is_no_execute(self) -> bool {
+        self.contains(Self::NO_EXECUTE)
+    }
+
+    fn set_no_execute(&mut self, no_execute: bool) {
+        self.set(Self::NO_EXECUTE, no_execute);
+    }
+}
+
+impl Default for PageFlags {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
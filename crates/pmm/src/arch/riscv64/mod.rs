@@ -0,0 +1,166 @@
+//! RISC-V (RV64) architecture-specific implementation.
+//!
+//! This module provides the Sv39 and Sv48 paging implementation for RISC-V, including address
+//! validation, page table configuration, and low-level page table primitives. The entry
+//! encoding does not depend on RISC-V instructions, so it is compiled and unit tested on the
+//! host as well.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+mod entry;
+mod flags;
+mod table;
+
+pub use entry::PageEntry;
+pub use flags::PageFlags;
+pub use table::PageTable;
+
+/// Maximum number of bits in a physical address on RISC-V: a 44-bit physical page number
+/// above a 12-bit page offset.
+pub const MAX_PHYSICAL_BITS: usize = 56;
+
+/// Maximum number of bits in a virtual address on RISC-V, reached with Sv48.
+pub const MAX_VIRTUAL_BITS: usize = 48;
+
+/// Default page size in bytes (4 KiB).
+pub const PAGE_SIZE: usize = 4096;
+
+/// Maximum number of page table levels on RISC-V (Sv48).
+pub const MAX_PAGE_TABLE_LEVELS: usize = 4;
+
+/// Number of page table levels in use, chosen by [`set_page_table_levels`].
+static PAGE_TABLE_LEVELS: AtomicUsize = AtomicUsize::new(3);
+
+/// Returns the number of page table levels in use: 3 with Sv39, or 4 with Sv48.
+#[inline]
+pub fn page_table_levels() -> usize {
+    PAGE_TABLE_LEVELS.load(Ordering::Relaxed)
+}
+
+/// Selects Sv39 (3 levels) or Sv48 (4 levels), matching the mode in `satp`.
+///
+/// This must be called before any virtual address is created or any page table is walked,
+/// since both depend on the level count.
+///
+/// # Panics
+/// Panics if `levels` is not 3 or 4.
+pub fn set_page_table_levels(levels: usize) {
+    assert!(
+        levels == 3 || levels == 4,
+        "RISC-V supports Sv39 (3 levels) or Sv48 (4 levels) paging"
+    );
+    PAGE_TABLE_LEVELS.store(levels, Ordering::Relaxed);
+}
+
+/// Returns the number of bits in a canonical virtual address: 39 with Sv39, or 48 with Sv48.
+#[inline]
+pub fn virtual_bits() -> usize {
+    12 + 9 * page_table_levels()
+}
+
+/// Upper bound (exclusive) of the DMA zone.
+///
+/// RISC-V has no legacy ISA DMA, so the zone is empty.
+pub const ZONE_DMA_LIMIT: usize = 0;
+
+/// Upper bound (exclusive) of the DMA32 zone: memory reachable by 32-bit DMA devices (4 GiB).
+pub const ZONE_DMA32_LIMIT: usize = 4 * 1024 * 1024 * 1024;
+
+/// Returns the page table index for a given virtual address at the specified level.
+///
+/// Sv39 and Sv48 use 9 bits at each level, with level 0 being the leaf table and level 2
+/// (Sv39) or level 3 (Sv48) the root table.
+#[inline]
+pub const fn page_index(address: usize, level: usize) -> usize {
+    let bits_for_level = match level {
+        0..MAX_PAGE_TABLE_LEVELS => 9,
+        _ => panic!("level out of range for RISC-V page table levels"),
+    };
+    let shift = 12 + (level * bits_for_level);
+    (address >> shift) & ((1 << bits_for_level) - 1)
+}
+
+/// Returns the number of bytes mapped by a single leaf entry at the specified level.
+///
+/// This is 4 KiB at level 0, 2 MiB (megapage) at level 1, 1 GiB (gigapage) at level 2 and
+/// 512 GiB (terapage) at level 3.
+#[inline]
+pub const fn level_size(level: usize) -> usize {
+    PAGE_SIZE << (9 * level)
+}
+
+/// Invalidates the TLB entry for the page containing `addr` on the current hart.
+#[inline]
+pub fn flush_tlb_page(addr: usize) {
+    #[cfg(target_arch = "riscv64")]
+    // SAFETY: sfence.vma only orders and invalidates address translation caches.
+    unsafe {
+        core::arch::asm!("sfence.vma {}, zero", in(reg) addr, options(nostack));
+    }
+    #[cfg(not(target_arch = "riscv64"))]
+    let _ = addr;
+}
+
+/// Invalidates all TLB entries on the current hart.
+#[inline]
+pub fn flush_tlb_all() {
+    #[cfg(target_arch = "riscv64")]
+    // SAFETY: sfence.vma only orders and invalidates address translation caches.
+    unsafe {
+        core::arch::asm!("sfence.vma zero, zero", options(nostack));
+    }
+}
+
+/// Validates a physical address for RISC-V.
+///
+/// Physical addresses must not exceed the maximum physical address width.
+#[inline]
+pub const fn validate_physical(addr: usize) -> bool {
+    let max_addr = (1usize << MAX_PHYSICAL_BITS) - 1;
+    addr <= max_addr
+}
+
+/// Validates a virtual address for RISC-V.
+///
+/// Virtual addresses must be canonical: the bits above the top bit of the address width (bit 38
+/// with Sv39, or bit 47 with Sv48) must be copies of it.
+#[inline]
+pub fn validate_virtual(addr: usize) -> bool {
+    canonicalize_virtual(addr) == addr
+}
+
+/// Canonicalizes a virtual address for RISC-V.
+///
+/// This sign-extends the top bit of the address width (bit 38 with Sv39, or bit 47 with Sv48)
+/// to the bits above it.
+#[inline]
+pub fn canonicalize_virtual(addr: usize) -> usize {
+    let unused = usize::BITS as usize - virtual_bits();
+    (((addr << unused) as isize) >> unused) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sv48_widens_canonical_addresses() {
+        assert_eq!(virtual_bits(), 39);
+        assert!(validate_virtual(0xffff_ffc0_0000_0000));
+        assert!(!validate_virtual(0x0000_0040_0000_0000));
+        assert_eq!(page_index(0xffff_ffc0_0000_0000, 2), 0x100);
+
+        set_page_table_levels(4);
+        assert_eq!(virtual_bits(), 48);
+        assert!(validate_virtual(0x0000_0040_0000_0000));
+        assert!(!validate_virtual(0xffff_ffc0_0000_0000 & !(1 << 47)));
+        assert_eq!(
+            canonicalize_virtual(0x0000_8000_0000_0000),
+            0xffff_8000_0000_0000
+        );
+        assert_eq!(page_index(0xffff_8000_0000_0000, 3), 0x100);
+
+        set_page_table_levels(3);
+        assert!(!validate_virtual(0x0000_0040_0000_0000));
+    }
+}
@@ -0,0 +1,91 @@
+//! Page table structure for RISC-V.
+
+use crate::{PhysicalAddress, address::AddressTranslator};
+
+use super::entry::PageEntry;
+
+/// Number of entries in a Sv39/Sv48 page table.
+const ENTRY_COUNT: usize = 512;
+
+/// A Sv39/Sv48 page table: 512 eight-byte entries filling one 4 KiB page.
+///
+/// Like the x86_64 table, a pointer to `PageTable` is always a pointer to the page of entries
+/// the hart walks, so HHDM-mapped physical page table addresses can be cast to
+/// `*mut PageTable`.
+#[repr(C, align(4096))]
+pub struct PageTable {
+    /// The entries in this page table.
+    entries: [PageEntry; ENTRY_COUNT],
+}
+
+impl PageTable {
+    /// Creates a new, empty page table.
+    ///
+    /// All entries are initialized to zero (not present).
+    pub fn new() -> Self {
+        Self {
+            entries: [PageEntry::default(); ENTRY_COUNT],
+        }
+    }
+
+    /// Returns a reference to the entry at the given index.
+    ///
+    /// # Panics
+    /// Panics if index >= 512.
+    pub fn entry(&self, index: usize) -> PageEntry {
+        assert!(index < ENTRY_COUNT, "page table index out of bounds");
+        self.entries[index]
+    }
+
+    /// Returns a mutable reference to the entry at the given index.
+    ///
+    /// # Panics
+    /// Panics if index >= 512.
+    pub fn entry_mut(&mut self, index: usize) -> &mut PageEntry {
+        assert!(index < ENTRY_COUNT, "page table index out of bounds");
+        &mut self.entries[index]
+    }
+
+    /// Returns the number of entries in this page table.
+    pub const fn len(&self) -> usize {
+        ENTRY_COUNT
+    }
+
+    /// Returns the physical address of this page table.
+    ///
+    /// This is the address that would be stored in a parent page table entry
+    /// or loaded into `satp`.
+    pub fn physical_address(&self) -> PhysicalAddress {
+        let virt = self as *const Self as usize;
+        PhysicalAddress::new(AddressTranslator::current().virt_to_phys(virt))
+    }
+
+    /// Activates this page table by loading it into `satp`, in the Sv39 or Sv48 mode selected
+    /// by [`set_page_table_levels`](super::set_page_table_levels).
+    ///
+    /// # Safety
+    /// This function is unsafe because loading an invalid page table can cause
+    /// undefined behavior, including memory corruption and system crashes.
+    /// The caller must ensure:
+    /// - The page table correctly maps all memory that will be accessed
+    /// - The kernel is properly mapped
+    /// - The page table itself is mapped
+    pub unsafe fn activate(&self) {
+        // satp.MODE is 8 for Sv39 and 9 for Sv48.
+        let mode = super::page_table_levels() + 5;
+        let satp = mode << 60 | self.physical_address().as_usize() >> 12;
+        #[cfg(target_arch = "riscv64")]
+        // SAFETY: Caller must ensure the page table is valid
+        unsafe {
+            core::arch::asm!("csrw satp, {}", "sfence.vma zero, zero", in(reg) satp, options(nostack));
+        }
+        #[cfg(not(target_arch = "riscv64"))]
+        let _ = satp;
+    }
+}
+
+impl Default for PageTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -3,14 +3,18 @@
 use crate::PhysicalAddress;
 
 use super::flags::PageFlags;
+use crate::arch::PageFlagBits;
 
 /// A single page table entry for software emulation.
 ///
 /// This is a scale model of x86_64 using 16-bit addresses stored in 64-bit values.
 /// The entry format:
-/// - Bits 0-3: Flags (4 bits reserved for flags)
+/// - Bits 0-3: Flags (present, writable, user, no-execute)
 /// - Bits 4-19: Physical address (16 bits, sign-extended to 64 bits)
-/// - Bits 20-63: Reserved (must be sign-extension of bit 19)
+/// - Bit 20: Huge page (the entry maps a large page rather than pointing at a table)
+/// - Bit 21: Owned table (the entry points at a table allocated by `PageDirectory`)
+/// - Bits 22-26: Flags (write-through, cache-disable, accessed, dirty, global)
+/// - Bits 27-63: Reserved
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
 pub struct PageEntry(usize);
@@ -21,11 +25,17 @@ impl PageEntry {
     /// Bits 8-19 are the actual address bits we care about.
     const ADDRESS_MASK: usize = 0xFFFF0;
 
-    /// Flag bits mask (bits 0-3).
-    const FLAGS_MASK: usize = 0xF;
+    /// Flag bits mask (bits 0-3 and 22-26).
+    const FLAGS_MASK: usize = 0xF | (0x1F << 22);
 
-    /// Huge page bit (bit 7 in the address field, which is bit 11 overall).
-    const HUGE_PAGE_BIT: usize = 1 << 7;
+    /// Huge page bit (bit 20, just above the address field).
+    const HUGE_PAGE_BIT: usize = 1 << 20;
+
+    /// Owned table bit (bit 21, just above the huge page bit).
+    ///
+    /// Marks entries pointing at page tables allocated by `PageDirectory`, which may be freed
+    /// once empty. Tables set up by the bootloader never carry this bit.
+    const OWNED_TABLE_BIT: usize = 1 << 21;
 
     /// Creates a new page table entry.
     ///
@@ -39,10 +49,17 @@ impl PageEntry {
         // Canonicalize the address (sign-extend from bit 15)
         let addr = Self::canonicalize(address.as_usize());
         let addr_bits = addr & Self::ADDRESS_MASK;
-        let flag_bits = flags.to_raw() & Self::FLAGS_MASK;
+        let flag_bits = flags.as_usize() & Self::FLAGS_MASK;
         Self(addr_bits | flag_bits)
     }
 
+    /// Creates a new leaf entry mapping a huge page at level 1 or 2.
+    ///
+    /// The physical address must be aligned to the size of the page mapped at that level.
+    pub fn new_huge(address: PhysicalAddress, flags: PageFlags) -> Self {
+        Self(Self::new(address, flags).0 | Self::HUGE_PAGE_BIT)
+    }
+
     /// Returns the physical address stored in this entry.
     ///
     /// Returns None if the entry is not present.
@@ -56,30 +73,60 @@ impl PageEntry {
         }
     }
 
+    /// Returns the physical address stored in this entry, which lives at `level`.
+    ///
+    /// The software format reads the same at every level, so this is the same as
+    /// [`address`](Self::address).
+    pub fn address_at(self, _level: usize) -> Option<PhysicalAddress> {
+        self.address()
+    }
+
     /// Returns the flags for this entry.
     pub fn flags(self) -> PageFlags {
-        PageFlags::from_raw(self.0 & Self::FLAGS_MASK)
+        PageFlags::from(self.0 & Self::FLAGS_MASK)
+    }
+
+    /// Returns the flags for this entry, which lives at `level`.
+    pub fn flags_at(self, _level: usize) -> PageFlags {
+        self.flags()
     }
 
     /// Sets the flags for this entry, preserving the address.
     pub fn set_flags(&mut self, flags: PageFlags) {
-        let addr_bits = self.0 & Self::ADDRESS_MASK;
-        let flag_bits = flags.to_raw() & Self::FLAGS_MASK;
+        let addr_bits = self.0 & (Self::ADDRESS_MASK | Self::HUGE_PAGE_BIT | Self::OWNED_TABLE_BIT);
+        let flag_bits = flags.as_usize() & Self::FLAGS_MASK;
         self.0 = addr_bits | flag_bits;
     }
 
+    /// Sets the flags for this entry, which lives at `level`, preserving the address.
+    pub fn set_flags_at(&mut self, _level: usize, flags: PageFlags) {
+        self.set_flags(flags);
+    }
+
     /// Returns whether this entry is present (valid).
     pub fn is_present(self) -> bool {
         self.flags().is_present()
     }
 
-    /// Returns whether this entry is a leaf entry (maps a page directly).
+    /// Returns whether this entry is a huge leaf entry (maps a page directly).
     ///
-    /// For software emulation, this is determined by the huge page bit.
+    /// For software emulation, this is determined by the huge page bit. If set at level 1 or 2,
+    /// this entry maps a 256-byte or 4 KiB page respectively. At level 0 (the lowest level),
+    /// all present entries are leaf entries.
     pub fn is_leaf(self) -> bool {
         self.is_present() && (self.0 & Self::HUGE_PAGE_BIT != 0)
     }
 
+    /// Returns whether this entry points at a page table allocated by `PageDirectory`.
+    pub fn is_owned_table(self) -> bool {
+        self.is_present() && !self.is_leaf() && (self.0 & Self::OWNED_TABLE_BIT != 0)
+    }
+
+    /// Marks this entry as pointing at a page table allocated by `PageDirectory`.
+    pub fn set_owned_table(&mut self) {
+        self.0 |= Self::OWNED_TABLE_BIT;
+    }
+
     /// Clears this entry (sets it to zero).
     pub fn clear(&mut self) {
         self.0 = 0;
@@ -1,9 +1,11 @@
 //! Page table entry flags for software emulation.
 
+use crate::arch::PageFlagBits;
+
 /// Page table entry flags for software emulation.
 ///
 /// This provides a simplified flag implementation for testing. Flags are stored
-/// as a raw u64 with specific bits representing different permissions.
+/// as a raw usize with each bit at its position in a [`PageEntry`](super::PageEntry).
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PageFlags(usize);
 
@@ -20,75 +22,121 @@ impl PageFlags {
     /// No-execute bit (bit 3).
     const NO_EXECUTE: usize = 1 << 3;
 
+    /// Write-through bit (bit 22, above the owned table bit).
+    const WRITE_THROUGH: usize = 1 << 22;
+
+    /// Cache-disable bit (bit 23).
+    const CACHE_DISABLE: usize = 1 << 23;
+
+    /// Accessed bit (bit 24).
+    const ACCESSED: usize = 1 << 24;
+
+    /// Dirty bit (bit 25).
+    const DIRTY: usize = 1 << 25;
+
+    /// Global bit (bit 26).
+    const GLOBAL: usize = 1 << 26;
+
     /// Creates empty page flags (page not present).
     pub const fn empty() -> Self {
         Self(0)
     }
 
-    /// Creates page flags from a raw usize value.
-    pub const fn from_raw(raw: usize) -> Self {
-        Self(raw)
-    }
-
     /// Returns the raw usize value of these flags.
-    pub const fn to_raw(self) -> usize {
+    pub const fn as_usize(self) -> usize {
         self.0
     }
 
-    /// Returns whether the present bit is set.
-    pub fn is_present(self) -> bool {
-        (self.0 & Self::PRESENT) != 0
+    fn contains(self, bit: usize) -> bool {
+        (self.0 & bit) != 0
     }
 
-    /// Sets or clears the present bit.
-    pub fn set_present(&mut self, present: bool) {
-        if present {
-            self.0 |= Self::PRESENT;
+    fn set(&mut self, bit: usize, value: bool) {
+        if value {
+            self.0 |= bit;
         } else {
-            self.0 &= !Self::PRESENT;
+            self.0 &= !bit;
         }
     }
+}
 
-    /// Returns whether the writable bit is set.
-    pub fn is_writable(self) -> bool {
-        (self.0 & Self::WRITABLE) != 0
+impl From<usize> for PageFlags {
+    fn from(value: usize) -> Self {
+        Self(value)
     }
+}
 
-    /// Sets or clears the writable bit.
-    pub fn set_writable(&mut self, writable: bool) {
-        if writable {
-            self.0 |= Self::WRITABLE;
-        } else {
-            self.0 &= !Self::WRITABLE;
-        }
+impl PageFlagBits for PageFlags {
+    fn is_present(self) -> bool {
+        self.contains(Self::PRESENT)
     }
 
-    /// Returns whether the user-accessible bit is set.
-    pub fn is_user(self) -> bool {
-        (self.0 & Self::USER) != 0
+    fn set_present(&mut self, present: bool) {
+        self.set(Self::PRESENT, present);
     }
 
-    /// Sets or clears the user-accessible bit.
-    pub fn set_user(&mut self, user: bool) {
-        if user {
-            self.0 |= Self::USER;
-        } else {
-            self.0 &= !Self::USER;
-        }
+    fn is_writable(self) -> bool {
+        self.contains(Self::WRITABLE)
     }
 
-    /// Returns whether the no-execute bit is set.
-    pub fn is_no_execute(self) -> bool {
-        (self.0 & Self::NO_EXECUTE) != 0
+    fn set_writable(&mut self, writable: bool) {
+        self.set(Self::WRITABLE, writable);
     }
 
-    /// Sets or clears the no-execute bit.
-    pub fn set_no_execute(&mut self, no_execute: bool) {
-        if no_execute {
-            self.0 |= Self::NO_EXECUTE;
-        } else {
-            self.0 &= !Self::NO_EXECUTE;
-        }
+    fn is_user(self) -> bool {
+        self.contains(Self::USER)
+    }
+
+    fn set_user(&mut self, user: bool) {
+        self.set(Self::USER, user);
+    }
+
+    fn is_global(self) -> bool {
+        self.contains(Self::GLOBAL)
+    }
+
+    fn set_global(&mut self, global: bool) {
+        self.set(Self::GLOBAL, global);
+    }
+
+    fn is_accessed(self) -> bool {
+        self.contains(Self::ACCESSED)
+    }
+
+    fn set_accessed(&mut self, accessed: bool) {
+        self.set(Self::ACCESSED, accessed);
+    }
+
+    fn is_dirty(self) -> bool {
+        self.contains(Self::DIRTY)
+    }
+
+    fn set_dirty(&mut self, dirty: bool) {
+        self.set(Self::DIRTY, dirty);
+    }
+
+    fn is_write_through(self) -> bool {
+        self.contains(Self::WRITE_THROUGH)
+    }
+
+    fn set_write_through(&mut self, write_through: bool) {
+        self.set(Self::WRITE_THROUGH, write_through);
+    }
+
+    fn is_cache_disable(self) -> bool {
+        self.contains(Self::CACHE_DISABLE)
+    }
+
+    fn set_cache_disable(&mut self, cache_disable: bool) {
+        self.set(Self::CACHE_DISABLE, cache_disable);
+    }
+
+    fn is_no_execute(self) -> bool {
+        self.contains(Self::NO_EXECUTE)
+    }
+
+    fn set_no_execute(&mut self, no_execute: bool) {
+        self.set(Self::NO_EXECUTE, no_execute);
     }
 }
 
@@ -0,0 +1,276 @@
+//! Emulated MMU that translates accesses through software page tables.
+//!
+//! The MMU walks the page table hierarchy the way an x86_64 CPU does (with `CR0.WP` and
+//! `EFER.NXE` set, and without SMEP/SMAP): an access must find a present entry at every level,
+//! the writable and user bits must be set at every level for writes and user accesses, and the
+//! no-execute bit at any level forbids instruction fetches. Failed accesses are reported as a
+//! [`PageFault`] carrying an x86-style error code.
+
+use core::ops::BitOr;
+
+use crate::arch::PageFlagBits;
+use crate::{PhysicalAddress, VirtualAddress};
+
+use super::{EmulatedMemory, PAGE_SIZE, PageTable, level_size, page_index, page_table_levels};
+
+/// Entry bits above the global bit, which must be zero in every entry.
+const RESERVED_BITS: usize = !((1 << 27) - 1);
+
+/// The kind of memory access performed through the emulated MMU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// A data read.
+    Read,
+    /// A data write.
+    Write,
+    /// An instruction fetch.
+    Execute,
+}
+
+/// The privilege level an access is performed at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Privilege {
+    /// Kernel mode (CPL 0).
+    Supervisor,
+    /// User mode (CPL 3).
+    User,
+}
+
+/// Error code of a page fault, laid out like the one x86_64 pushes for `#PF`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PageFaultErrorCode(u64);
+
+impl PageFaultErrorCode {
+    /// The fault was caused by a protection violation rather than a non-present entry.
+    pub const PROTECTION_VIOLATION: Self = Self(1 << 0);
+    /// The fault was caused by a write.
+    pub const CAUSED_BY_WRITE: Self = Self(1 << 1);
+    /// The fault happened in user mode.
+    pub const USER_MODE: Self = Self(1 << 2);
+    /// A reserved bit was set in one of the entries walked.
+    pub const MALFORMED_TABLE: Self = Self(1 << 3);
+    /// The fault was caused by an instruction fetch.
+    pub const INSTRUCTION_FETCH: Self = Self(1 << 4);
+
+    /// Returns an error code with no bits set (a supervisor read of a non-present page).
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Returns the raw error code.
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// Returns true if every bit of `other` is set in `self`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for PageFaultErrorCode {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// A fault raised by the emulated MMU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFault {
+    /// The faulting virtual address, as it would be reported in `CR2`.
+    pub address: VirtualAddress,
+    /// Why the access faulted.
+    pub error_code: PageFaultErrorCode,
+}
+
+impl EmulatedMemory {
+    /// Translates a virtual address through the page tables rooted at `root`, checking that
+    /// the access is permitted.
+    pub fn translate_virtual(
+        &self,
+        root: &PageTable,
+        virt: VirtualAddress,
+        access: Access,
+        privilege: Privilege,
+    ) -> Result<PhysicalAddress, PageFault> {
+        let mut error_code = match access {
+            Access::Read => PageFaultErrorCode::empty(),
+            Access::Write => PageFaultErrorCode::CAUSED_BY_WRITE,
+            Access::Execute => PageFaultErrorCode::INSTRUCTION_FETCH,
+        };
+        if privilege == Privilege::User {
+            error_code = error_code | PageFaultErrorCode::USER_MODE;
+        }
+        let fault = |error_code| PageFault {
+            address: virt,
+            error_code,
+        };
+
+        let addr = virt.as_usize();
+        let mut table = root;
+        let mut writable = true;
+        let mut user = true;
+        let mut no_execute = false;
+        for level in (0..page_table_levels()).rev() {
+            let entry = table.entry(page_index(addr, level));
+            if !entry.is_present() {
+                return Err(fault(error_code));
+            }
+            if entry.as_usize() & RESERVED_BITS != 0 {
+                return Err(fault(
+                    error_code
+                        | PageFaultErrorCode::PROTECTION_VIOLATION
+                        | PageFaultErrorCode::MALFORMED_TABLE,
+                ));
+            }
+
+            let flags = entry.flags();
+            writable &= flags.is_writable();
+            user &= flags.is_user();
+            no_execute |= flags.is_no_execute();
+
+            let phys = entry.address().expect("entry should be present");
+            if level > 0 && !entry.is_leaf() {
+                // SAFETY: Present non-leaf entries point at page tables in emulated memory.
+                table = unsafe { &*(self.translate(phys.as_usize()) as *const PageTable) };
+                continue;
+            }
+
+            let denied = match access {
+                Access::Read => false,
+                Access::Write => !writable,
+                Access::Execute => no_execute,
+            } || (privilege == Privilege::User && !user);
+            if denied {
+                return Err(fault(error_code | PageFaultErrorCode::PROTECTION_VIOLATION));
+            }
+
+            let offset = addr & (level_size(level) - 1);
+            return Ok(PhysicalAddress::new(phys.as_usize() + offset));
+        }
+
+        unreachable!("level 0 entries are always leaves")
+    }
+
+    /// Reads `buf.len()` bytes at `virt` through the page tables rooted at `root`.
+    ///
+    /// Every page touched is translated before any of it is read, so a fault leaves `buf`
+    /// untouched.
+    pub fn read(
+        &self,
+        root: &PageTable,
+        virt: VirtualAddress,
+        buf: &mut [u8],
+        privilege: Privilege,
+    ) -> Result<(), PageFault> {
+        self.access(
+            root,
+            virt,
+            buf.len(),
+            Access::Read,
+            privilege,
+            |offset, phys, len| {
+                // SAFETY: `access` checks that every piece lies inside emulated memory.
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        self.translate(phys),
+                        buf[offset..].as_mut_ptr(),
+                        len,
+                    )
+                };
+            },
+        )
+    }
+
+    /// Fetches `buf.len()` bytes of instructions at `virt` through the page tables rooted at
+    /// `root`.
+    pub fn fetch(
+        &self,
+        root: &PageTable,
+        virt: VirtualAddress,
+        buf: &mut [u8],
+        privilege: Privilege,
+    ) -> Result<(), PageFault> {
+        self.access(
+            root,
+            virt,
+            buf.len(),
+            Access::Execute,
+            privilege,
+            |offset, phys, len| {
+                // SAFETY: As in `read`.
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        self.translate(phys),
+                        buf[offset..].as_mut_ptr(),
+                        len,
+                    )
+                };
+            },
+        )
+    }
+
+    /// Writes `data` at `virt` through the page tables rooted at `root`.
+    ///
+    /// Every page touched is translated before any of it is written, so a fault leaves memory
+    /// untouched.
+    pub fn write(
+        &self,
+        root: &PageTable,
+        virt: VirtualAddress,
+        data: &[u8],
+        privilege: Privilege,
+    ) -> Result<(), PageFault> {
+        self.access(
+            root,
+            virt,
+            data.len(),
+            Access::Write,
+            privilege,
+            |offset, phys, len| {
+                // SAFETY: As in `read`.
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        data[offset..].as_ptr(),
+                        self.translate(phys),
+                        len,
+                    )
+                };
+            },
+        )
+    }
+
+    /// Translates every page of `[virt, virt + len)`, then calls `copy` with the offset,
+    /// physical address and length of each page-sized piece.
+    fn access(
+        &self,
+        root: &PageTable,
+        virt: VirtualAddress,
+        len: usize,
+        access: Access,
+        privilege: Privilege,
+        mut copy: impl FnMut(usize, usize, usize),
+    ) -> Result<(), PageFault> {
+        let start = virt.as_usize();
+        let mut pieces = alloc::vec::Vec::new();
+        let mut offset = 0;
+        while offset < len {
+            let addr = VirtualAddress::new(start + offset);
+            let piece = (PAGE_SIZE - addr.as_usize() % PAGE_SIZE).min(len - offset);
+            let phys = self.translate_virtual(root, addr, access, privilege)?;
+            assert!(
+                phys.as_usize() + piece <= self.size(),
+                "physical address out of bounds"
+            );
+            pieces.push((offset, phys.as_usize(), piece));
+            offset += piece;
+        }
+
+        for (offset, phys, piece) in pieces {
+            copy(offset, phys, piece);
+        }
+        Ok(())
+    }
+}
@@ -5,18 +5,23 @@
 //!
 //! The software-emulated architecture is a "scale model" of x86_64:
 //! - 16-bit addresses (vs 48-bit on x86_64)
-//! - 3 levels of page tables (vs 4 on x86_64)
+//! - 3 levels of page tables (vs 4 on x86_64), or 2 to stand in for the choice between 4-level
+//!   and 5-level paging
 //! - 4-bit indexes (16 entries per table, vs 9-bit/512 entries on x86_64)
 //! - 4-bit page offset (16-byte pages, vs 12-bit/4KB on x86_64)
 //!
 //! This provides realistic paging behavior while keeping memory usage minimal for testing.
 
+use alloc::sync::Arc;
+
 mod entry;
 mod flags;
+mod mmu;
 mod table;
 
 pub use entry::PageEntry;
 pub use flags::PageFlags;
+pub use mmu::{Access, PageFault, PageFaultErrorCode, Privilege};
 pub use table::PageTable;
 
 /// Maximum number of bits in a physical address for software emulation.
@@ -28,8 +33,49 @@ pub const MAX_VIRTUAL_BITS: usize = 16;
 /// Page size in bytes (16 bytes = 2^4).
 pub const PAGE_SIZE: usize = 16;
 
-/// Number of page table levels (3 levels: level 2, 1, 0).
-pub const PAGE_TABLE_LEVELS: usize = 3;
+/// Maximum number of page table levels (3 levels: level 2, 1, 0).
+pub const MAX_PAGE_TABLE_LEVELS: usize = 3;
+
+std::thread_local! {
+    /// Number of page table levels in use on this thread, chosen by [`set_page_table_levels`].
+    ///
+    /// Like the address translator, this is per thread so that tests running in parallel can
+    /// pick different paging modes.
+    static PAGE_TABLE_LEVELS: core::cell::Cell<usize> = const { core::cell::Cell::new(3) };
+}
+
+/// Returns the number of page table levels in use on this thread: 3 by default, or 2.
+#[inline]
+pub fn page_table_levels() -> usize {
+    PAGE_TABLE_LEVELS.with(|levels| levels.get())
+}
+
+/// Selects 2-level or 3-level paging for this thread.
+///
+/// This must be called before any virtual address is created or any page table is walked,
+/// since both depend on the level count.
+///
+/// # Panics
+/// Panics if `levels` is not 2 or 3.
+pub fn set_page_table_levels(levels: usize) {
+    assert!(
+        levels == 2 || levels == 3,
+        "software emulation supports 2-level or 3-level paging"
+    );
+    PAGE_TABLE_LEVELS.with(|current| current.set(levels));
+}
+
+/// Returns the number of bits in a canonical virtual address: 16, or 12 with 2-level paging.
+#[inline]
+pub fn virtual_bits() -> usize {
+    4 + 4 * page_table_levels()
+}
+
+/// Upper bound (exclusive) of the DMA zone (a scaled-down stand-in for x86_64's 16 MiB).
+pub const ZONE_DMA_LIMIT: usize = 0x1000;
+
+/// Upper bound (exclusive) of the DMA32 zone (a scaled-down stand-in for x86_64's 4 GiB).
+pub const ZONE_DMA32_LIMIT: usize = 0x4000;
 
 /// Returns the page table index for a given virtual address at the specified level.
 ///
@@ -40,13 +86,34 @@ pub const PAGE_TABLE_LEVELS: usize = 3;
 #[inline]
 pub const fn page_index(address: usize, level: usize) -> usize {
     let bits_for_level = match level {
-        0 | 1 | 2 => 4,
+        0..MAX_PAGE_TABLE_LEVELS => 4,
         _ => panic!("level out of range for software emulation (0-2)"),
     };
     let shift = 4 + (level * bits_for_level);
     ((address >> shift) & ((1 << bits_for_level) - 1)) as usize
 }
 
+/// Returns the number of bytes mapped by a single leaf entry at the specified level.
+///
+/// For software emulation, this is 16 bytes at level 0, 256 bytes at level 1 and 4 KiB at
+/// level 2.
+#[inline]
+pub const fn level_size(level: usize) -> usize {
+    PAGE_SIZE << (4 * level)
+}
+
+/// Invalidates the TLB entry for the page containing `addr`.
+///
+/// Software emulation has no TLB, so this is a no-op.
+#[inline]
+pub fn flush_tlb_page(_addr: usize) {}
+
+/// Invalidates all TLB entries.
+///
+/// Software emulation has no TLB, so this is a no-op.
+#[inline]
+pub fn flush_tlb_all() {}
+
 /// Validates a physical address for software emulation.
 ///
 /// Physical addresses must fit within 16 bits.
@@ -57,46 +124,53 @@ pub const fn validate_physical(addr: usize) -> bool {
 
 /// Validates a virtual address for software emulation.
 ///
-/// Virtual addresses must be canonical (bits 16-63 must be sign-extended from bit 15).
+/// Virtual addresses must be canonical: the bits above the top bit of the address width (bit 15,
+/// or bit 11 with 2-level paging) must be copies of it.
 #[inline]
-pub const fn validate_virtual(addr: usize) -> bool {
-    let canonical = if (addr & 0x8000) != 0 {
-        addr | 0xFFFF_FFFF_FFFF_0000
-    } else {
-        addr & 0xFFFF
-    };
-    canonical == addr
+pub fn validate_virtual(addr: usize) -> bool {
+    canonicalize_virtual(addr) == addr
 }
 
 /// Canonicalizes a virtual address for software emulation.
 ///
-/// This sign-extends bit 15 to bits 16-63.
+/// This sign-extends the top bit of the address width (bit 15, or bit 11 with 2-level paging)
+/// to the bits above it.
 #[inline]
-pub const fn canonicalize_virtual(addr: usize) -> usize {
-    if (addr & 0x8000) != 0 {
-        addr | 0xFFFF_FFFF_FFFF_0000
-    } else {
-        addr & 0xFFFF
-    }
+pub fn canonicalize_virtual(addr: usize) -> usize {
+    let unused = usize::BITS as usize - virtual_bits();
+    (((addr << unused) as isize) >> unused) as usize
 }
 
 /// Emulated memory for software simulation.
 ///
 /// This provides a simulated physical memory space for testing page table operations
-/// without requiring actual hardware or virtual memory support from the host OS.
+/// without requiring actual hardware or virtual memory support from the host OS. Accesses can
+/// also be made through page tables with an emulated MMU; see [`read`](Self::read) and
+/// [`write`](Self::write).
+///
+/// The memory can be [shared](Self::share) with other threads, so multi-threaded tests can
+/// install translators over the same physical memory on each of them.
 pub struct EmulatedMemory {
     /// The underlying memory buffer.
-    memory: Vec<u8>,
+    memory: Arc<[u8]>,
     /// Next allocation offset (simple bump allocator).
-    next_alloc: core::sync::atomic::AtomicUsize,
+    next_alloc: Arc<core::sync::atomic::AtomicUsize>,
 }
 
 impl EmulatedMemory {
     /// Creates a new emulated memory region of the specified size.
     pub fn new(size: usize) -> Self {
         Self {
-            memory: alloc::vec![0u8; size],
-            next_alloc: core::sync::atomic::AtomicUsize::new(0),
+            memory: alloc::vec![0u8; size].into(),
+            next_alloc: Arc::new(core::sync::atomic::AtomicUsize::new(0)),
+        }
+    }
+
+    /// Returns another handle to the same memory.
+    pub fn share(&self) -> Self {
+        Self {
+            memory: Arc::clone(&self.memory),
+            next_alloc: Arc::clone(&self.next_alloc),
         }
     }
 
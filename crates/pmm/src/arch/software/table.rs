@@ -1,8 +1,6 @@
 //! Page table structure for software emulation.
 
-use alloc::boxed::Box;
-
-use crate::{PhysicalAddress, VirtualAddress};
+use crate::PhysicalAddress;
 
 use super::entry::PageEntry;
 
@@ -23,9 +21,13 @@ const ENTRY_COUNT: usize = 16;
 /// - Bits 4-7: Level 0 index (PT)
 /// - Bits 8-11: Level 1 index (PD)
 /// - Bits 12-15: Level 2 index (PDP/root)
+///
+/// Like the x86_64 table, this is a transparent wrapper around its entries, so a table placed
+/// in emulated memory is entirely contained in it.
+#[repr(transparent)]
 pub struct PageTable {
     /// The entries in this page table.
-    entries: Box<[PageEntry; ENTRY_COUNT]>,
+    entries: [PageEntry; ENTRY_COUNT],
 }
 
 impl PageTable {
@@ -34,7 +36,7 @@ impl PageTable {
     /// All entries are initialized to zero (not present).
     pub fn new() -> Self {
         Self {
-            entries: Box::new([PageEntry::default(); ENTRY_COUNT]),
+            entries: [PageEntry::default(); ENTRY_COUNT],
         }
     }
 
@@ -66,12 +68,12 @@ impl PageTable {
     /// This is the address that would be stored in a parent page table entry
     /// or used as the root table address.
     pub fn physical_address(&self) -> PhysicalAddress {
-        // In software emulation, we treat the pointer to the entries array as the address.
-        // We need to translate this virtual address to a physical address.
-        let ptr = self.entries.as_ptr() as *const u8;
-        let virt_addr = VirtualAddress::new(ptr as usize);
+        // In software emulation, the table lives in the host buffer backing emulated memory.
+        // Host pointers aren't canonical emulated virtual addresses, so translate the raw
+        // pointer directly.
+        let ptr = self.entries.as_ptr() as usize;
         let translator = crate::address::AddressTranslator::current();
-        PhysicalAddress::new(translator.virt_to_phys(virt_addr.as_usize()))
+        PhysicalAddress::new(translator.virt_to_phys(ptr))
     }
 
     /// Activates this page table by setting it as the current root table.
@@ -3,6 +3,7 @@
 use crate::PhysicalAddress;
 
 use super::flags::PageFlags;
+use crate::arch::PageFlagBits;
 
 /// A single page table entry for x86_64.
 ///
@@ -23,6 +24,18 @@ impl PageEntry {
     /// Bit indicating this is a huge page (2MB or 1GB).
     const HUGE_PAGE_BIT: usize = 1 << 7;
 
+    /// Owned table bit (bit 9, one of the bits available to software).
+    ///
+    /// Marks entries pointing at page tables allocated by `PageDirectory`, which may be freed
+    /// once empty. Tables set up by the bootloader never carry this bit.
+    const OWNED_TABLE_BIT: usize = 1 << 9;
+
+    /// PAT bit for 4 KiB pages (bit 7), which shares its position with the huge page bit.
+    const PAT_BIT: usize = 1 << 7;
+
+    /// PAT bit for huge pages (bit 12), which overlaps the low address bits of a page table.
+    const HUGE_PAT_BIT: usize = 1 << 12;
+
     /// Creates a new page table entry.
     ///
     /// The physical address must be page-aligned (lowest 12 bits must be zero).
@@ -37,32 +50,105 @@ impl PageEntry {
         Self(addr_bits | flag_bits)
     }
 
-    /// Returns the physical address stored in this entry.
+    /// Creates a new leaf entry mapping a huge page at level 1 (2MB) or level 2 (1GB).
     ///
-    /// Returns None if the entry is not present.
+    /// The physical address must be aligned to the size of the page mapped at that level.
+    ///
+    /// The PAT bit of `flags` is moved to bit 12, where huge pages keep it.
+    pub fn new_huge(address: PhysicalAddress, flags: PageFlags) -> Self {
+        let mut entry = Self::new(address, flags).0;
+        if entry & Self::PAT_BIT != 0 {
+            entry = (entry & !Self::PAT_BIT) | Self::HUGE_PAT_BIT;
+        }
+        Self(entry | Self::HUGE_PAGE_BIT)
+    }
+
+    /// Returns the physical address stored in this entry, read as an entry above level 0.
+    ///
+    /// Returns None if the entry is not present. Use [`address_at`](Self::address_at) for
+    /// entries that may be at level 0, where bit 7 is the PAT bit rather than the huge page bit.
     pub fn address(self) -> Option<PhysicalAddress> {
-        if self.is_present() {
+        if self.is_leaf() {
+            Some(PhysicalAddress::new(
+                self.0 & Self::ADDRESS_MASK & !Self::HUGE_PAT_BIT,
+            ))
+        } else if self.is_present() {
             Some(PhysicalAddress::new(self.0 & Self::ADDRESS_MASK))
         } else {
             None
         }
     }
 
-    /// Returns the flags for this entry.
+    /// Returns the physical address stored in this entry, which lives at `level`.
+    ///
+    /// Returns None if the entry is not present.
+    pub fn address_at(self, level: usize) -> Option<PhysicalAddress> {
+        if level == 0 {
+            self.is_present()
+                .then(|| PhysicalAddress::new(self.0 & Self::ADDRESS_MASK))
+        } else {
+            self.address()
+        }
+    }
+
+    /// Returns the flags for this entry, read as an entry above level 0.
+    ///
+    /// The huge page bit is not included; it is reported by [`is_leaf`](Self::is_leaf) so that
+    /// flags compare equal regardless of the size of the page they map. The PAT bit of a huge
+    /// page is reported in bit 7, where [`PageFlags`] keeps it. Use
+    /// [`flags_at`](Self::flags_at) for entries that may be at level 0.
     pub fn flags(self) -> PageFlags {
-        PageFlags::from(self.0 & Self::FLAGS_MASK)
+        let flag_bits = self.0 & Self::FLAGS_MASK & !Self::HUGE_PAGE_BIT;
+        if self.is_leaf() && self.0 & Self::HUGE_PAT_BIT != 0 {
+            PageFlags::from(flag_bits | Self::PAT_BIT)
+        } else {
+            PageFlags::from(flag_bits)
+        }
+    }
+
+    /// Returns the flags for this entry, which lives at `level`.
+    pub fn flags_at(self, level: usize) -> PageFlags {
+        if level == 0 {
+            PageFlags::from(self.0 & Self::FLAGS_MASK)
+        } else {
+            self.flags()
+        }
     }
 
-    /// Sets the flags for this entry, preserving the address.
+    /// Sets the flags for this entry, read as an entry above level 0, preserving the address.
+    ///
+    /// The PAT bit of `flags` is moved to bit 12 if the entry maps a huge page, and dropped if
+    /// it points at a page table. Use [`set_flags_at`](Self::set_flags_at) for entries that
+    /// may be at level 0.
     pub fn set_flags(&mut self, flags: PageFlags) {
-        let addr_bits = self.0 & Self::ADDRESS_MASK;
-        let flag_bits = flags.as_usize() & Self::FLAGS_MASK;
-        self.0 = addr_bits | flag_bits;
+        let flag_bits = flags.as_usize() & Self::FLAGS_MASK & !Self::PAT_BIT;
+        let kept = self.0 & (Self::HUGE_PAGE_BIT | Self::OWNED_TABLE_BIT);
+        if self.is_leaf() {
+            let pat = if flags.as_usize() & Self::PAT_BIT != 0 {
+                Self::HUGE_PAT_BIT
+            } else {
+                0
+            };
+            self.0 = (self.0 & Self::ADDRESS_MASK & !Self::HUGE_PAT_BIT) | pat | flag_bits | kept;
+        } else {
+            self.0 = (self.0 & Self::ADDRESS_MASK) | flag_bits | kept;
+        }
+    }
+
+    /// Sets the flags for this entry, which lives at `level`, preserving the address.
+    pub fn set_flags_at(&mut self, level: usize, flags: PageFlags) {
+        if level == 0 {
+            let addr_bits = self.0 & Self::ADDRESS_MASK;
+            let flag_bits = flags.as_usize() & Self::FLAGS_MASK;
+            self.0 = addr_bits | flag_bits | (self.0 & Self::OWNED_TABLE_BIT);
+        } else {
+            self.set_flags(flags);
+        }
     }
 
     /// Returns whether this entry is present (valid).
     pub fn is_present(self) -> bool {
-        self.flags().is_present()
+        self.flags_at(0).is_present()
     }
 
     /// Returns whether this entry is a leaf entry (maps a page directly).
@@ -74,6 +160,16 @@ impl PageEntry {
         self.is_present() && (self.0 & Self::HUGE_PAGE_BIT != 0)
     }
 
+    /// Returns whether this entry points at a page table allocated by `PageDirectory`.
+    pub fn is_owned_table(self) -> bool {
+        self.is_present() && !self.is_leaf() && (self.0 & Self::OWNED_TABLE_BIT != 0)
+    }
+
+    /// Marks this entry as pointing at a page table allocated by `PageDirectory`.
+    pub fn set_owned_table(&mut self) {
+        self.0 |= Self::OWNED_TABLE_BIT;
+    }
+
     /// Clears this entry (sets it to zero).
     pub fn clear(&mut self) {
         self.0 = 0;
@@ -96,3 +192,104 @@ impl Default for PageEntry {
         Self(0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    //! Host tests validate physical addresses against the software model, so the addresses
+    //! used here stay below 64 KiB even for huge pages.
+
+    use super::super::flags::{MemoryType, PAT_LAYOUT};
+    use super::*;
+
+    fn flags(memory_type: MemoryType) -> PageFlags {
+        let mut flags = PageFlags::empty();
+        flags.set_present(true);
+        flags.set_writable(true);
+        flags.set_memory_type(memory_type);
+        flags
+    }
+
+    const MEMORY_TYPES: [MemoryType; 5] = [
+        MemoryType::WriteBack,
+        MemoryType::WriteCombining,
+        MemoryType::WriteThrough,
+        MemoryType::UncachedMinus,
+        MemoryType::Uncached,
+    ];
+
+    #[test]
+    fn memory_types_select_pat_entries_with_matching_types() {
+        // PAT memory type encodings, as programmed into IA32_PAT.
+        let expected = |memory_type| match memory_type {
+            MemoryType::WriteBack => 0x06,
+            MemoryType::WriteCombining => 0x01,
+            MemoryType::WriteThrough => 0x04,
+            MemoryType::UncachedMinus => 0x07,
+            MemoryType::Uncached => 0x00,
+        };
+
+        for memory_type in MEMORY_TYPES {
+            let bits = PageEntry::new(PhysicalAddress::new(0x1000), flags(memory_type)).0;
+            let index = ((bits >> 7) & 1) << 2 | ((bits >> 4) & 1) << 1 | ((bits >> 3) & 1);
+            assert_eq!(
+                (PAT_LAYOUT >> (index * 8)) & 0xFF,
+                expected(memory_type),
+                "{memory_type:?}"
+            );
+            assert_eq!(flags(memory_type).memory_type(), memory_type);
+        }
+    }
+
+    #[test]
+    fn small_pages_keep_the_pat_bit_in_bit_7() {
+        let phys = PhysicalAddress::new(0x7000);
+        let entry = PageEntry::new(phys, flags(MemoryType::WriteCombining));
+
+        assert_eq!(entry.0 & PageEntry::PAT_BIT, PageEntry::PAT_BIT);
+        assert_eq!(entry.address_at(0), Some(phys));
+        assert_eq!(entry.flags_at(0).memory_type(), MemoryType::WriteCombining);
+    }
+
+    #[test]
+    fn huge_pages_keep_the_pat_bit_in_bit_12() {
+        let phys = PhysicalAddress::new(0xe000);
+        let entry = PageEntry::new_huge(phys, flags(MemoryType::WriteCombining));
+
+        assert!(entry.is_leaf());
+        assert_eq!(entry.0 & PageEntry::HUGE_PAT_BIT, PageEntry::HUGE_PAT_BIT);
+        assert_eq!(entry.address(), Some(phys));
+        assert_eq!(entry.flags(), flags(MemoryType::WriteCombining));
+    }
+
+    #[test]
+    fn set_flags_moves_the_pat_bit_for_each_level() {
+        let mut small = PageEntry::new(PhysicalAddress::new(0x7000), flags(MemoryType::WriteBack));
+        small.set_flags_at(0, flags(MemoryType::WriteCombining));
+        assert_eq!(small.flags_at(0), flags(MemoryType::WriteCombining));
+        small.set_flags_at(0, flags(MemoryType::WriteBack));
+        assert_eq!(small.0 & PageEntry::PAT_BIT, 0);
+
+        let phys = PhysicalAddress::new(0xe000);
+        let mut huge = PageEntry::new_huge(phys, flags(MemoryType::WriteBack));
+        huge.set_flags_at(2, flags(MemoryType::WriteCombining));
+        assert!(huge.is_leaf());
+        assert_eq!(huge.flags_at(2), flags(MemoryType::WriteCombining));
+        huge.set_flags_at(2, flags(MemoryType::Uncached));
+        assert!(huge.is_leaf());
+        assert_eq!(huge.address_at(2), Some(phys));
+        assert_eq!(huge.flags_at(2), flags(MemoryType::Uncached));
+    }
+
+    #[test]
+    fn set_flags_never_turns_a_table_into_a_huge_page() {
+        let table = PhysicalAddress::new(0x1000);
+        let mut entry = PageEntry::new(table, flags(MemoryType::WriteBack));
+        entry.set_owned_table();
+
+        entry.set_flags(flags(MemoryType::WriteCombining));
+
+        assert!(!entry.is_leaf());
+        assert!(entry.is_owned_table());
+        assert_eq!(entry.address(), Some(table));
+    }
+}
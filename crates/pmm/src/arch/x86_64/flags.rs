@@ -1,5 +1,44 @@
 //! Page table entry flags for x86_64 architecture.
 
+use x86_64::structures::paging::PageTableFlags;
+
+use crate::arch::PageFlagBits;
+
+/// The Page Attribute Table layout programmed into `IA32_PAT` at boot.
+///
+/// Entries 0-3 keep their power-on values and entries 0-5 match the layout Limine sets up, so
+/// mappings made before the kernel takes over keep their memory types:
+///
+/// | Entry | PAT | PCD | PWT | Memory type |
+/// |-------|-----|-----|-----|-------------|
+/// | 0     | 0   | 0   | 0   | WB          |
+/// | 1     | 0   | 0   | 1   | WT          |
+/// | 2     | 0   | 1   | 0   | UC-         |
+/// | 3     | 0   | 1   | 1   | UC          |
+/// | 4     | 1   | 0   | 0   | WP          |
+/// | 5     | 1   | 0   | 1   | WC          |
+/// | 6     | 1   | 1   | 0   | UC-         |
+/// | 7     | 1   | 1   | 1   | UC          |
+pub const PAT_LAYOUT: u64 = 0x0007_0105_0007_0406;
+
+/// The caching behaviour of a mapping, selected through the Page Attribute Table.
+///
+/// The encodings assume `IA32_PAT` holds [`PAT_LAYOUT`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemoryType {
+    /// Write-back: fully cached. Used for ordinary memory.
+    #[default]
+    WriteBack,
+    /// Write-combining: uncached, but writes are buffered and merged. Used for framebuffers.
+    WriteCombining,
+    /// Write-through: reads are cached, writes go straight to memory.
+    WriteThrough,
+    /// Uncached, but can be overridden to write-combining by the MTRRs.
+    UncachedMinus,
+    /// Strongly uncached. Used for MMIO registers.
+    Uncached,
+}
+
 /// Page table entry flags for x86_64.
 ///
 /// This wraps the x86_64 crate's page table entry flags, providing a minimal
@@ -9,14 +48,20 @@ pub struct PageFlags(x86_64::structures::paging::PageTableFlags);
 
 impl From<usize> for PageFlags {
     fn from(value: usize) -> Self {
-        Self(x86_64::structures::paging::PageTableFlags::from_bits_truncate(value as u64))
+        Self(PageTableFlags::from_bits_truncate(value as u64))
     }
 }
 
 impl PageFlags {
+    /// The PAT bit of a 4 KiB page (bit 7).
+    ///
+    /// Huge page entries use bit 7 as the page size bit and keep the PAT bit in bit 12 instead;
+    /// [`PageEntry`](super::PageEntry) moves it between the two positions.
+    const PAT: PageTableFlags = PageTableFlags::HUGE_PAGE;
+
     /// Creates empty page flags (page not present).
     pub const fn empty() -> Self {
-        Self(x86_64::structures::paging::PageTableFlags::empty())
+        Self(PageTableFlags::empty())
     }
 
     /// Returns the raw usize value of these flags.
@@ -24,43 +69,117 @@ impl PageFlags {
         self.0.bits() as usize
     }
 
-    /// Returns whether the present bit is set.
-    pub fn is_present(self) -> bool {
-        self.0
-            .contains(x86_64::structures::paging::PageTableFlags::PRESENT)
+    /// Returns the memory type selected by the PAT, PCD and PWT bits.
+    ///
+    /// The write-protect entry of [`PAT_LAYOUT`] is reported as [`MemoryType::WriteBack`], since
+    /// no `MemoryType` selects it.
+    pub fn memory_type(self) -> MemoryType {
+        let pat = self.0.contains(Self::PAT);
+        let pcd = self.0.contains(PageTableFlags::NO_CACHE);
+        let pwt = self.0.contains(PageTableFlags::WRITE_THROUGH);
+        match (pat, pcd, pwt) {
+            (false, false, true) => MemoryType::WriteThrough,
+            (_, true, false) => MemoryType::UncachedMinus,
+            (_, true, true) => MemoryType::Uncached,
+            (true, false, true) => MemoryType::WriteCombining,
+            (_, false, false) => MemoryType::WriteBack,
+        }
     }
 
-    /// Sets or clears the present bit.
-    pub fn set_present(&mut self, present: bool) {
-        self.0
-            .set(x86_64::structures::paging::PageTableFlags::PRESENT, present);
+    /// Sets the PAT, PCD and PWT bits to select the given memory type.
+    pub fn set_memory_type(&mut self, memory_type: MemoryType) {
+        let (pat, pcd, pwt) = match memory_type {
+            MemoryType::WriteBack => (false, false, false),
+            MemoryType::WriteThrough => (false, false, true),
+            MemoryType::UncachedMinus => (false, true, false),
+            MemoryType::Uncached => (false, true, true),
+            MemoryType::WriteCombining => (true, false, true),
+        };
+        self.0.set(Self::PAT, pat);
+        self.0.set(PageTableFlags::NO_CACHE, pcd);
+        self.0.set(PageTableFlags::WRITE_THROUGH, pwt);
+    }
+}
+
+impl PageFlagBits for PageFlags {
+    fn is_present(self) -> bool {
+        self.0.contains(PageTableFlags::PRESENT)
+    }
+
+    fn set_present(&mut self, present: bool) {
+        self.0.set(PageTableFlags::PRESENT, present);
     }
 
-    /// Sets or clears the writable bit.
-    pub fn set_writable(&mut self, writable: bool) {
-        self.0
-            .set(x86_64::structures::paging::PageTableFlags::WRITABLE, writable);
+    fn is_writable(self) -> bool {
+        self.0.contains(PageTableFlags::WRITABLE)
+    }
+
+    fn set_writable(&mut self, writable: bool) {
+        self.0.set(PageTableFlags::WRITABLE, writable);
+    }
+
+    fn is_user(self) -> bool {
+        self.0.contains(PageTableFlags::USER_ACCESSIBLE)
+    }
+
+    fn set_user(&mut self, user: bool) {
+        self.0.set(PageTableFlags::USER_ACCESSIBLE, user);
+    }
+
+    fn is_global(self) -> bool {
+        self.0.contains(PageTableFlags::GLOBAL)
+    }
+
+    fn set_global(&mut self, global: bool) {
+        self.0.set(PageTableFlags::GLOBAL, global);
+    }
+
+    fn is_accessed(self) -> bool {
+        self.0.contains(PageTableFlags::ACCESSED)
+    }
+
+    fn set_accessed(&mut self, accessed: bool) {
+        self.0.set(PageTableFlags::ACCESSED, accessed);
+    }
+
+    fn is_dirty(self) -> bool {
+        self.0.contains(PageTableFlags::DIRTY)
+    }
+
+    fn set_dirty(&mut self, dirty: bool) {
+        self.0.set(PageTableFlags::DIRTY, dirty);
+    }
+
+    fn is_write_through(self) -> bool {
+        self.0.contains(PageTableFlags::WRITE_THROUGH)
     }
 
     /// Sets or clears the write-through bit (PWT).
-    pub fn set_write_through(&mut self, v: bool) {
-        self.0
-            .set(x86_64::structures::paging::PageTableFlags::WRITE_THROUGH, v);
+    ///
+    /// Prefer [`set_memory_type`](PageFlags::set_memory_type), which also accounts for the PAT
+    /// bit.
+    fn set_write_through(&mut self, write_through: bool) {
+        self.0.set(PageTableFlags::WRITE_THROUGH, write_through);
+    }
+
+    fn is_cache_disable(self) -> bool {
+        self.0.contains(PageTableFlags::NO_CACHE)
     }
 
     /// Sets or clears the cache-disable bit (PCD).
     ///
-    /// When set, the page is treated as UC- (Uncacheable Minus), preventing CPU caching.
-    /// Use this for MMIO regions.
-    pub fn set_cache_disable(&mut self, v: bool) {
-        self.0
-            .set(x86_64::structures::paging::PageTableFlags::NO_CACHE, v);
+    /// Prefer [`set_memory_type`](PageFlags::set_memory_type), which also accounts for the PAT
+    /// bit.
+    fn set_cache_disable(&mut self, cache_disable: bool) {
+        self.0.set(PageTableFlags::NO_CACHE, cache_disable);
+    }
+
+    fn is_no_execute(self) -> bool {
+        self.0.contains(PageTableFlags::NO_EXECUTE)
     }
 
-    /// Sets or clears the no-execute bit (NX/XD).
-    pub fn set_no_execute(&mut self, v: bool) {
-        self.0
-            .set(x86_64::structures::paging::PageTableFlags::NO_EXECUTE, v);
+    fn set_no_execute(&mut self, no_execute: bool) {
+        self.0.set(PageTableFlags::NO_EXECUTE, no_execute);
     }
 }
 
@@ -4,43 +4,108 @@
 //! including address validation, page table configuration, and low-level
 //! page table primitives.
 
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 mod entry;
 mod flags;
 mod table;
 
 pub use entry::PageEntry;
 pub use flags::PageFlags;
+#[cfg(not(any(test, feature = "software-emulation")))]
+pub use flags::{MemoryType, PAT_LAYOUT};
 pub use table::PageTable;
 
 /// Maximum number of bits in a physical address on x86_64.
 /// This is typically 52 bits on modern CPUs, but we use 48 as a conservative default.
 pub const MAX_PHYSICAL_BITS: usize = 48;
 
-/// Maximum number of bits in a virtual address on x86_64 with 4-level paging.
-pub const MAX_VIRTUAL_BITS: usize = 48;
+/// Maximum number of bits in a virtual address on x86_64, reached with 5-level paging.
+pub const MAX_VIRTUAL_BITS: usize = 57;
 
 /// Default page size in bytes (4 KiB).
 pub const PAGE_SIZE: usize = 4096;
 
-/// Number of page table levels in x86_64 (4-level paging).
-/// This can be 5 with 5-level paging, but 4 is the standard.
-pub const PAGE_TABLE_LEVELS: usize = 4;
+/// Maximum number of page table levels in x86_64 (5-level paging).
+pub const MAX_PAGE_TABLE_LEVELS: usize = 5;
+
+/// Number of page table levels in use, chosen by [`set_page_table_levels`].
+static PAGE_TABLE_LEVELS: AtomicUsize = AtomicUsize::new(4);
+
+/// Returns the number of page table levels in use: 4, or 5 with LA57 enabled.
+#[inline]
+pub fn page_table_levels() -> usize {
+    PAGE_TABLE_LEVELS.load(Ordering::Relaxed)
+}
+
+/// Selects 4-level or 5-level paging, matching `CR4.LA57`.
+///
+/// This must be called before any virtual address is created or any page table is walked,
+/// since both depend on the level count.
+///
+/// # Panics
+/// Panics if `levels` is not 4 or 5.
+pub fn set_page_table_levels(levels: usize) {
+    assert!(
+        levels == 4 || levels == 5,
+        "x86_64 supports 4-level or 5-level paging"
+    );
+    PAGE_TABLE_LEVELS.store(levels, Ordering::Relaxed);
+}
+
+/// Returns the number of bits in a canonical virtual address: 48, or 57 with 5-level paging.
+#[inline]
+pub fn virtual_bits() -> usize {
+    12 + 9 * page_table_levels()
+}
+
+/// Upper bound (exclusive) of the DMA zone: memory reachable by legacy ISA DMA (16 MiB).
+#[cfg(not(any(test, feature = "software-emulation")))]
+pub const ZONE_DMA_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Upper bound (exclusive) of the DMA32 zone: memory reachable by 32-bit DMA devices (4 GiB).
+#[cfg(not(any(test, feature = "software-emulation")))]
+pub const ZONE_DMA32_LIMIT: usize = 4 * 1024 * 1024 * 1024;
 
 /// Returns the page table index for a given virtual address at the specified level.
 ///
 /// For x86_64, each level uses 9 bits, with level 0 being the page table (PT),
 /// level 1 being the page directory (PD), level 2 being the page directory pointer
-/// table (PDPT), and level 3 being the page map level 4 (PML4).
+/// table (PDPT), level 3 being the page map level 4 (PML4) and level 4 being the page map
+/// level 5 (PML5) with 5-level paging.
 #[inline]
 pub const fn page_index(address: usize, level: usize) -> usize {
     let bits_for_level = match level {
-        0 | 1 | 2 | 3 => 9,
+        0..MAX_PAGE_TABLE_LEVELS => 9,
         _ => panic!("level out of range for x86_64 page table levels"),
     };
     let shift = 12 + (level * bits_for_level);
     ((address >> shift) & ((1 << bits_for_level) - 1)) as usize
 }
 
+/// Returns the number of bytes mapped by a single leaf entry at the specified level.
+///
+/// For x86_64, this is 4 KiB at level 0, 2 MiB at level 1 and 1 GiB at level 2.
+#[cfg(not(any(test, feature = "software-emulation")))]
+#[inline]
+pub const fn level_size(level: usize) -> usize {
+    PAGE_SIZE << (9 * level)
+}
+
+/// Invalidates the TLB entry for the page containing `addr` on the current CPU.
+#[cfg(not(any(test, feature = "software-emulation")))]
+#[inline]
+pub fn flush_tlb_page(addr: usize) {
+    x86_64::instructions::tlb::flush(x86_64::VirtAddr::new(addr as u64));
+}
+
+/// Invalidates all non-global TLB entries on the current CPU.
+#[cfg(not(any(test, feature = "software-emulation")))]
+#[inline]
+pub fn flush_tlb_all() {
+    x86_64::instructions::tlb::flush_all();
+}
+
 /// Validates a physical address for x86_64.
 ///
 /// Physical addresses must not exceed the maximum physical address width.
@@ -52,25 +117,44 @@ pub const fn validate_physical(addr: usize) -> bool {
 
 /// Validates a virtual address for x86_64.
 ///
-/// Virtual addresses must be canonical (bits 47-63 must be sign-extended from bit 47).
+/// Virtual addresses must be canonical: the bits above the top bit of the address width (bit 47,
+/// or bit 56 with 5-level paging) must be copies of it.
 #[inline]
-pub const fn validate_virtual(addr: usize) -> bool {
-    let canonical = if (addr & (1 << 47)) != 0 {
-        addr | 0xFFFF_0000_0000_0000
-    } else {
-        addr & 0x0000_FFFF_FFFF_FFFF
-    };
-    canonical == addr
+pub fn validate_virtual(addr: usize) -> bool {
+    canonicalize_virtual(addr) == addr
 }
 
 /// Canonicalizes a virtual address for x86_64.
 ///
-/// This sign-extends bit 47 to bits 48-63.
+/// This sign-extends the top bit of the address width (bit 47, or bit 56 with 5-level paging)
+/// to the bits above it.
 #[inline]
-pub const fn canonicalize_virtual(addr: usize) -> usize {
-    if (addr & (1 << 47)) != 0 {
-        addr | 0xFFFF_0000_0000_0000
-    } else {
-        addr & 0x0000_FFFF_FFFF_FFFF
+pub fn canonicalize_virtual(addr: usize) -> usize {
+    let unused = usize::BITS as usize - virtual_bits();
+    (((addr << unused) as isize) >> unused) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn five_level_paging_widens_canonical_addresses() {
+        assert_eq!(virtual_bits(), 48);
+        assert!(validate_virtual(0xffff_8000_0000_0000));
+        assert!(!validate_virtual(0xff00_0000_0000_0000));
+
+        set_page_table_levels(5);
+        assert_eq!(virtual_bits(), 57);
+        assert!(validate_virtual(0xff00_0000_0000_0000));
+        assert!(!validate_virtual(0x0100_0000_0000_0000));
+        assert_eq!(
+            canonicalize_virtual(0x0100_0000_0000_0000),
+            0xff00_0000_0000_0000
+        );
+        assert_eq!(page_index(0xff00_0000_0000_0000, 4), 0x100);
+
+        set_page_table_levels(4);
+        assert!(!validate_virtual(0xff00_0000_0000_0000));
     }
 }
@@ -1,8 +1,14 @@
 //! Block-based physical memory allocator.
 //!
 //! This module provides a simple block allocator inspired by Linux's memblock allocator.
-//! It maintains static arrays of memory regions to track both available and reserved
-//! physical memory, allowing early kernel initialization without requiring dynamic allocation.
+//! It starts with static arrays of memory regions to track both available and reserved
+//! physical memory, allowing early kernel initialization without requiring dynamic allocation,
+//! and grows them out of the memory it manages once they fill up.
+//!
+//! With the `free-poisoning` feature, memory is filled with [`FREE_POISON`](crate::FREE_POISON)
+//! when it is allocated and when it is freed. Unlike the buddy allocator, the block allocator
+//! cannot check the pattern on allocation: memory it has never handed out holds whatever the
+//! firmware left there.
 
 use core::alloc::{AllocError as CoreAllocError, Allocator, Layout};
 use core::ptr::NonNull;
@@ -10,9 +16,16 @@ use core::ptr::NonNull;
 use crate::arch::PAGE_SIZE;
 use crate::{PhysicalAddress, VirtualAddress};
 
-/// Maximum number of memory regions that can be tracked.
+/// Number of memory regions each list can track before it is first grown.
 const MAX_REGIONS: usize = 128;
 
+/// Number of free slots kept in each region array.
+///
+/// Adding or subtracting a region needs at most one free slot, and growing the memory array
+/// needs up to two in the reserved array: one to reserve the new storage, and one in case
+/// releasing the old storage splits a reserved region.
+const HEADROOM: usize = 2;
+
 /// Errors that can occur during memory allocation operations.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AllocError {
@@ -20,7 +33,7 @@ pub enum AllocError {
     OutOfMemory,
     /// The requested alignment is invalid (e.g., not a power of two).
     InvalidAlignment,
-    /// The region arrays are full and cannot track more regions.
+    /// The region arrays are full and no memory is available to grow them.
     RegionsFull,
     /// Memory region overlaps with existing region in an invalid way.
     RegionOverlap,
@@ -32,6 +45,103 @@ impl From<AllocError> for CoreAllocError {
     }
 }
 
+/// The end of the allowed range that a [`BlockAllocator`] allocation is taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AllocationDirection {
+    /// Take the lowest suitable address, leaving high memory free.
+    #[default]
+    BottomUp,
+    /// Take the highest suitable address, leaving low memory free for devices and firmware
+    /// interfaces that need it.
+    TopDown,
+}
+
+/// A request for memory from a [`BlockAllocator`].
+///
+/// By default, a request may be served from anywhere in physical memory, bottom-up. The
+/// bounds restrict where the allocation may lie, for example below 4 GiB for 32-bit DMA or
+/// below 1 MiB for an AP trampoline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocationRequest {
+    size: usize,
+    align: usize,
+    min_address: usize,
+    /// One past the highest address the allocation may cover.
+    limit: usize,
+    direction: AllocationDirection,
+}
+
+impl AllocationRequest {
+    /// Creates a request for `size` bytes aligned to `align`, which must be a power of two.
+    ///
+    /// The size is rounded up to whole pages when the request is served.
+    pub const fn new(size: usize, align: usize) -> Self {
+        Self {
+            size,
+            align,
+            min_address: 0,
+            limit: usize::MAX,
+            direction: AllocationDirection::BottomUp,
+        }
+    }
+
+    /// Returns this request restricted to memory starting at or above `min_address`.
+    pub const fn with_min_address(self, min_address: PhysicalAddress) -> Self {
+        Self {
+            min_address: min_address.as_usize(),
+            ..self
+        }
+    }
+
+    /// Returns this request restricted to memory lying entirely at or below `max_address`.
+    pub const fn with_max_address(self, max_address: PhysicalAddress) -> Self {
+        Self {
+            limit: max_address.as_usize().saturating_add(1),
+            ..self
+        }
+    }
+
+    /// Returns this request served from the given end of the allowed range.
+    pub const fn with_direction(self, direction: AllocationDirection) -> Self {
+        Self { direction, ..self }
+    }
+
+    /// Returns this request served from the highest suitable address.
+    pub const fn top_down(self) -> Self {
+        self.with_direction(AllocationDirection::TopDown)
+    }
+
+    /// Returns the number of bytes requested.
+    pub const fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the requested alignment.
+    pub const fn align(&self) -> usize {
+        self.align
+    }
+
+    /// Returns the direction the request is served from.
+    pub const fn direction(&self) -> AllocationDirection {
+        self.direction
+    }
+
+    /// Returns this request with its size rounded up to whole pages, or an error if it cannot
+    /// be served.
+    const fn page_aligned(self) -> Result<Self, AllocError> {
+        if self.size == 0 {
+            return Err(AllocError::OutOfMemory);
+        }
+        if !self.align.is_power_of_two() {
+            return Err(AllocError::InvalidAlignment);
+        }
+        Ok(Self {
+            size: self.size.next_multiple_of(PAGE_SIZE),
+            ..self
+        })
+    }
+}
+
 /// A contiguous range of physical memory.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct MemoryRegion {
@@ -99,18 +209,32 @@ impl MemoryRegion {
     }
 }
 
-/// Fixed-size array of memory regions with static allocation.
+/// Array of memory regions, sorted by base address.
+///
+/// The array starts out in fixed-size storage inside the allocator, so regions can be tracked
+/// before any memory can be allocated. Once it is nearly full, [`BlockAllocator`] moves it to
+/// a larger array allocated from its own memory.
 #[derive(Debug)]
 struct RegionArray {
-    regions: [Option<MemoryRegion>; MAX_REGIONS],
+    /// The storage used until the array first grows.
+    initial: [MemoryRegion; MAX_REGIONS],
+    /// Storage allocated by the block allocator, used instead of `initial` once the array
+    /// has grown.
+    grown: Option<NonNull<MemoryRegion>>,
+    capacity: usize,
     count: usize,
 }
 
+// SAFETY: The grown storage is owned by the array, like a `Box`, and only reached through it.
+unsafe impl Send for RegionArray {}
+
 impl RegionArray {
     /// Creates a new empty region array.
     const fn new() -> Self {
         Self {
-            regions: [None; MAX_REGIONS],
+            initial: [MemoryRegion::new(PhysicalAddress::new(0), 0); MAX_REGIONS],
+            grown: None,
+            capacity: MAX_REGIONS,
             count: 0,
         }
     }
@@ -127,12 +251,75 @@ impl RegionArray {
 
     /// Returns true if the array is full.
     const fn is_full(&self) -> bool {
-        self.count >= MAX_REGIONS
+        self.count >= self.capacity
+    }
+
+    /// Returns the number of regions the array can hold before it must grow.
+    const fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns every slot of the current storage, including the unused ones.
+    fn storage(&self) -> &[MemoryRegion] {
+        match self.grown {
+            // SAFETY: Grown storage holds `capacity` initialized regions; see `move_to`.
+            Some(storage) => unsafe {
+                core::slice::from_raw_parts(storage.as_ptr(), self.capacity)
+            },
+            None => &self.initial,
+        }
+    }
+
+    /// Returns every slot of the current storage, including the unused ones.
+    fn storage_mut(&mut self) -> &mut [MemoryRegion] {
+        match self.grown {
+            // SAFETY: As in `storage`.
+            Some(storage) => unsafe {
+                core::slice::from_raw_parts_mut(storage.as_ptr(), self.capacity)
+            },
+            None => &mut self.initial,
+        }
     }
 
     /// Returns an iterator over the regions.
-    fn iter(&self) -> impl Iterator<Item = &MemoryRegion> {
-        self.regions[..self.count].iter().filter_map(|r| r.as_ref())
+    fn iter(&self) -> impl DoubleEndedIterator<Item = &MemoryRegion> {
+        self.storage()[..self.count].iter()
+    }
+
+    /// Returns the physical memory holding the grown storage, if the array has grown.
+    fn grown_region(&self) -> Option<MemoryRegion> {
+        self.grown.map(|storage| {
+            let base =
+                PhysicalAddress::from_direct_mapped(VirtualAddress::from_ptr(storage.as_ptr()));
+            MemoryRegion::new(base, storage_size(self.capacity))
+        })
+    }
+
+    /// Moves the regions to `storage`, which holds `capacity` regions, and returns the
+    /// storage and capacity the array grew out of, if it was allocated by the block allocator.
+    ///
+    /// # Safety
+    ///
+    /// `storage` must be valid for reads and writes of `capacity` regions, suitably aligned,
+    /// and not used by anything else for as long as the array uses it.
+    unsafe fn move_to(
+        &mut self,
+        storage: NonNull<MemoryRegion>,
+        capacity: usize,
+    ) -> Option<(NonNull<MemoryRegion>, usize)> {
+        debug_assert!(capacity >= self.count, "region array cannot shrink");
+
+        // SAFETY: The caller guarantees `storage` is valid for `capacity` regions; zeroed
+        // bytes are an empty region.
+        unsafe {
+            core::ptr::write_bytes(storage.as_ptr(), 0, capacity);
+            core::ptr::copy_nonoverlapping(self.storage().as_ptr(), storage.as_ptr(), self.count);
+        }
+
+        let previous = self.grown.map(|grown| (grown, self.capacity));
+        self.grown = Some(storage);
+        self.capacity = capacity;
+        previous
     }
 
     /// Inserts a region at the specified index, shifting subsequent regions.
@@ -145,11 +332,10 @@ impl RegionArray {
         }
 
         // Shift regions to make space
-        for i in (index..self.count).rev() {
-            self.regions[i + 1] = self.regions[i];
-        }
-
-        self.regions[index] = Some(region);
+        let count = self.count;
+        let storage = self.storage_mut();
+        storage.copy_within(index..count, index + 1);
+        storage[index] = region;
         self.count += 1;
         Ok(())
     }
@@ -161,11 +347,8 @@ impl RegionArray {
         }
 
         // Shift regions to fill the gap
-        for i in index..self.count - 1 {
-            self.regions[i] = self.regions[i + 1];
-        }
-
-        self.regions[self.count - 1] = None;
+        let count = self.count;
+        self.storage_mut().copy_within(index + 1..count, index);
         self.count -= 1;
     }
 
@@ -222,7 +405,7 @@ impl RegionArray {
 
         let mut i = 0;
         while i < self.count {
-            let existing = self.regions[i].unwrap();
+            let existing = self.storage()[i];
 
             if !existing.overlaps(&region) {
                 i += 1;
@@ -263,7 +446,215 @@ impl RegionArray {
     }
 }
 
-/// A block-based physical memory allocator with static accounting space.
+/// The region arrays of a [`BlockAllocator`].
+#[derive(Debug)]
+struct Regions {
+    /// All memory regions in the system (usable + reserved).
+    memory: RegionArray,
+    /// Regions that are reserved or allocated.
+    reserved: RegionArray,
+}
+
+/// Selects one of the region arrays of a [`Regions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Array {
+    Memory,
+    Reserved,
+}
+
+impl Regions {
+    /// Returns the selected region array.
+    fn array(&mut self, array: Array) -> &mut RegionArray {
+        match array {
+            Array::Memory => &mut self.memory,
+            Array::Reserved => &mut self.reserved,
+        }
+    }
+
+    /// Adds a usable memory region.
+    fn add_memory(&mut self, region: MemoryRegion) -> Result<(), AllocError> {
+        self.make_room(Array::Memory);
+        self.memory.add(region)
+    }
+
+    /// Marks a region as reserved.
+    fn reserve(&mut self, region: MemoryRegion) -> Result<(), AllocError> {
+        self.make_room(Array::Reserved);
+        self.reserved.add(region)
+    }
+
+    /// Removes a region from the reserved list.
+    fn unreserve(&mut self, region: MemoryRegion) -> Result<(), AllocError> {
+        self.make_room(Array::Reserved);
+        self.reserved.subtract(region)
+    }
+
+    /// Finds and reserves memory for `request`, returning its physical address.
+    ///
+    /// The request's size must be a multiple of the page size.
+    fn allocate(&mut self, request: &AllocationRequest) -> Result<PhysicalAddress, AllocError> {
+        self.make_room(Array::Reserved);
+        let base = self.find_free(request).ok_or(AllocError::OutOfMemory)?;
+        self.reserved.add(MemoryRegion::new(base, request.size))?;
+        Ok(base)
+    }
+
+    /// Reserves `region`, which must be free memory.
+    fn allocate_at(&mut self, region: MemoryRegion) -> Result<(), AllocError> {
+        if !self
+            .memory
+            .iter()
+            .any(|memory| memory.contains(region.base(), region.size()))
+        {
+            return Err(AllocError::OutOfMemory);
+        }
+        if self
+            .reserved
+            .iter()
+            .any(|reserved| reserved.overlaps(&region))
+        {
+            return Err(AllocError::RegionOverlap);
+        }
+        self.reserve(region)
+    }
+
+    /// Finds a free range for `request`, without reserving it.
+    fn find_free(&self, request: &AllocationRequest) -> Option<PhysicalAddress> {
+        match request.direction {
+            AllocationDirection::BottomUp => self.find_free_bottom_up(request),
+            AllocationDirection::TopDown => self.find_free_top_down(request),
+        }
+    }
+
+    /// Finds the lowest free range for `request`.
+    fn find_free_bottom_up(&self, request: &AllocationRequest) -> Option<PhysicalAddress> {
+        let (size, align) = (request.size, request.align);
+        for mem_region in self.memory.iter() {
+            // Check each potential allocation within this memory region
+            let mut current = mem_region.base().as_usize().max(request.min_address);
+            let end = mem_region.end().as_usize().min(request.limit);
+
+            while current + size <= end {
+                // Align current address
+                let aligned_current = (current + align - 1) & !(align - 1);
+
+                if aligned_current + size > end {
+                    break;
+                }
+
+                let candidate = PhysicalAddress::new(aligned_current);
+                let candidate_region = MemoryRegion::new(candidate, size);
+
+                // Jump past the lowest reserved region in the way, if there is one
+                match self
+                    .reserved
+                    .iter()
+                    .find(|reserved| candidate_region.overlaps(reserved))
+                {
+                    Some(reserved) => current = reserved.end().as_usize(),
+                    None => return Some(candidate),
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Finds the highest free range for `request`.
+    fn find_free_top_down(&self, request: &AllocationRequest) -> Option<PhysicalAddress> {
+        let (size, align) = (request.size, request.align);
+        for mem_region in self.memory.iter().rev() {
+            let start = mem_region.base().as_usize().max(request.min_address);
+            let mut top = mem_region.end().as_usize().min(request.limit);
+
+            while top >= start + size {
+                let aligned_candidate = (top - size) & !(align - 1);
+                if aligned_candidate < start {
+                    break;
+                }
+
+                let candidate = PhysicalAddress::new(aligned_candidate);
+                let candidate_region = MemoryRegion::new(candidate, size);
+
+                // Drop below the highest reserved region in the way, if there is one
+                match self
+                    .reserved
+                    .iter()
+                    .rev()
+                    .find(|reserved| candidate_region.overlaps(reserved))
+                {
+                    Some(reserved) => top = reserved.base().as_usize(),
+                    None => return Some(candidate),
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Grows the selected array if fewer than [`HEADROOM`] slots are left.
+    ///
+    /// If no memory is available to grow into, the array is left as it is, and the operation
+    /// that needed the room fails with [`AllocError::RegionsFull`] once it runs out.
+    fn make_room(&mut self, array: Array) {
+        let regions = self.array(array);
+        if regions.len() + HEADROOM > regions.capacity() {
+            let _ = self.grow(array);
+        }
+    }
+
+    /// Moves the selected array to storage twice its size, allocated from free memory.
+    ///
+    /// Like Linux's `memblock_double_array`, the new storage is reserved, and the storage the
+    /// array grew out of is released unless it is the initial storage inside the allocator.
+    fn grow(&mut self, array: Array) -> Result<(), AllocError> {
+        if array == Array::Memory {
+            // The new storage is reserved below.
+            self.make_room(Array::Reserved);
+        }
+
+        let capacity = self.array(array).capacity() * 2;
+        let size = storage_size(capacity);
+        let base = self
+            .find_free(&AllocationRequest::new(size, PAGE_SIZE))
+            .ok_or(AllocError::RegionsFull)?;
+        let storage = NonNull::new(VirtualAddress::direct_mapped(base).as_mut_ptr())
+            .expect("direct-mapped address should not be null");
+
+        // SAFETY: The storage is free memory of at least `size` bytes, page-aligned, and is
+        // reserved below so nothing else will be allocated there.
+        let previous = unsafe { self.array(array).move_to(storage, capacity) };
+        self.reserved.add(MemoryRegion::new(base, size))?;
+
+        if let Some((previous, previous_capacity)) = previous {
+            let previous_base =
+                PhysicalAddress::from_direct_mapped(VirtualAddress::from_ptr(previous.as_ptr()));
+            self.reserved.subtract(MemoryRegion::new(
+                previous_base,
+                storage_size(previous_capacity),
+            ))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the page-aligned number of bytes needed to store `capacity` regions.
+const fn storage_size(capacity: usize) -> usize {
+    let size = capacity * core::mem::size_of::<MemoryRegion>();
+    (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+/// A range of memory reported by [`BlockAllocator::hand_off`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandOffRange {
+    /// Usable memory that the block allocator never handed out, or that has been freed.
+    Free(MemoryRegion),
+    /// Usable memory that is reserved or still allocated.
+    Allocated(MemoryRegion),
+}
+
+/// A block-based physical memory allocator.
 ///
 /// This allocator maintains two lists of memory regions:
 /// - Memory regions: all physical memory in the system
@@ -271,24 +662,31 @@ impl RegionArray {
 ///
 /// Free memory is implicitly defined as memory in the memory list but not in the reserved list.
 ///
+/// Both lists start out in 128-entry arrays inside the allocator. When either is
+/// nearly full, it is moved to an array twice the size allocated from the allocator itself,
+/// so fragmented memory maps and many early allocations don't run out of regions. The grown
+/// arrays are allocated like any other memory, so every reserved region should be registered
+/// before the usable memory around it can be allocated from.
+///
 /// # Thread Safety
 ///
 /// The allocator uses `spin::Mutex` for interior mutability to allow the `Allocator` trait
 /// (which requires `&self`) to modify internal state. This provides thread safety with minimal
 /// overhead. Contention should be minimal until secondary processors are launched.
 pub struct BlockAllocator {
-    /// All memory regions in the system (usable + reserved).
-    memory: spin::Mutex<RegionArray>,
-    /// Regions that are reserved or allocated.
-    reserved: spin::Mutex<RegionArray>,
+    /// The memory and reserved lists, locked together since growing either one allocates from
+    /// both.
+    regions: spin::Mutex<Regions>,
 }
 
 impl BlockAllocator {
     /// Creates a new empty block allocator.
     pub const fn new() -> Self {
         Self {
-            memory: spin::Mutex::new(RegionArray::new()),
-            reserved: spin::Mutex::new(RegionArray::new()),
+            regions: spin::Mutex::new(Regions {
+                memory: RegionArray::new(),
+                reserved: RegionArray::new(),
+            }),
         }
     }
 
@@ -314,7 +712,7 @@ impl BlockAllocator {
         }
 
         let region = MemoryRegion::new(aligned_base, aligned_size);
-        self.memory.lock().add(region)
+        self.regions.lock().add_memory(region)
     }
 
     /// Reserves a memory region, marking it as unavailable for allocation.
@@ -335,7 +733,7 @@ impl BlockAllocator {
         let aligned_size = aligned_end - aligned_base.as_usize();
 
         let region = MemoryRegion::new(aligned_base, aligned_size);
-        self.reserved.lock().add(region)
+        self.regions.lock().reserve(region)
     }
 
     /// Allocates physical memory from the available regions, returning a direct-mapped virtual address.
@@ -351,59 +749,57 @@ impl BlockAllocator {
         &mut self,
         size: usize,
         align: usize,
+    ) -> Result<VirtualAddress, AllocError> {
+        self.allocate_with(AllocationRequest::new(size, align))
+    }
+
+    /// Allocates physical memory as described by `request`, returning a direct-mapped virtual
+    /// address.
+    ///
+    /// Like [`allocate_raw`](Self::allocate_raw), the size is rounded up to whole pages and the
+    /// first fit is taken, searching from the bottom or the top of the allowed range depending
+    /// on the request's [`AllocationDirection`]. Fails with [`AllocError::OutOfMemory`] if no
+    /// free range within the request's bounds is large enough.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the direct map offset has not been set via [`crate::set_direct_map_offset`].
+    pub fn allocate_with(
+        &mut self,
+        request: AllocationRequest,
+    ) -> Result<VirtualAddress, AllocError> {
+        let request = request.page_aligned()?;
+        let base = self.regions.lock().allocate(&request)?;
+        poison(base, request.size());
+        Ok(VirtualAddress::direct_mapped(base))
+    }
+
+    /// Allocates `size` bytes at the fixed physical address `base`, returning a direct-mapped
+    /// virtual address.
+    ///
+    /// `base` must be page-aligned; the size is rounded up to whole pages. Fails with
+    /// [`AllocError::OutOfMemory`] if the range is not entirely usable memory, and with
+    /// [`AllocError::RegionOverlap`] if any of it is already reserved or allocated.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the direct map offset has not been set via [`crate::set_direct_map_offset`].
+    pub fn allocate_at(
+        &mut self,
+        base: PhysicalAddress,
+        size: usize,
     ) -> Result<VirtualAddress, AllocError> {
         if size == 0 {
             return Err(AllocError::OutOfMemory);
         }
-
-        // Validate alignment is a power of two
-        if align == 0 || (align & (align - 1)) != 0 {
+        if !base.as_usize().is_multiple_of(PAGE_SIZE) {
             return Err(AllocError::InvalidAlignment);
         }
 
-        // Align size up to page boundary
-        let aligned_size = (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
-
-        // Search for a suitable free region
-        let memory = self.memory.lock();
-        let mut reserved = self.reserved.lock();
-
-        for mem_region in memory.iter() {
-            // Check each potential allocation within this memory region
-            let mut current = mem_region.base().as_usize();
-            let end = mem_region.end().as_usize();
-
-            while current + aligned_size <= end {
-                // Align current address
-                let aligned_current = (current + align - 1) & !(align - 1);
-
-                if aligned_current + aligned_size > end {
-                    break;
-                }
-
-                let candidate = PhysicalAddress::new(aligned_current);
-                let candidate_region = MemoryRegion::new(candidate, aligned_size);
-
-                // Check if this candidate overlaps with any reserved region
-                let mut is_free = true;
-                for reserved_region in reserved.iter() {
-                    if candidate_region.overlaps(reserved_region) {
-                        is_free = false;
-                        // Jump past this reserved region
-                        current = reserved_region.end().as_usize();
-                        break;
-                    }
-                }
-
-                if is_free {
-                    // Found a suitable region - reserve it and return the direct-mapped virtual address
-                    reserved.add(candidate_region)?;
-                    return Ok(VirtualAddress::direct_mapped(candidate));
-                }
-            }
-        }
-
-        Err(AllocError::OutOfMemory)
+        let region = MemoryRegion::new(base, size.next_multiple_of(PAGE_SIZE));
+        self.regions.lock().allocate_at(region)?;
+        poison(base, region.size());
+        Ok(VirtualAddress::direct_mapped(base))
     }
 
     /// Frees a previously allocated memory region.
@@ -430,23 +826,88 @@ impl BlockAllocator {
         let aligned_size = aligned_end - aligned_base.as_usize();
 
         let region = MemoryRegion::new(aligned_base, aligned_size);
-        self.reserved.lock().subtract(region)
+        poison(aligned_base, aligned_size);
+        self.regions.lock().unreserve(region)
     }
 
     /// Returns the total amount of physical memory tracked by the allocator.
     pub fn total_memory(&self) -> usize {
-        self.memory.lock().total_size()
+        self.regions.lock().memory.total_size()
     }
 
     /// Returns the total amount of reserved or allocated memory.
     pub fn reserved_memory(&self) -> usize {
-        self.reserved.lock().total_size()
+        self.regions.lock().reserved.total_size()
     }
 
     /// Returns the total amount of available (free) memory.
     pub fn available_memory(&self) -> usize {
         self.total_memory().saturating_sub(self.reserved_memory())
     }
+
+    /// Consumes the allocator, reporting every usable byte it tracks to `f` exactly once, in
+    /// address order, as either free or allocated.
+    ///
+    /// This is how the allocator hands its memory over to the physical memory manager.
+    /// Reserved regions outside the usable memory, such as bootloader structures, are not
+    /// reported. The storage of grown region arrays is reported as free last, once the arrays
+    /// are no longer read, so `f` may write to free memory as soon as it is reported.
+    pub fn hand_off(self, mut f: impl FnMut(HandOffRange)) {
+        let regions = self.regions.into_inner();
+        let mut storage = [
+            regions.memory.grown_region(),
+            regions.reserved.grown_region(),
+        ];
+        storage.sort_unstable_by_key(|region| region.map(|region| region.base()));
+
+        for memory in regions.memory.iter() {
+            let mut cursor = memory.base().as_usize();
+            let end = memory.end().as_usize();
+
+            for reserved in regions.reserved.iter() {
+                let start = reserved.base().as_usize().max(cursor);
+                let stop = reserved.end().as_usize().min(end);
+                if start >= stop {
+                    continue;
+                }
+
+                if cursor < start {
+                    f(HandOffRange::Free(region_between(cursor, start)));
+                }
+
+                // The array storage is reserved like any allocation, but is freed below.
+                let mut allocated = start;
+                for hole in storage.iter().flatten() {
+                    let hole_start = hole.base().as_usize().clamp(allocated, stop);
+                    let hole_end = hole.end().as_usize().clamp(allocated, stop);
+                    if allocated < hole_start {
+                        f(HandOffRange::Allocated(region_between(
+                            allocated, hole_start,
+                        )));
+                    }
+                    allocated = allocated.max(hole_end);
+                }
+                if allocated < stop {
+                    f(HandOffRange::Allocated(region_between(allocated, stop)));
+                }
+
+                cursor = stop;
+            }
+
+            if cursor < end {
+                f(HandOffRange::Free(region_between(cursor, end)));
+            }
+        }
+
+        for region in storage.into_iter().flatten() {
+            f(HandOffRange::Free(region));
+        }
+    }
+}
+
+/// Returns the region covering `[start, end)`.
+const fn region_between(start: usize, end: usize) -> MemoryRegion {
+    MemoryRegion::new(PhysicalAddress::new(start), end - start)
 }
 
 impl Default for BlockAllocator {
@@ -473,45 +934,23 @@ unsafe impl Allocator for BlockAllocator {
             return Err(CoreAllocError);
         }
 
-        // Search for a suitable free region
-        let memory = self.memory.lock();
-        let mut reserved = self.reserved.lock();
-
-        for mem_region in memory.iter() {
-            let mut current = mem_region.base().as_usize();
-            let end = mem_region.end().as_usize();
-
-            while current + aligned_size <= end {
-                let aligned_current = (current + align - 1) & !(align - 1);
-
-                if aligned_current + aligned_size > end {
-                    break;
-                }
-
-                let candidate = PhysicalAddress::new(aligned_current);
-                let candidate_region = MemoryRegion::new(candidate, aligned_size);
-
-                let mut is_free = true;
-                for reserved_region in reserved.iter() {
-                    if candidate_region.overlaps(reserved_region) {
-                        is_free = false;
-                        current = reserved_region.end().as_usize();
-                        break;
-                    }
-                }
-
-                if is_free {
-                    reserved.add(candidate_region).map_err(|_| CoreAllocError)?;
-                    let virt_addr = VirtualAddress::direct_mapped(candidate);
-                    let ptr = virt_addr.as_mut_ptr::<u8>();
-                    let slice = unsafe { core::slice::from_raw_parts_mut(ptr, size) };
-                    return Ok(NonNull::from(slice));
-                }
+        match self
+            .regions
+            .lock()
+            .allocate(&AllocationRequest::new(aligned_size, align))
+        {
+            Ok(base) => {
+                poison(base, aligned_size);
+                let virt_addr = VirtualAddress::direct_mapped(base);
+                let ptr = virt_addr.as_mut_ptr::<u8>();
+                let slice = unsafe { core::slice::from_raw_parts_mut(ptr, size) };
+                Ok(NonNull::from(slice))
+            }
+            Err(_) => {
+                log::error!("out of memory: failed to allocate {} bytes", size);
+                Err(CoreAllocError)
             }
         }
-
-        log::error!("out of memory: failed to allocate {} bytes", size);
-        Err(CoreAllocError)
     }
 
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
@@ -528,17 +967,37 @@ unsafe impl Allocator for BlockAllocator {
         let aligned_size = aligned_end - aligned_base.as_usize();
 
         let region = MemoryRegion::new(aligned_base, aligned_size);
-        let _ = self.reserved.lock().subtract(region);
+        poison(aligned_base, aligned_size);
+        let _ = self.regions.lock().unreserve(region);
     }
 }
 
+/// Fills `size` bytes at `base` with the free poison. Does nothing without free poisoning.
+fn poison(base: PhysicalAddress, size: usize) {
+    #[cfg(feature = "free-poisoning")]
+    // SAFETY: The range is usable memory that was just allocated or freed, so its previous
+    // contents no longer matter to anyone.
+    unsafe {
+        crate::free_poison::fill(VirtualAddress::direct_mapped(base).as_mut_ptr(), size);
+    }
+    #[cfg(not(feature = "free-poisoning"))]
+    let _ = (base, size);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     /// Sets up the direct map offset for testing.
     /// Uses a simple offset of 0xFFFF_FFFF_FFFF_8000 for 16-bit software emulation.
+    ///
+    /// Free poisoning writes to the memory being allocated and freed, so it needs emulated
+    /// memory behind the direct map.
     fn setup_test_direct_map() {
+        if cfg!(feature = "free-poisoning") {
+            setup_emulated_memory();
+            return;
+        }
         // With thread-local storage, we just need to ensure it's set for this thread
         if crate::AddressTranslator::try_current().is_none() {
             crate::AddressTranslator::set_current(crate::AddressTranslator::hardware(
@@ -547,6 +1006,18 @@ mod tests {
         }
     }
 
+    /// Sets up emulated memory covering the whole 16-bit physical address space, so the
+    /// region arrays can grow into it. Regions stop a page short of the top, since their
+    /// end address must be a valid physical address.
+    fn setup_emulated_memory() {
+        crate::AddressTranslator::set_current(crate::AddressTranslator::emulated(0x10000));
+    }
+
+    /// Returns the physical address of page `index`.
+    fn page(index: usize) -> PhysicalAddress {
+        PhysicalAddress::new(index * PAGE_SIZE)
+    }
+
     #[test]
     fn memory_region_operations() {
         let r1 = MemoryRegion::new(PhysicalAddress::new(0x0100), 0x0100);
@@ -650,9 +1121,11 @@ mod tests {
 
         let addr = allocator.allocate_raw(0x0100, 0x0100).unwrap();
         // Check that the virtual address is in the direct map region
-        assert!(addr.as_usize() >= 0xFFFF_FFFF_FFFF_8000);
+        let phys = PhysicalAddress::from_direct_mapped(addr);
+        assert_eq!(VirtualAddress::direct_mapped(phys), addr);
+        assert!(phys.as_usize() >= 0x0100);
         // Check page alignment
-        assert_eq!(addr.as_usize() & 0xf, 0); // 16-byte alignment
+        assert_eq!(phys.as_usize() & 0xf, 0); // 16-byte alignment
         assert_eq!(allocator.reserved_memory(), 0x0100);
 
         allocator.free(addr, 0x0100).unwrap();
@@ -684,6 +1157,304 @@ mod tests {
         allocator.add(PhysicalAddress::new(0x0100), 0x1000).unwrap();
 
         let addr = allocator.allocate_raw(0x0100, 0x0400).unwrap();
-        assert_eq!(addr.as_usize() & 0x03ff, 0); // Aligned to 1KB
+        let phys = PhysicalAddress::from_direct_mapped(addr);
+        assert_eq!(phys.as_usize() & 0x03ff, 0); // Aligned to 1KB
+    }
+
+    /// Allocates `request` and returns the physical address it was served from.
+    fn allocate_phys(
+        allocator: &mut BlockAllocator,
+        request: AllocationRequest,
+    ) -> Result<PhysicalAddress, AllocError> {
+        allocator
+            .allocate_with(request)
+            .map(PhysicalAddress::from_direct_mapped)
+    }
+
+    #[test]
+    fn requests_respect_address_bounds() {
+        setup_test_direct_map();
+        let mut allocator = BlockAllocator::new();
+        allocator.add(page(0x10), 0x10 * PAGE_SIZE).unwrap();
+        allocator.add(page(0x40), 0x10 * PAGE_SIZE).unwrap();
+
+        let above = AllocationRequest::new(PAGE_SIZE, PAGE_SIZE).with_min_address(page(0x18));
+        assert_eq!(allocate_phys(&mut allocator, above), Ok(page(0x18)));
+
+        // The gap between the regions is skipped.
+        let above = AllocationRequest::new(PAGE_SIZE, PAGE_SIZE).with_min_address(page(0x20));
+        assert_eq!(allocate_phys(&mut allocator, above), Ok(page(0x40)));
+
+        // The whole allocation must lie at or below the maximum.
+        let below = AllocationRequest::new(2 * PAGE_SIZE, PAGE_SIZE)
+            .with_min_address(page(0x1e))
+            .with_max_address(PhysicalAddress::new(page(0x20).as_usize() - 1));
+        assert_eq!(allocate_phys(&mut allocator, below), Ok(page(0x1e)));
+        assert_eq!(
+            allocate_phys(&mut allocator, below),
+            Err(AllocError::OutOfMemory)
+        );
+
+        let empty = AllocationRequest::new(PAGE_SIZE, PAGE_SIZE)
+            .with_min_address(page(0x20))
+            .with_max_address(page(0x30));
+        assert_eq!(
+            allocate_phys(&mut allocator, empty),
+            Err(AllocError::OutOfMemory)
+        );
+    }
+
+    #[test]
+    fn top_down_requests_take_the_highest_fit() {
+        setup_test_direct_map();
+        let mut allocator = BlockAllocator::new();
+        allocator.add(page(0x10), 0x10 * PAGE_SIZE).unwrap();
+        allocator.add(page(0x40), 0x10 * PAGE_SIZE).unwrap();
+        allocator.reserve(page(0x4e), 2 * PAGE_SIZE).unwrap();
+        allocator.reserve(page(0x4a), PAGE_SIZE).unwrap();
+
+        // Below the reserved page at 0x4e, then below the one at 0x4a.
+        let request = AllocationRequest::new(3 * PAGE_SIZE, PAGE_SIZE).top_down();
+        assert_eq!(allocate_phys(&mut allocator, request), Ok(page(0x4b)));
+        assert_eq!(allocate_phys(&mut allocator, request), Ok(page(0x47)));
+
+        // Alignment rounds the base down.
+        let aligned = AllocationRequest::new(PAGE_SIZE, 4 * PAGE_SIZE).top_down();
+        assert_eq!(allocate_phys(&mut allocator, aligned), Ok(page(0x44)));
+
+        let below = request.with_max_address(page(0x30));
+        assert_eq!(allocate_phys(&mut allocator, below), Ok(page(0x1d)));
+
+        let between = request
+            .with_min_address(page(0x40))
+            .with_max_address(PhysicalAddress::new(page(0x44).as_usize() - 1));
+        assert_eq!(allocate_phys(&mut allocator, between), Ok(page(0x41)));
+        assert_eq!(
+            allocate_phys(&mut allocator, between),
+            Err(AllocError::OutOfMemory)
+        );
+    }
+
+    #[test]
+    fn allocate_at_reserves_a_fixed_range() {
+        setup_test_direct_map();
+        let mut allocator = BlockAllocator::new();
+        allocator.add(page(0x10), 0x10 * PAGE_SIZE).unwrap();
+        allocator.reserve(page(0x18), PAGE_SIZE).unwrap();
+
+        let addr = allocator
+            .allocate_at(page(0x12), 2 * PAGE_SIZE - 1)
+            .unwrap();
+        assert_eq!(PhysicalAddress::from_direct_mapped(addr), page(0x12));
+        assert_eq!(allocator.reserved_memory(), 3 * PAGE_SIZE);
+
+        assert_eq!(
+            allocator.allocate_at(page(0x13), PAGE_SIZE),
+            Err(AllocError::RegionOverlap)
+        );
+        assert_eq!(
+            allocator.allocate_at(page(0x17), 2 * PAGE_SIZE),
+            Err(AllocError::RegionOverlap)
+        );
+        assert_eq!(
+            allocator.allocate_at(page(0x1f), 2 * PAGE_SIZE),
+            Err(AllocError::OutOfMemory)
+        );
+        assert_eq!(
+            allocator.allocate_at(PhysicalAddress::new(page(0x14).as_usize() + 1), PAGE_SIZE),
+            Err(AllocError::InvalidAlignment)
+        );
+
+        // Bottom-up allocations go around it.
+        let request = AllocationRequest::new(3 * PAGE_SIZE, PAGE_SIZE);
+        assert_eq!(allocate_phys(&mut allocator, request), Ok(page(0x14)));
+        assert_eq!(allocator.reserved_memory(), 6 * PAGE_SIZE);
+
+        allocator.free(addr, 2 * PAGE_SIZE).unwrap();
+        assert_eq!(allocator.allocate_at(page(0x12), PAGE_SIZE), Ok(addr));
+    }
+
+    #[test]
+    fn memory_list_grows_past_initial_capacity() {
+        setup_emulated_memory();
+        let mut allocator = BlockAllocator::new();
+
+        // Room for the grown arrays, above a map fragmented into every other page.
+        allocator.add(page(0x800), 0x7ff * PAGE_SIZE).unwrap();
+        for i in 0..1000 {
+            allocator.add(page(2 * i), PAGE_SIZE).unwrap();
+        }
+
+        assert_eq!(allocator.total_memory(), (0x7ff + 1000) * PAGE_SIZE);
+        let regions = allocator.regions.lock();
+        assert_eq!(regions.memory.len(), 1001);
+        assert!(regions.memory.capacity() >= 1001 + HEADROOM);
+        for (i, region) in regions.memory.iter().take(1000).enumerate() {
+            assert_eq!(*region, MemoryRegion::new(page(2 * i), PAGE_SIZE));
+        }
+
+        // Only the current storage is reserved; everything it grew out of was released.
+        assert_eq!(regions.reserved.len(), 1);
+        assert_eq!(
+            regions.reserved.total_size(),
+            storage_size(regions.memory.capacity())
+        );
+    }
+
+    #[test]
+    fn reserved_list_grows_and_shrinks_back() {
+        setup_emulated_memory();
+        let mut allocator = BlockAllocator::new();
+        allocator.add(page(0), 0xfff * PAGE_SIZE).unwrap();
+
+        // Reserve every other page of the top half, leaving the bottom half for the arrays.
+        let reserved =
+            |index: usize| (0x800..0x800 + 2000).contains(&index) && index.is_multiple_of(2);
+        for i in 0..1000 {
+            allocator.reserve(page(0x800 + 2 * i), PAGE_SIZE).unwrap();
+        }
+        let capacity = allocator.regions.lock().reserved.capacity();
+        assert!(capacity > MAX_REGIONS);
+        assert_eq!(
+            allocator.reserved_memory(),
+            1000 * PAGE_SIZE + storage_size(capacity)
+        );
+
+        // Allocating every free page one at a time never hands out a reserved page.
+        let free_pages = allocator.available_memory() / PAGE_SIZE;
+        let mut allocated = Vec::new();
+        while let Ok(addr) = allocator.allocate_raw(PAGE_SIZE, PAGE_SIZE) {
+            let index = PhysicalAddress::from_direct_mapped(addr).as_usize() / PAGE_SIZE;
+            assert!(!reserved(index), "page {index:#x} is reserved");
+            allocated.push(addr);
+        }
+        assert_eq!(allocated.len(), free_pages);
+
+        for addr in allocated {
+            allocator.free(addr, PAGE_SIZE).unwrap();
+        }
+        for i in 0..1000 {
+            allocator
+                .free(
+                    VirtualAddress::direct_mapped(page(0x800 + 2 * i)),
+                    PAGE_SIZE,
+                )
+                .unwrap();
+        }
+        let capacity = allocator.regions.lock().reserved.capacity();
+        assert_eq!(allocator.regions.lock().reserved.len(), 1);
+        assert_eq!(allocator.reserved_memory(), storage_size(capacity));
+        assert_eq!(
+            allocator.available_memory(),
+            0xfff * PAGE_SIZE - storage_size(capacity)
+        );
+    }
+
+    #[test]
+    fn split_reservations_grow_the_reserved_list() {
+        setup_emulated_memory();
+        let mut allocator = BlockAllocator::new();
+        allocator.add(page(0), 0xfff * PAGE_SIZE).unwrap();
+
+        // Allocate a run of pages, then free every other one, splitting the run into
+        // a thousand reservations.
+        let run = allocator.allocate_raw(2000 * PAGE_SIZE, PAGE_SIZE).unwrap();
+        let base = PhysicalAddress::from_direct_mapped(run);
+        assert_eq!(allocator.regions.lock().reserved.len(), 1);
+        for i in 0..1000 {
+            allocator
+                .free(
+                    VirtualAddress::direct_mapped(base + (2 * i + 1) * PAGE_SIZE),
+                    PAGE_SIZE,
+                )
+                .unwrap();
+        }
+
+        let regions = allocator.regions.lock();
+        assert!(regions.reserved.len() >= 1000);
+        assert_eq!(
+            regions.reserved.total_size(),
+            1000 * PAGE_SIZE + storage_size(regions.reserved.capacity())
+        );
+    }
+
+    #[test]
+    fn regions_full_without_memory_to_grow_into() {
+        setup_emulated_memory();
+        let mut allocator = BlockAllocator::new();
+
+        // Single pages leave no room for a larger array.
+        for i in 0..MAX_REGIONS {
+            allocator.add(page(2 * i), PAGE_SIZE).unwrap();
+        }
+
+        assert_eq!(
+            allocator.add(page(2 * MAX_REGIONS), PAGE_SIZE),
+            Err(AllocError::RegionsFull)
+        );
+        assert_eq!(allocator.regions.lock().memory.capacity(), MAX_REGIONS);
+    }
+
+    #[test]
+    fn hand_off_reports_usable_memory_as_free_or_allocated() {
+        setup_emulated_memory();
+        let mut allocator = BlockAllocator::new();
+        allocator.add(page(0x10), 0x10 * PAGE_SIZE).unwrap();
+        allocator.add(page(0x30), 0x10 * PAGE_SIZE).unwrap();
+        // Half inside usable memory, half outside.
+        allocator.reserve(page(0x18), 0x10 * PAGE_SIZE).unwrap();
+        // Entirely outside usable memory, so not reported.
+        allocator.reserve(page(0x50), PAGE_SIZE).unwrap();
+        let addr = allocator.allocate_raw(2 * PAGE_SIZE, PAGE_SIZE).unwrap();
+        assert_eq!(PhysicalAddress::from_direct_mapped(addr), page(0x10));
+
+        let mut ranges = Vec::new();
+        allocator.hand_off(|range| ranges.push(range));
+        assert_eq!(
+            ranges,
+            [
+                HandOffRange::Allocated(MemoryRegion::new(page(0x10), 2 * PAGE_SIZE)),
+                HandOffRange::Free(MemoryRegion::new(page(0x12), 6 * PAGE_SIZE)),
+                HandOffRange::Allocated(MemoryRegion::new(page(0x18), 8 * PAGE_SIZE)),
+                HandOffRange::Free(MemoryRegion::new(page(0x30), 0x10 * PAGE_SIZE)),
+            ]
+        );
+    }
+
+    #[test]
+    fn hand_off_frees_grown_storage_last() {
+        setup_emulated_memory();
+        let mut allocator = BlockAllocator::new();
+        allocator.add(page(0), 0xfff * PAGE_SIZE).unwrap();
+        for i in 0..1000 {
+            allocator.reserve(page(0x800 + 2 * i), PAGE_SIZE).unwrap();
+        }
+        let storage = allocator
+            .regions
+            .lock()
+            .reserved
+            .grown_region()
+            .expect("reserved list should have grown");
+
+        let mut ranges = Vec::new();
+        allocator.hand_off(|range| ranges.push(range));
+
+        assert_eq!(ranges.last(), Some(&HandOffRange::Free(storage)));
+        let mut free = 0;
+        let mut allocated = 0;
+        for range in &ranges[..ranges.len() - 1] {
+            match range {
+                HandOffRange::Free(region) => {
+                    assert!(!region.overlaps(&storage));
+                    free += region.size();
+                }
+                HandOffRange::Allocated(region) => {
+                    assert!(!region.overlaps(&storage));
+                    allocated += region.size();
+                }
+            }
+        }
+        assert_eq!(allocated, 1000 * PAGE_SIZE);
+        assert_eq!(free + storage.size(), (0xfff - 1000) * PAGE_SIZE);
     }
 }
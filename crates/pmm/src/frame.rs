@@ -1,4 +1,5 @@
-use core::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use core::fmt;
+use core::sync::atomic::{AtomicU8, AtomicU32, AtomicU64, Ordering};
 
 /// Special order value indicating the frame is allocated but not from the buddy allocator,
 /// or that the frame has never been managed by the buddy allocator.
@@ -14,6 +15,9 @@ pub struct Frame {
     /// The order of allocation for this frame (0-11 for buddy allocator blocks, 0xFF if not from buddy allocator).
     /// Only meaningful when the Allocated flag is set.
     order: AtomicU8,
+    /// Number of references held to the block headed by this frame.
+    /// Only meaningful on the first frame of an allocated block.
+    ref_count: AtomicU32,
 }
 
 impl Frame {
@@ -26,6 +30,45 @@ impl Frame {
     pub fn set_order(&self, order: u8) {
         self.order.store(order, Ordering::Release);
     }
+
+    /// Gets the number of references held to this frame.
+    pub fn ref_count(&self) -> u32 {
+        self.ref_count.load(Ordering::Acquire)
+    }
+
+    /// Sets the reference count for this frame.
+    pub(crate) fn set_ref_count(&self, count: u32) {
+        self.ref_count.store(count, Ordering::Release);
+    }
+
+    /// Takes an additional reference to this frame, returning the new count.
+    pub fn get(&self) -> u32 {
+        let old = self.ref_count.fetch_add(1, Ordering::AcqRel);
+        debug_assert!(old != u32::MAX, "frame reference count overflow");
+        old + 1
+    }
+
+    /// Drops a reference to this frame, returning the new count.
+    ///
+    /// Panics if the count is already zero. When the count reaches zero the frame should be
+    /// returned to the allocator; use [`PhysicalMemoryManager::put_frame`] to do both.
+    ///
+    /// [`PhysicalMemoryManager::put_frame`]: crate::PhysicalMemoryManager::put_frame
+    pub fn put(&self) -> u32 {
+        let old = self.ref_count.fetch_sub(1, Ordering::AcqRel);
+        assert!(old != 0, "frame reference count underflow");
+        old - 1
+    }
+}
+
+impl fmt::Debug for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Frame")
+            .field("flags", &self.flags)
+            .field("order", &self.order())
+            .field("ref_count", &self.ref_count())
+            .finish()
+    }
 }
 
 impl Default for Frame {
@@ -33,21 +76,63 @@ impl Default for Frame {
         Self {
             flags: FrameFlags::new(),
             order: AtomicU8::new(ORDER_NOT_BUDDY),
+            ref_count: AtomicU32::new(0),
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FrameFlag {
     /// Frame is allocated.
     Allocated = 1 << 0,
     /// Frame is reserved and should not be allocated.
     Reserved = 1 << 1,
+    /// Frame holds a page table.
+    PageTable = 1 << 2,
+    /// Frame belongs to a slab of the slab allocator.
+    Slab = 1 << 3,
+    /// Frame holds part of the kernel image and must never be freed.
+    KernelImage = 1 << 4,
+    /// Frame has been found to be faulty and must never be handed out again.
+    Poisoned = 1 << 5,
+    /// Frame is pinned in place and must not be freed until it is unlocked.
+    Locked = 1 << 6,
+    /// Frame was handed out by the block allocator before the physical memory manager took
+    /// over, and must be freed with
+    /// [`deallocate_boot`](crate::PhysicalMemoryManager::deallocate_boot).
+    Boot = 1 << 7,
+}
+
+impl FrameFlag {
+    /// Every flag, in bit order.
+    pub const ALL: [FrameFlag; 8] = [
+        FrameFlag::Allocated,
+        FrameFlag::Reserved,
+        FrameFlag::PageTable,
+        FrameFlag::Slab,
+        FrameFlag::KernelImage,
+        FrameFlag::Poisoned,
+        FrameFlag::Locked,
+        FrameFlag::Boot,
+    ];
 }
 
 /// Atomic flags for a physical memory frame.
 #[derive(Default)]
 pub struct FrameFlags(AtomicU64);
 
+impl fmt::Debug for FrameFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries(
+                FrameFlag::ALL
+                    .into_iter()
+                    .filter(|&flag| self.atomic_test(flag)),
+            )
+            .finish()
+    }
+}
+
 impl FrameFlags {
     /// Creates a new `FrameFlags` instance with all flags cleared.
     pub const fn new() -> Self {
@@ -95,7 +180,7 @@ impl FrameFlags {
     /// Clears the given flag atomically.
     pub fn atomic_clear(&self, flag: FrameFlag) {
         let mask = !(flag as u64);
-        self.0.fetch_or(mask, Ordering::AcqRel);
+        self.0.fetch_and(mask, Ordering::AcqRel);
     }
 
     /// Tests if the given flag is set, atomically.
@@ -111,4 +196,43 @@ impl FrameFlags {
         let old = self.0.fetch_or(mask, Ordering::AcqRel);
         (old & mask) != 0
     }
+
+    /// Tests the given flag and clears it atomically, returning the previous value.
+    pub fn atomic_test_and_clear(&self, flag: FrameFlag) -> bool {
+        let mask = flag as u64;
+        let old = self.0.fetch_and(!mask, Ordering::AcqRel);
+        (old & mask) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atomic_clear_only_clears_the_given_flag() {
+        let flags = FrameFlags::from_bits(FrameFlag::Allocated as u64 | FrameFlag::Locked as u64);
+
+        flags.atomic_clear(FrameFlag::Locked);
+        assert!(!flags.atomic_test(FrameFlag::Locked));
+        assert!(flags.atomic_test(FrameFlag::Allocated));
+        assert!(!flags.atomic_test(FrameFlag::Reserved));
+
+        assert!(flags.atomic_test_and_clear(FrameFlag::Allocated));
+        assert!(!flags.atomic_test_and_clear(FrameFlag::Allocated));
+        assert!(!flags.atomic_test(FrameFlag::Allocated));
+    }
+
+    #[test]
+    fn debug_lists_set_flags() {
+        let frame = Frame::default();
+        frame.flags.atomic_set(FrameFlag::Allocated);
+        frame.flags.atomic_set(FrameFlag::Slab);
+        frame.set_order(2);
+
+        assert_eq!(
+            alloc::format!("{frame:?}"),
+            "Frame { flags: {Allocated, Slab}, order: 2, ref_count: 0 }"
+        );
+    }
 }
@@ -0,0 +1,34 @@
+//! Poisoning of freed memory, enabled by the `free-poisoning` feature.
+//!
+//! Memory is filled with [`FREE_POISON`] when it is freed, and checked for the pattern when it
+//! is handed out again. A byte that no longer holds the pattern was written after the memory
+//! was freed, which points at a use-after-free. Memory that is handed out still holds the
+//! pattern, so reads of uninitialised memory show up as `0x6b6b...` values.
+//!
+//! Unrelated to [`FrameFlag::Poisoned`](crate::FrameFlag::Poisoned), which marks faulty frames.
+
+/// The byte freed memory is filled with.
+pub const FREE_POISON: u8 = 0x6b;
+
+/// Fills `len` bytes at `ptr` with [`FREE_POISON`].
+///
+/// # Safety
+/// `ptr` must be valid for writes of `len` bytes.
+pub(crate) unsafe fn fill(ptr: *mut u8, len: usize) {
+    // SAFETY: Guaranteed by the caller.
+    unsafe { ptr.write_bytes(FREE_POISON, len) };
+}
+
+/// Returns the offset of the first of `len` bytes at `ptr` that does not hold [`FREE_POISON`],
+/// together with the byte found there.
+///
+/// # Safety
+/// `ptr` must be valid for reads of `len` bytes.
+pub(crate) unsafe fn find_overwritten(ptr: *const u8, len: usize) -> Option<(usize, u8)> {
+    // SAFETY: Guaranteed by the caller.
+    let bytes = unsafe { core::slice::from_raw_parts(ptr, len) };
+    bytes
+        .iter()
+        .position(|&byte| byte != FREE_POISON)
+        .map(|offset| (offset, bytes[offset]))
+}
@@ -20,22 +20,40 @@ mod address_space;
 mod arch;
 mod block_allocator;
 mod frame;
+#[cfg(feature = "free-poisoning")]
+mod free_poison;
 mod human_address;
 mod human_size;
 mod memmap;
 mod numbers;
 mod page_directory;
 mod physical_memory_manager;
+mod slab_allocator;
+mod stats;
 
 pub use address::{AddressTranslator, PhysicalAddress, VirtualAddress};
-pub use address_space::AddressSpace;
-pub use block_allocator::{AllocError, BlockAllocator, MemoryRegion};
+pub use address_space::{AddressSpace, Backing, Protection, Vma, VmaError};
+pub use block_allocator::{
+    AllocError, AllocationDirection, AllocationRequest, BlockAllocator, HandOffRange, MemoryRegion,
+};
 pub use frame::{Frame, FrameFlag, FrameFlags, ORDER_NOT_BUDDY};
+#[cfg(feature = "free-poisoning")]
+pub use free_poison::FREE_POISON;
 pub use human_address::HumanAddress;
 pub use human_size::HumanSize;
 pub use memmap::{BootMemoryRegion, FRAMES_PER_SECTION, MemoryMap, SECTION_SIZE};
 pub use numbers::{FrameNumber, PageNumber};
-pub use page_directory::PageDirectory;
-pub use physical_memory_manager::PhysicalMemoryManager;
+pub use page_directory::{MapError, Mapping, Mappings, PageDirectory, PageSize, TlbFlush};
+#[cfg(not(any(test, feature = "software-emulation")))]
+pub use page_directory::{PageTableAllocator, set_page_table_allocator};
+#[cfg(any(test, debug_assertions, feature = "verify"))]
+pub use physical_memory_manager::VerifyError;
+pub use physical_memory_manager::{FrameAllocator, PhysicalMemoryManager, Watermarks, Zone};
+pub use slab_allocator::{MAX_SLAB_OBJECT_SIZE, SlabAllocator};
+pub use stats::PmmStats;
 
-pub use arch::{PAGE_SIZE, PageFlags};
+#[cfg(all(target_arch = "x86_64", not(test), not(feature = "software-emulation")))]
+pub use arch::{MemoryType, PAT_LAYOUT};
+pub use arch::{
+    PAGE_SIZE, PageFlagBits, PageFlags, page_table_levels, set_page_table_levels, virtual_bits,
+};
@@ -25,6 +25,9 @@
 //! ```
 
 use alloc::boxed::Box;
+use alloc::collections::BinaryHeap;
+use alloc::vec;
+use alloc::vec::Vec;
 
 use crate::{Frame, FrameFlag, FrameNumber, HumanSize, PhysicalAddress, arch};
 
@@ -51,13 +54,22 @@ pub trait BootMemoryRegion {
     /// Non-usable memory (reserved, ACPI, device memory, etc.) should
     /// return `false`.
     fn is_usable(&self) -> bool;
+
+    /// Returns whether this region can be reclaimed once the data in it is no longer needed.
+    ///
+    /// Reclaimable memory (bootloader data structures, ACPI tables, etc.) starts out reserved
+    /// but is covered by the memory map, so that it can later be handed to the physical memory
+    /// manager with [`PhysicalMemoryManager::reclaim_region`](crate::PhysicalMemoryManager::reclaim_region).
+    fn is_reclaimable(&self) -> bool {
+        false
+    }
 }
 
 /// A section of the memory map containing frames for a contiguous region.
 ///
 /// Each section covers `FRAMES_PER_SECTION` frame indices. If the section contains
-/// any usable memory, `frames` holds the frame metadata for the smallest contiguous
-/// region that includes all usable memory in the section.
+/// any usable or reclaimable memory, `frames` holds the frame metadata for the smallest
+/// contiguous region that includes all such memory in the section.
 pub struct Section {
     /// The starting frame number within this section's frame array.
     /// Frame index `start_frame` maps to `frames[0]`.
@@ -164,12 +176,27 @@ impl MemoryMap {
             HumanSize::from(max_address)
         );
 
-        // Build each section
-        let sections: Box<[Section]> = (0..section_count)
-            .map(|idx| Self::build_section(idx, boot_map))
-            .collect();
+        let mut map = Self {
+            sections: Self::section_hulls(section_count, boot_map)
+                .into_iter()
+                .enumerate()
+                .map(|(idx, hull)| Self::build_section(idx, hull))
+                .collect(),
+        };
 
-        Self { sections }
+        // Frames start out reserved; clear the flag on every frame that the winning entry marks
+        // usable.
+        for (start, end) in Self::usable_runs(boot_map) {
+            let first = start.div_ceil(arch::PAGE_SIZE);
+            let last = end.div_ceil(arch::PAGE_SIZE);
+            for idx in first..last {
+                if let Some(frame) = map.frame_mut(FrameNumber::new(idx)) {
+                    frame.flags.clear(FrameFlag::Reserved);
+                }
+            }
+        }
+
+        map
     }
 
     /// Returns a reference to the frame at the given frame number.
@@ -214,55 +241,61 @@ impl MemoryMap {
         (max_frame / FRAMES_PER_SECTION) + 1
     }
 
-    /// Builds a single section from the boot map.
-    fn build_section<R: BootMemoryRegion>(section_idx: usize, boot_map: &[R]) -> Section {
-        let section_start_frame = section_idx * FRAMES_PER_SECTION;
-        let section_end_frame = section_start_frame + FRAMES_PER_SECTION;
-
-        // Find the range of usable memory within this section
-        let mut min_usable_frame: Option<usize> = None;
-        let mut max_usable_frame: Option<usize> = None;
+    /// Computes, for each section, the smallest frame range `[start, end)` holding all of the
+    /// usable or reclaimable memory in it.
+    ///
+    /// Each region only visits the sections it overlaps, so this is linear in the number of
+    /// regions plus sections.
+    fn section_hulls<R: BootMemoryRegion>(
+        section_count: usize,
+        boot_map: &[R],
+    ) -> Vec<Option<(usize, usize)>> {
+        let mut hulls = vec![None; section_count];
 
         for region in boot_map {
-            if !region.is_usable() {
+            if !region.is_usable() && !region.is_reclaimable() {
                 continue;
             }
 
-            let region_start_frame = (region.base().as_usize() / arch::PAGE_SIZE) as usize;
-            let region_end_frame =
-                ((region.base().as_usize() + region.size()) / arch::PAGE_SIZE) as usize;
+            let region_start = region.base().as_usize() / arch::PAGE_SIZE;
+            let region_end = (region.base().as_usize() + region.size()) / arch::PAGE_SIZE;
+
+            let first_section = region_start / FRAMES_PER_SECTION;
+            let last_section = region_end.saturating_sub(1).max(region_start) / FRAMES_PER_SECTION;
+            for (idx, hull) in hulls
+                .iter_mut()
+                .enumerate()
+                .take(last_section + 1)
+                .skip(first_section)
+            {
+                let section_start = idx * FRAMES_PER_SECTION;
+                let section_end = section_start + FRAMES_PER_SECTION;
+                if region_end <= section_start || region_start >= section_end {
+                    continue;
+                }
 
-            // Check if this region overlaps with the section
-            if region_end_frame <= section_start_frame || region_start_frame >= section_end_frame {
-                continue;
+                let start = region_start.max(section_start);
+                let end = region_end.min(section_end);
+                *hull = Some(match *hull {
+                    Some((min, max)) => (start.min(min), end.max(max)),
+                    None => (start, end),
+                });
             }
-
-            // Clamp to section bounds
-            let start = region_start_frame.max(section_start_frame);
-            let end = region_end_frame.min(section_end_frame);
-
-            min_usable_frame = Some(min_usable_frame.map_or(start, |m| m.min(start)));
-            max_usable_frame = Some(max_usable_frame.map_or(end, |m| m.max(end)));
         }
 
-        // If no usable memory in this section, return empty
-        let (min_frame, max_frame) = match (min_usable_frame, max_usable_frame) {
-            (Some(min), Some(max)) => (min, max),
-            _ => return Section::empty(),
+        hulls
+    }
+
+    /// Builds a single section covering `hull`, with every frame marked reserved.
+    fn build_section(section_idx: usize, hull: Option<(usize, usize)>) -> Section {
+        let Some((min_frame, max_frame)) = hull else {
+            return Section::empty();
         };
 
-        // Allocate frames for the contiguous region
-        let frame_count = max_frame - min_frame;
         let frames: Box<[Frame]> = (min_frame..max_frame)
-            .map(|frame_idx| {
+            .map(|_| {
                 let mut frame = Frame::default();
-
-                // Determine the type of this frame based on boot map regions
-                let frame_addr = PhysicalAddress::new(frame_idx * arch::PAGE_SIZE);
-                if !Self::is_frame_usable(frame_addr, boot_map) {
-                    frame.flags.set(FrameFlag::Reserved);
-                }
-
+                frame.flags.set(FrameFlag::Reserved);
                 frame
             })
             .collect();
@@ -270,29 +303,59 @@ impl MemoryMap {
         log::trace!(
             "section {}: allocated {} frames starting at frame {}",
             section_idx,
-            frame_count,
+            max_frame - min_frame,
             min_frame
         );
 
         Section::with_frames(FrameNumber::new(min_frame), frames)
     }
 
-    /// Determines if a frame is usable based on the boot map.
+    /// Resolves overlapping boot map entries into the sorted, disjoint byte ranges whose
+    /// winning entry is usable.
     ///
-    /// A frame is usable if any usable region contains it and no later
-    /// non-usable region overrides it. Boot map entries are processed
-    /// in order, with later entries taking precedence.
-    fn is_frame_usable<R: BootMemoryRegion>(addr: PhysicalAddress, boot_map: &[R]) -> bool {
-        let mut usable = false;
+    /// Boot map entries are processed in order, with later entries taking precedence. The
+    /// entry boundaries are swept in address order while a max-heap holds the entries covering
+    /// the current position, keyed by their position in the boot map, so the top of the heap is
+    /// the entry that wins. This takes `O(n log n)` time in the number of entries.
+    fn usable_runs<R: BootMemoryRegion>(boot_map: &[R]) -> Vec<(usize, usize)> {
+        let end_of = |idx: usize| boot_map[idx].base().as_usize() + boot_map[idx].size();
+
+        let mut by_start: Vec<usize> = (0..boot_map.len())
+            .filter(|&idx| boot_map[idx].size() > 0)
+            .collect();
+        by_start.sort_unstable_by_key(|&idx| boot_map[idx].base().as_usize());
 
-        for region in boot_map {
-            let region_end = region.base().as_usize() + region.size();
-            if addr.as_usize() >= region.base().as_usize() && addr.as_usize() < region_end {
-                usable = region.is_usable();
+        let mut points: Vec<usize> = by_start
+            .iter()
+            .flat_map(|&idx| [boot_map[idx].base().as_usize(), end_of(idx)])
+            .collect();
+        points.sort_unstable();
+        points.dedup();
+
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        let mut active = BinaryHeap::new();
+        let mut next = 0;
+        for window in points.windows(2) {
+            let (start, end) = (window[0], window[1]);
+
+            while next < by_start.len() && boot_map[by_start[next]].base().as_usize() <= start {
+                active.push(by_start[next]);
+                next += 1;
+            }
+            while active.peek().is_some_and(|&idx| end_of(idx) <= start) {
+                active.pop();
+            }
+
+            if !active.peek().is_some_and(|&idx| boot_map[idx].is_usable()) {
+                continue;
+            }
+            match runs.last_mut() {
+                Some(last) if last.1 == start => last.1 = end,
+                _ => runs.push((start, end)),
             }
         }
 
-        usable
+        runs
     }
 }
 
@@ -305,6 +368,7 @@ mod tests {
         base: PhysicalAddress,
         size: usize,
         usable: bool,
+        reclaimable: bool,
     }
 
     impl TestRegion {
@@ -313,6 +377,7 @@ mod tests {
                 base: PhysicalAddress::new(base),
                 size,
                 usable: true,
+                reclaimable: false,
             }
         }
 
@@ -321,6 +386,16 @@ mod tests {
                 base: PhysicalAddress::new(base),
                 size,
                 usable: false,
+                reclaimable: false,
+            }
+        }
+
+        fn reclaimable(base: usize, size: usize) -> Self {
+            Self {
+                base: PhysicalAddress::new(base),
+                size,
+                usable: false,
+                reclaimable: true,
             }
         }
     }
@@ -337,6 +412,10 @@ mod tests {
         fn is_usable(&self) -> bool {
             self.usable
         }
+
+        fn is_reclaimable(&self) -> bool {
+            self.reclaimable
+        }
     }
 
     #[test]
@@ -436,6 +515,22 @@ mod tests {
         assert!(!frame_70.unwrap().flags.atomic_test(FrameFlag::Reserved));
     }
 
+    #[test]
+    fn reclaimable_region_is_covered_but_reserved() {
+        let boot_map = [
+            TestRegion::usable(0, arch::PAGE_SIZE * 50),
+            TestRegion::reserved(arch::PAGE_SIZE * 50, arch::PAGE_SIZE * 10),
+            TestRegion::reclaimable(arch::PAGE_SIZE * 60, arch::PAGE_SIZE * 20),
+        ];
+
+        let map = MemoryMap::from_boot_map(&boot_map);
+
+        assert_eq!(map.allocated_frame_count(), 80);
+        let frame = map.frame(FrameNumber::new(70)).unwrap();
+        assert!(frame.flags.atomic_test(FrameFlag::Reserved));
+        assert!(map.frame(FrameNumber::new(80)).is_none());
+    }
+
     #[test]
     #[cfg(not(test))]
     fn multiple_sections() {
@@ -552,4 +647,133 @@ mod tests {
         let hole_addr = PhysicalAddress::new(arch::PAGE_SIZE * 100);
         assert!(map.frame_for(hole_addr).is_none());
     }
+
+    /// The original construction, which rescans the whole boot map for every section and then
+    /// again for every frame. Returns each section's first frame and reserved flags.
+    fn reference_sections(boot_map: &[TestRegion]) -> Vec<Option<(usize, Vec<bool>)>> {
+        let max_address = boot_map
+            .iter()
+            .map(|r| r.base().as_usize() + r.size())
+            .max()
+            .unwrap_or(0);
+        let section_count = MemoryMap::section_count_for_address(max_address);
+
+        (0..section_count)
+            .map(|section_idx| {
+                let section_start = section_idx * FRAMES_PER_SECTION;
+                let section_end = section_start + FRAMES_PER_SECTION;
+                let mut hull: Option<(usize, usize)> = None;
+                for region in boot_map {
+                    if !region.is_usable() && !region.is_reclaimable() {
+                        continue;
+                    }
+                    let start = region.base().as_usize() / arch::PAGE_SIZE;
+                    let end = (region.base().as_usize() + region.size()) / arch::PAGE_SIZE;
+                    if end <= section_start || start >= section_end {
+                        continue;
+                    }
+                    let (start, end) = (start.max(section_start), end.min(section_end));
+                    hull = Some(
+                        hull.map_or((start, end), |(min, max)| (min.min(start), max.max(end))),
+                    );
+                }
+
+                let (min, max) = hull?;
+                let reserved = (min..max)
+                    .map(|idx| {
+                        let addr = idx * arch::PAGE_SIZE;
+                        let mut usable = false;
+                        for region in boot_map {
+                            let base = region.base().as_usize();
+                            if addr >= base && addr < base + region.size() {
+                                usable = region.is_usable();
+                            }
+                        }
+                        !usable
+                    })
+                    .collect();
+                Some((min, reserved))
+            })
+            .collect()
+    }
+
+    /// Generates a boot map of `count` overlapping, unaligned regions of random kinds.
+    fn synthetic_boot_map(seed: u64, count: usize) -> Vec<TestRegion> {
+        let mut state = seed;
+        let mut next = move |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound as u64) as usize
+        };
+
+        let limit = 0x10000;
+        (0..count)
+            .map(|_| {
+                let base = next(limit);
+                let size = next((limit - base).min(arch::PAGE_SIZE * 64)) + 1;
+                match next(3) {
+                    0 => TestRegion::usable(base, size),
+                    1 => TestRegion::reserved(base, size),
+                    _ => TestRegion::reclaimable(base, size),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sweep_matches_reference_on_large_synthetic_maps() {
+        for seed in 1..=16 {
+            let boot_map = synthetic_boot_map(seed * 0x9E37_79B9, 2000);
+
+            let map = MemoryMap::from_boot_map(&boot_map);
+            let reference = reference_sections(&boot_map);
+
+            assert_eq!(map.sections().len(), reference.len(), "seed {}", seed);
+            for (section, expected) in map.sections().iter().zip(&reference) {
+                let Some((start, reserved)) = expected else {
+                    assert!(section.frame_range().is_none(), "seed {}", seed);
+                    continue;
+                };
+
+                let (first, last) = section.frame_range().unwrap();
+                assert_eq!(first.as_usize(), *start, "seed {}", seed);
+                assert_eq!(last.as_usize(), start + reserved.len(), "seed {}", seed);
+                for (offset, &expected) in reserved.iter().enumerate() {
+                    let frame = map.frame(FrameNumber::new(start + offset)).unwrap();
+                    assert_eq!(
+                        frame.flags.atomic_test(FrameFlag::Reserved),
+                        expected,
+                        "seed {} frame {}",
+                        seed,
+                        start + offset
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn later_entries_win_in_either_direction() {
+        let boot_map = [
+            TestRegion::reserved(0, arch::PAGE_SIZE * 20),
+            TestRegion::usable(arch::PAGE_SIZE * 5, arch::PAGE_SIZE * 10),
+            TestRegion::reserved(arch::PAGE_SIZE * 8, arch::PAGE_SIZE * 2),
+        ];
+
+        let map = MemoryMap::from_boot_map(&boot_map);
+
+        let reserved = |idx| {
+            map.frame(FrameNumber::new(idx))
+                .unwrap()
+                .flags
+                .atomic_test(FrameFlag::Reserved)
+        };
+        assert!(!reserved(5));
+        assert!(!reserved(7));
+        assert!(reserved(8));
+        assert!(reserved(9));
+        assert!(!reserved(10));
+        assert!(!reserved(14));
+    }
 }
@@ -136,13 +136,13 @@ impl_page_number_common!(
 impl PageNumber {
     /// Returns the virtual address at the start of this page.
     #[inline]
-    pub const fn start(self) -> VirtualAddress {
+    pub fn start(self) -> VirtualAddress {
         VirtualAddress::new(self.0 * arch::PAGE_SIZE)
     }
 
     /// Returns the virtual address at the end of this page (start of next page).
     #[inline]
-    pub const fn end(self) -> VirtualAddress {
+    pub fn end(self) -> VirtualAddress {
         VirtualAddress::new((self.0 + 1) * arch::PAGE_SIZE)
     }
 }
@@ -3,10 +3,13 @@
 //! This module provides the `PageDirectory` type, which wraps the architecture-specific
 //! `PageTable` and provides high-level operations for mapping and unmapping virtual addresses.
 
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+
 use crate::{
     PhysicalAddress, VirtualAddress,
     address::AddressTranslator,
-    arch::{self, PageEntry, PageFlags, PageTable},
+    arch::{self, PageEntry, PageFlagBits, PageFlags, PageTable},
 };
 
 #[cfg(not(any(test, feature = "software-emulation")))]
@@ -39,10 +42,267 @@ fn alloc_page_table() -> *mut PageTable {
     }
 }
 
-/// Allocates a new page table using the standard heap allocator.
+/// Source of the frames holding the page tables a [`PageDirectory`] allocates.
+///
+/// Until one is installed with [`set_page_table_allocator`], tables come from the heap.
+#[cfg(not(any(test, feature = "software-emulation")))]
+pub trait PageTableAllocator: Sync {
+    /// Allocates a zeroed frame for a page table.
+    fn allocate_table(&self) -> Option<PhysicalAddress>;
+
+    /// Frees a page table frame.
+    ///
+    /// Besides the frames returned by [`allocate_table`](Self::allocate_table), this is also
+    /// handed the tables that were allocated from the heap before the allocator was installed.
+    fn free_table(&self, frame: PhysicalAddress);
+}
+
+/// The allocator installed with [`set_page_table_allocator`].
+#[cfg(not(any(test, feature = "software-emulation")))]
+static PAGE_TABLE_ALLOCATOR: spin::Once<&'static dyn PageTableAllocator> = spin::Once::new();
+
+/// Installs the allocator every [`PageDirectory`] takes its page tables from from now on.
+///
+/// Only the first call has any effect.
+#[cfg(not(any(test, feature = "software-emulation")))]
+pub fn set_page_table_allocator(allocator: &'static dyn PageTableAllocator) {
+    PAGE_TABLE_ALLOCATOR.call_once(|| allocator);
+}
+
+/// Allocates a new page table from the installed [`PageTableAllocator`], or from the heap
+/// before there is one.
 #[cfg(not(any(test, feature = "software-emulation")))]
 fn alloc_page_table() -> *mut PageTable {
-    Box::into_raw(Box::new(PageTable::new()))
+    match PAGE_TABLE_ALLOCATOR.get() {
+        Some(allocator) => {
+            let frame = allocator
+                .allocate_table()
+                .expect("out of memory for page tables");
+            VirtualAddress::direct_mapped(frame).as_mut_ptr()
+        }
+        None => Box::into_raw(Box::new(PageTable::new())),
+    }
+}
+
+/// Frees a page table allocated by `alloc_page_table()`.
+///
+/// Emulated memory has no individual free operation, so in test/software-emulation mode the
+/// table is simply abandoned.
+#[cfg(any(test, feature = "software-emulation"))]
+fn free_page_table(_table: *mut PageTable) {}
+
+/// Frees a page table allocated by `alloc_page_table()`.
+#[cfg(not(any(test, feature = "software-emulation")))]
+fn free_page_table(table: *mut PageTable) {
+    match PAGE_TABLE_ALLOCATOR.get() {
+        Some(allocator) => allocator.free_table(PhysicalAddress::from_direct_mapped(
+            VirtualAddress::from_ptr(table),
+        )),
+        // SAFETY: Without an installed allocator, alloc_page_table() used Box::into_raw.
+        None => unsafe { drop(Box::from_raw(table)) },
+    }
+}
+
+/// Returns the number of bytes of virtual address space covered by the root page table.
+pub(crate) fn address_space_size() -> usize {
+    arch::level_size(arch::page_table_levels())
+}
+
+/// Number of pages above which a [`TlbFlush`] flushes the whole TLB instead of single pages.
+const FULL_FLUSH_THRESHOLD: usize = 32;
+
+/// Errors returned by range operations on a [`PageDirectory`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    /// Part of the range is already mapped, starting at the given address.
+    AlreadyMapped(VirtualAddress),
+    /// The range starts or ends inside the huge page at the given address, which cannot be
+    /// partially unmapped.
+    PartialHugePage(VirtualAddress),
+    /// The range does not fit within one half of the virtual address space.
+    OutOfRange,
+}
+
+/// A pending TLB flush for a range whose mappings have changed.
+///
+/// Returned by the range operations of [`PageDirectory`]. Changes are not guaranteed to be
+/// visible to the CPU until [`flush`](Self::flush) is called. Ranges of up to
+/// `FULL_FLUSH_THRESHOLD` pages are invalidated page by page with `invlpg`; larger ranges
+/// use a single full flush.
+#[must_use = "page table changes are not visible until the TLB is flushed"]
+#[derive(Debug)]
+pub struct TlbFlush {
+    start: VirtualAddress,
+    size: usize,
+}
+
+impl TlbFlush {
+    /// Creates a flush covering `size` bytes starting at `start`.
+    pub(crate) fn new(start: VirtualAddress, size: usize) -> Self {
+        Self { start, size }
+    }
+
+    /// Returns true if this flush invalidates the whole TLB rather than individual pages.
+    pub fn is_full(&self) -> bool {
+        self.size / arch::PAGE_SIZE > FULL_FLUSH_THRESHOLD
+    }
+
+    /// Invalidates the TLB entries for the changed range on the current CPU.
+    pub fn flush(self) {
+        if self.is_full() {
+            arch::flush_tlb_all();
+        } else {
+            for offset in (0..self.size).step_by(arch::PAGE_SIZE) {
+                arch::flush_tlb_page(self.start.as_usize() + offset);
+            }
+        }
+    }
+
+    /// Discards this flush without invalidating anything.
+    ///
+    /// Only correct when the page directory is not active on any CPU, or when the caller
+    /// flushes the TLB some other way.
+    pub fn ignore(self) {}
+}
+
+/// Size of a page mapped by a single leaf entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PageSize {
+    /// A base page, mapped at level 0 (4 KiB on x86_64).
+    Base,
+    /// A large page, mapped with a huge leaf entry at level 1 (2 MiB on x86_64).
+    Large,
+    /// A huge page, mapped with a huge leaf entry at level 2 (1 GiB on x86_64).
+    Huge,
+}
+
+impl PageSize {
+    /// Returns the page table level a leaf entry of this size lives at.
+    pub const fn level(self) -> usize {
+        match self {
+            PageSize::Base => 0,
+            PageSize::Large => 1,
+            PageSize::Huge => 2,
+        }
+    }
+
+    /// Returns the number of bytes mapped by a page of this size.
+    pub const fn bytes(self) -> usize {
+        arch::level_size(self.level())
+    }
+
+    /// Returns the size of a page mapped by a leaf entry at the given level, if leaves are
+    /// allowed there.
+    pub const fn from_level(level: usize) -> Option<Self> {
+        match level {
+            0 => Some(PageSize::Base),
+            1 => Some(PageSize::Large),
+            2 => Some(PageSize::Huge),
+            _ => None,
+        }
+    }
+}
+
+/// A contiguous range of virtual memory mapped to contiguous physical memory with identical
+/// flags, as yielded by [`PageDirectory::mappings`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    /// First virtual address of the range.
+    pub virt: VirtualAddress,
+    /// Physical address `virt` maps to.
+    pub phys: PhysicalAddress,
+    /// Length of the range in bytes.
+    pub size: usize,
+    /// Flags shared by every page in the range.
+    pub flags: PageFlags,
+}
+
+impl Mapping {
+    /// Returns true if `next` starts where this mapping ends and can be merged into it.
+    fn continues_with(&self, next: &Mapping) -> bool {
+        self.virt.as_usize().checked_add(self.size) == Some(next.virt.as_usize())
+            && self.phys.as_usize() + self.size == next.phys.as_usize()
+            && self.flags == next.flags
+    }
+}
+
+/// Iterator over the mapped ranges of a [`PageDirectory`], in ascending virtual address order.
+///
+/// Adjacent pages are merged into a single [`Mapping`] when they are contiguous in both
+/// virtual and physical memory and share the same flags, regardless of page size.
+pub struct Mappings<'a> {
+    /// Table being scanned at each level, together with the next index to visit.
+    tables: [(*const PageTable, usize); arch::MAX_PAGE_TABLE_LEVELS],
+    /// Virtual address covered by the first entry of the table at each level.
+    bases: [usize; arch::MAX_PAGE_TABLE_LEVELS],
+    /// Level currently being scanned.
+    level: usize,
+    /// Mapping built so far, not yet yielded.
+    pending: Option<Mapping>,
+    _directory: PhantomData<&'a PageDirectory>,
+}
+
+impl Mappings<'_> {
+    /// Returns the next present leaf entry as a single-page mapping.
+    fn next_leaf(&mut self) -> Option<Mapping> {
+        loop {
+            let (table_ptr, index) = self.tables[self.level];
+            // SAFETY: Every table on the stack was reached through a present, non-leaf entry
+            // of the directory borrowed for the lifetime of this iterator.
+            let table = unsafe { &*table_ptr };
+
+            if index == table.len() {
+                if self.level == arch::page_table_levels() - 1 {
+                    return None;
+                }
+                self.level += 1;
+                continue;
+            }
+            self.tables[self.level].1 += 1;
+
+            let entry = table.entry(index);
+            if !entry.is_present() {
+                continue;
+            }
+
+            let virt = self.bases[self.level] + index * arch::level_size(self.level);
+            if self.level == 0 || entry.is_leaf() {
+                return Some(Mapping {
+                    virt: VirtualAddress::new(arch::canonicalize_virtual(virt)),
+                    phys: entry.address_at(self.level)?,
+                    size: arch::level_size(self.level),
+                    flags: entry.flags_at(self.level),
+                });
+            }
+
+            // SAFETY: The entry is present and not a leaf, so it points to a page table.
+            let next: *const PageTable = unsafe { PageDirectory::next_table(&entry) };
+            self.level -= 1;
+            self.tables[self.level] = (next, 0);
+            self.bases[self.level] = virt;
+        }
+    }
+}
+
+impl Iterator for Mappings<'_> {
+    type Item = Mapping;
+
+    fn next(&mut self) -> Option<Mapping> {
+        loop {
+            let Some(leaf) = self.next_leaf() else {
+                return self.pending.take();
+            };
+
+            match &mut self.pending {
+                Some(pending) if pending.continues_with(&leaf) => pending.size += leaf.size,
+                pending => {
+                    if let Some(done) = pending.replace(leaf) {
+                        return Some(done);
+                    }
+                }
+            }
+        }
+    }
 }
 
 /// An architecture-independent page table manager.
@@ -51,8 +311,9 @@ fn alloc_page_table() -> *mut PageTable {
 /// virtual addresses to physical addresses. It handles walking the page table hierarchy
 /// and allocating intermediate tables as needed.
 ///
-/// The root page table may be owned (heap-allocated, freed on drop) or borrowed
-/// (pointing to existing page tables, e.g. those set up by the bootloader).
+/// The root page table may be owned (heap-allocated, freed on drop together with every table
+/// below it that the directory allocated) or borrowed (pointing to existing page tables, e.g.
+/// those set up by the bootloader, which stay in place on drop).
 pub struct PageDirectory {
     /// Raw pointer to the root page table.
     ///
@@ -70,11 +331,12 @@ unsafe impl Sync for PageDirectory {}
 
 impl Drop for PageDirectory {
     fn drop(&mut self) {
-        // In test/software-emulation mode, emulated memory has no individual free operation.
-        #[cfg(not(any(test, feature = "software-emulation")))]
         if self.owns_root {
-            // SAFETY: alloc_page_table() used Box::into_raw; reclaim with Box::from_raw.
-            unsafe { drop(Box::from_raw(self.root)) };
+            // SAFETY: The root pointer is valid, and nothing uses the tables once the directory
+            // is gone.
+            let root = unsafe { &mut *self.root };
+            Self::free_owned_tables(root, arch::page_table_levels() - 1);
+            free_page_table(self.root);
         }
     }
 }
@@ -112,6 +374,12 @@ impl PageDirectory {
         }
     }
 
+    /// Returns the root page table.
+    pub fn root_table(&self) -> &PageTable {
+        // SAFETY: root is a valid PageTable pointer (either owned or borrowed from Limine).
+        unsafe { &*self.root }
+    }
+
     /// Maps a virtual address to a physical address with the given flags.
     ///
     /// This function walks the page table hierarchy, allocating intermediate tables
@@ -119,9 +387,64 @@ impl PageDirectory {
     /// to the physical address.
     ///
     /// # Panics
-    /// Panics if the virtual address is not page-aligned or if the physical address
-    /// is not page-aligned.
+    /// Panics if the virtual address is not page-aligned, if the physical address
+    /// is not page-aligned, or if the virtual address is already covered by a huge page.
     pub fn map(&mut self, virt: VirtualAddress, phys: PhysicalAddress, flags: PageFlags) {
+        self.map_huge(virt, phys, PageSize::Base, flags);
+    }
+
+    /// Maps a single page of the given size.
+    ///
+    /// [`PageSize::Base`] pages are mapped at level 0 like [`map`](Self::map). Larger pages are
+    /// mapped with a huge leaf entry at level 1 or 2, covering [`PageSize::bytes`] bytes with a
+    /// single entry.
+    ///
+    /// # Panics
+    /// Panics if either address is not aligned to the page size, if the virtual address is
+    /// already covered by a larger huge page, or if a huge page would replace an existing
+    /// page table.
+    pub fn map_huge(
+        &mut self,
+        virt: VirtualAddress,
+        phys: PhysicalAddress,
+        size: PageSize,
+        flags: PageFlags,
+    ) {
+        assert!(
+            virt.is_aligned(size.bytes()),
+            "virtual address must be aligned to the page size"
+        );
+        assert!(
+            phys.is_aligned(size.bytes()),
+            "physical address must be aligned to the page size"
+        );
+
+        let entry = self.walk_or_create(virt, size.level());
+        assert!(
+            size == PageSize::Base || !entry.is_present() || entry.is_leaf(),
+            "cannot replace a page table with a huge page"
+        );
+        *entry = Self::leaf_entry(phys, size, flags);
+    }
+
+    /// Maps a contiguous range of `size` bytes starting at the given addresses.
+    ///
+    /// The range is covered with the largest pages that the alignment of both addresses and
+    /// the remaining length allow, so large regions use huge pages where possible. A huge page
+    /// is never placed over an existing page table; the range falls back to smaller pages there.
+    ///
+    /// Fails without changing anything if any part of the range is already mapped. On success,
+    /// returns the TLB flush that makes the new mappings visible.
+    ///
+    /// # Panics
+    /// Panics if either address or the size is not page-aligned.
+    pub fn map_range(
+        &mut self,
+        virt: VirtualAddress,
+        phys: PhysicalAddress,
+        size: usize,
+        flags: PageFlags,
+    ) -> Result<TlbFlush, MapError> {
         assert!(
             virt.is_aligned(arch::PAGE_SIZE),
             "virtual address must be page-aligned"
@@ -130,11 +453,69 @@ impl PageDirectory {
             phys.is_aligned(arch::PAGE_SIZE),
             "physical address must be page-aligned"
         );
+        assert!(
+            size.is_multiple_of(arch::PAGE_SIZE),
+            "size must be page-aligned"
+        );
 
-        let entry = self.walk_or_create(virt);
-        let mut new_flags = flags;
-        new_flags.set_present(true);
-        *entry = PageEntry::new(phys, new_flags);
+        let (start, end) = Self::range_bounds(virt, size)?;
+        if let Some(mapped) = self.first_mapped(start, end) {
+            return Err(MapError::AlreadyMapped(mapped));
+        }
+
+        let mut offset = 0;
+        while offset < size {
+            let virt = VirtualAddress::new(virt.as_usize() + offset);
+            let phys = PhysicalAddress::new(phys.as_usize() + offset);
+            let remaining = size - offset;
+
+            let page_size = [PageSize::Huge, PageSize::Large]
+                .into_iter()
+                .find(|&page_size| {
+                    let bytes = page_size.bytes();
+                    bytes <= remaining
+                        && virt.is_aligned(bytes)
+                        && phys.is_aligned(bytes)
+                        && self.can_map_huge(virt, page_size)
+                })
+                .unwrap_or(PageSize::Base);
+
+            self.map_huge(virt, phys, page_size, flags);
+            offset += page_size.bytes();
+        }
+
+        Ok(TlbFlush::new(virt, size))
+    }
+
+    /// Unmaps every page in a range of `size` bytes starting at `virt`.
+    ///
+    /// Unmapped holes inside the range are skipped. Page tables allocated by this directory
+    /// that become empty are freed, and the entries pointing at them cleared. Tables borrowed
+    /// from the bootloader are never freed.
+    ///
+    /// Fails without changing anything if the range starts or ends inside a huge page. On
+    /// success, returns the TLB flush that makes the removal visible.
+    ///
+    /// # Panics
+    /// Panics if the address or the size is not page-aligned.
+    pub fn unmap_range(&mut self, virt: VirtualAddress, size: usize) -> Result<TlbFlush, MapError> {
+        assert!(
+            virt.is_aligned(arch::PAGE_SIZE),
+            "virtual address must be page-aligned"
+        );
+        assert!(
+            size.is_multiple_of(arch::PAGE_SIZE),
+            "size must be page-aligned"
+        );
+
+        let (start, end) = Self::range_bounds(virt, size)?;
+        self.check_huge_page_boundaries(start, end)?;
+
+        // SAFETY: root is a valid PageTable pointer (either owned or borrowed from Limine).
+        let root = unsafe { &mut *self.root };
+        Self::unmap_table(root, arch::page_table_levels() - 1, 0, start, end);
+
+        Ok(TlbFlush::new(virt, size))
     }
 
     /// Unmaps a virtual address.
@@ -143,6 +524,9 @@ impl PageDirectory {
     /// given virtual address. Returns the physical address that was mapped, or
     /// None if the address was not mapped.
     ///
+    /// If the address falls inside a huge page, the whole huge page is unmapped and the
+    /// physical address of its first byte is returned.
+    ///
     /// # Panics
     /// Panics if the virtual address is not page-aligned.
     pub fn unmap(&mut self, virt: VirtualAddress) -> Option<PhysicalAddress> {
@@ -151,93 +535,418 @@ impl PageDirectory {
             "virtual address must be page-aligned"
         );
 
-        let entry = self.walk(virt)?;
-        let phys = entry.address()?;
+        let (entry, level) = self.walk(virt);
+        let phys = entry.address_at(level)?;
         entry.clear();
 
         Some(phys)
     }
 
+    /// Translates a virtual address using this page directory.
+    ///
+    /// Returns the physical address `virt` maps to (including its offset within the page),
+    /// together with the flags and size of the page that maps it, or None if the address is
+    /// not mapped.
+    pub fn translate(
+        &self,
+        virt: VirtualAddress,
+    ) -> Option<(PhysicalAddress, PageFlags, PageSize)> {
+        let (entry, level) = self.lookup(virt.as_usize());
+        let phys = entry.address_at(level)?;
+        let size = PageSize::from_level(level)?;
+        let offset = virt.as_usize() & (size.bytes() - 1);
+        Some((
+            PhysicalAddress::new(phys.as_usize() + offset),
+            entry.flags_at(level),
+            size,
+        ))
+    }
+
+    /// Returns an iterator over the mapped ranges of this page directory.
+    ///
+    /// See [`Mappings`] for how pages are merged into ranges.
+    pub fn mappings(&self) -> Mappings<'_> {
+        Mappings {
+            tables: [(self.root as *const PageTable, 0); arch::MAX_PAGE_TABLE_LEVELS],
+            bases: [0; arch::MAX_PAGE_TABLE_LEVELS],
+            level: arch::page_table_levels() - 1,
+            pending: None,
+            _directory: PhantomData,
+        }
+    }
+
+    /// Returns the physical frames holding this directory's page tables, root first.
+    ///
+    /// This includes tables that the directory does not own, such as those set up by the
+    /// bootloader.
+    pub fn table_frames(&self) -> Vec<PhysicalAddress> {
+        let mut frames = Vec::new();
+        // SAFETY: The root pointer is valid for the lifetime of the directory.
+        let root = unsafe { &*self.root };
+        Self::collect_table_frames(root, arch::page_table_levels() - 1, &mut frames);
+        frames
+    }
+
+    /// Changes the flags of every page mapped in a range of `size` bytes starting at `virt`.
+    ///
+    /// Unmapped holes inside the range are skipped. Fails without changing anything if the
+    /// range starts or ends inside a huge page. On success, returns the TLB flush that makes
+    /// the new flags visible.
+    ///
+    /// # Panics
+    /// Panics if the address or the size is not page-aligned.
+    pub fn protect_range(
+        &mut self,
+        virt: VirtualAddress,
+        size: usize,
+        flags: PageFlags,
+    ) -> Result<TlbFlush, MapError> {
+        assert!(
+            virt.is_aligned(arch::PAGE_SIZE),
+            "virtual address must be page-aligned"
+        );
+        assert!(
+            size.is_multiple_of(arch::PAGE_SIZE),
+            "size must be page-aligned"
+        );
+
+        let (start, end) = Self::range_bounds(virt, size)?;
+        self.check_huge_page_boundaries(start, end)?;
+
+        let mut new_flags = flags;
+        new_flags.set_present(true);
+
+        let mut addr = start;
+        while addr < end {
+            let (entry, level) = self.walk(VirtualAddress::new(arch::canonicalize_virtual(addr)));
+            if entry.is_present() {
+                entry.set_flags_at(level, new_flags);
+            }
+            addr = (addr | (arch::level_size(level) - 1)) + 1;
+        }
+
+        Ok(TlbFlush::new(virt, size))
+    }
+
+    /// Splits the huge page covering `virt` into pages of the next smaller size, repeating
+    /// until `virt` is mapped by a base page.
+    ///
+    /// Every address keeps translating to the same physical address with the same flags, but
+    /// the pages around `virt` can then be unmapped or protected on their own. The new page
+    /// tables are owned by this directory. Nothing changes if `virt` is unmapped or already
+    /// mapped by a base page. Returns the TLB flush that drops the huge page.
+    ///
+    /// # Panics
+    /// Panics if the address is not page-aligned.
+    pub fn split_huge_page(&mut self, virt: VirtualAddress) -> TlbFlush {
+        assert!(
+            virt.is_aligned(arch::PAGE_SIZE),
+            "virtual address must be page-aligned"
+        );
+
+        let virt_addr = virt.as_usize();
+        let mut flush = TlbFlush::new(virt, 0);
+        loop {
+            let (entry, level) = self.walk(virt);
+            if level == 0 || !entry.is_leaf() {
+                return flush;
+            }
+            let Some(phys) = entry.address_at(level) else {
+                return flush;
+            };
+            let flags = entry.flags_at(level);
+            let size = PageSize::from_level(level - 1).expect("huge pages split into leaves");
+
+            let table_ptr = alloc_page_table();
+            // SAFETY: alloc_page_table() returns a valid, zeroed page table.
+            let table = unsafe { &mut *table_ptr };
+            for index in 0..table.len() {
+                let page_phys = PhysicalAddress::new(phys.as_usize() + index * size.bytes());
+                *table.entry_mut(index) = Self::leaf_entry(page_phys, size, flags);
+            }
+            *entry = Self::table_entry(table_ptr, virt_addr);
+
+            if flush.size == 0 {
+                let bytes = arch::level_size(level);
+                let start = arch::canonicalize_virtual(virt_addr & !(bytes - 1));
+                flush = TlbFlush::new(VirtualAddress::new(start), bytes);
+            }
+        }
+    }
+
+    /// Fails if a range of `size` bytes starting at `virt` is out of range, or starts or ends
+    /// inside a huge page.
+    pub(crate) fn check_range(&self, virt: VirtualAddress, size: usize) -> Result<(), MapError> {
+        let (start, end) = Self::range_bounds(virt, size)?;
+        self.check_huge_page_boundaries(start, end)
+    }
+
+    /// Fails if the range `[start, end)` starts or ends inside a huge page.
+    fn check_huge_page_boundaries(&self, start: usize, end: usize) -> Result<(), MapError> {
+        if start == end {
+            return Ok(());
+        }
+
+        for addr in [start, end - arch::PAGE_SIZE] {
+            let (entry, level) = self.lookup(addr);
+            let block = arch::level_size(level);
+            let block_start = addr & !(block - 1);
+            if entry.is_present() && (block_start < start || block_start + block > end) {
+                return Err(MapError::PartialHugePage(VirtualAddress::new(
+                    arch::canonicalize_virtual(block_start),
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Converts a range to bounds within the index space of the root table.
+    ///
+    /// Upper-half addresses are stripped of their sign extension, so the bounds can be compared
+    /// against the addresses covered by each table entry.
+    fn range_bounds(virt: VirtualAddress, size: usize) -> Result<(usize, usize), MapError> {
+        let space_size = address_space_size();
+        let start = virt.as_usize() & (space_size - 1);
+        let end = start.checked_add(size).ok_or(MapError::OutOfRange)?;
+        let half = space_size / 2;
+        if end > space_size || (start < half && end > half) {
+            return Err(MapError::OutOfRange);
+        }
+        Ok((start, end))
+    }
+
+    /// Returns the first mapped address within `[start, end)`, if any.
+    fn first_mapped(&self, start: usize, end: usize) -> Option<VirtualAddress> {
+        let mut addr = start;
+        while addr < end {
+            let (entry, level) = self.lookup(addr);
+            if entry.is_present() {
+                return Some(VirtualAddress::new(arch::canonicalize_virtual(addr)));
+            }
+            // Nothing is mapped up to the end of the region covered by this entry.
+            addr = (addr | (arch::level_size(level) - 1)) + 1;
+        }
+        None
+    }
+
+    /// Clears every leaf entry within `[start, end)` below `table`, freeing owned tables that
+    /// become empty.
+    ///
+    /// `base` is the first address covered by `table`, which lives at `level`. Returns true if
+    /// `table` has no present entries left.
+    fn unmap_table(
+        table: &mut PageTable,
+        level: usize,
+        base: usize,
+        start: usize,
+        end: usize,
+    ) -> bool {
+        let entry_size = arch::level_size(level);
+        let first = (start.max(base) - base) / entry_size;
+        let last = (end.min(base + entry_size * table.len()) - base).div_ceil(entry_size);
+
+        for index in first..last {
+            let entry = table.entry_mut(index);
+            if !entry.is_present() {
+                continue;
+            }
+            if level == 0 || entry.is_leaf() {
+                entry.clear();
+                continue;
+            }
+
+            // SAFETY: The entry is present and not a leaf, so it points to a page table.
+            let child = unsafe { Self::next_table(entry) };
+            let child_base = base + index * entry_size;
+            if Self::unmap_table(child, level - 1, child_base, start, end) && entry.is_owned_table()
+            {
+                entry.clear();
+                free_page_table(child);
+            }
+        }
+
+        (0..table.len()).all(|index| !table.entry(index).is_present())
+    }
+
+    /// Frees every owned table below `table`, which lives at `level`. The frames mapped by the
+    /// tables are left alone.
+    fn free_owned_tables(table: &mut PageTable, level: usize) {
+        if level == 0 {
+            return;
+        }
+
+        for index in 0..table.len() {
+            let entry = table.entry_mut(index);
+            if entry.is_owned_table() {
+                // SAFETY: The entry is present and not a leaf, so it points to a page table.
+                let child = unsafe { Self::next_table(entry) };
+                Self::free_owned_tables(child, level - 1);
+                entry.clear();
+                free_page_table(child);
+            }
+        }
+    }
+
+    /// Appends the frame of `table`, which lives at `level`, and of every table below it.
+    fn collect_table_frames(table: &PageTable, level: usize, frames: &mut Vec<PhysicalAddress>) {
+        frames.push(table.physical_address());
+        if level == 0 {
+            return;
+        }
+
+        for index in 0..table.len() {
+            let entry = table.entry(index);
+            if entry.is_present() && !entry.is_leaf() {
+                // SAFETY: The entry is present and not a leaf, so it points to a page table.
+                let child = unsafe { Self::next_table(&entry) };
+                Self::collect_table_frames(child, level - 1, frames);
+            }
+        }
+    }
+
+    /// Builds a present leaf entry for a page of the given size.
+    fn leaf_entry(phys: PhysicalAddress, size: PageSize, flags: PageFlags) -> PageEntry {
+        let mut new_flags = flags;
+        new_flags.set_present(true);
+        match size {
+            PageSize::Base => PageEntry::new(phys, new_flags),
+            PageSize::Large | PageSize::Huge => PageEntry::new_huge(phys, new_flags),
+        }
+    }
+
+    /// Builds an entry pointing at `table`, a table allocated by `alloc_page_table()` to map
+    /// part of the address space around `virt_addr`.
+    fn table_entry(table: *mut PageTable, virt_addr: usize) -> PageEntry {
+        // alloc_page_table() returns a *mut PageTable pointing to a zeroed, page-aligned
+        // allocation whose physical address (via virt_to_phys) is the address the CPU will use
+        // to walk the hierarchy.
+        let translator = AddressTranslator::current();
+        let table_phys = PhysicalAddress::new(translator.virt_to_phys(table as usize));
+
+        let mut flags = PageFlags::empty();
+        flags.set_present(true);
+        // Intermediate entries must be writable for writes to propagate through the
+        // hierarchy; x86_64 CR0.WP enforces the writable bit at every level.
+        flags.set_writable(true);
+        // Likewise the user bit is enforced at every level, so tables in the lower
+        // (user) half are user-accessible and leave the decision to the leaf entries.
+        flags.set_user(virt_addr < address_space_size() / 2);
+
+        let mut entry = PageEntry::new(table_phys, flags);
+        entry.set_owned_table();
+        entry
+    }
+
+    /// Returns true if a huge page of the given size could be mapped at `virt` without
+    /// replacing an existing page table or landing inside a larger huge page.
+    fn can_map_huge(&self, virt: VirtualAddress, size: PageSize) -> bool {
+        let (entry, level) = self.lookup(virt.as_usize());
+        if level > size.level() {
+            // Either an intermediate table above the target level is missing, or a larger
+            // huge page covers the address.
+            !entry.is_present()
+        } else {
+            level == size.level()
+        }
+    }
+
+    /// Returns a reference to the next-level page table an entry points to.
+    ///
+    /// # Safety
+    /// The entry must be present and point to a page table (not a huge page).
+    unsafe fn next_table<'a>(entry: &PageEntry) -> &'a mut PageTable {
+        let next_table_phys = entry.address().expect("entry should be present");
+        let translator = AddressTranslator::current();
+        let next_table_virt_raw = translator.phys_to_virt(next_table_phys.as_usize());
+
+        // SAFETY: The entry contains a valid physical address of a page table.
+        // PageTable is repr(transparent) over the 512-entry array, so casting the
+        // HHDM virtual address to *mut PageTable is correct for both Limine-allocated
+        // and kernel-allocated sub-tables.
+        unsafe { &mut *(next_table_virt_raw as *mut PageTable) }
+    }
+
+    /// Walks the page table hierarchy without modifying it, returning a copy of the entry for
+    /// a virtual address and the level it was found at.
+    ///
+    /// Like [`walk`](Self::walk), stops early at huge leaf entries and at non-present entries.
+    fn lookup(&self, virt_addr: usize) -> (PageEntry, usize) {
+        // SAFETY: root is a valid PageTable pointer (either owned or borrowed from Limine).
+        let mut table = unsafe { &*self.root };
+
+        for level in (1..arch::page_table_levels()).rev() {
+            let entry = table.entry(arch::page_index(virt_addr, level));
+            if !entry.is_present() || entry.is_leaf() {
+                return (entry, level);
+            }
+
+            // SAFETY: The entry is present and not a leaf, so it points to a page table.
+            table = unsafe { Self::next_table(&entry) };
+        }
+
+        (table.entry(arch::page_index(virt_addr, 0)), 0)
+    }
+
     /// Walks the page table hierarchy to find the entry for a virtual address.
     ///
-    /// Returns None if any intermediate table is not present.
-    fn walk(&mut self, virt: VirtualAddress) -> Option<&mut PageEntry> {
+    /// Returns the entry together with the level it was found at. The walk stops early at huge
+    /// leaf entries, and at non-present entries where an intermediate table is missing.
+    fn walk(&mut self, virt: VirtualAddress) -> (&mut PageEntry, usize) {
         // SAFETY: root is a valid PageTable pointer (either owned or borrowed from Limine).
         let mut table = unsafe { &mut *self.root };
         let virt_addr = virt.as_usize();
 
         // Walk through all levels except the last
-        for level in (1..arch::PAGE_TABLE_LEVELS).rev() {
+        for level in (1..arch::page_table_levels()).rev() {
             let index = arch::page_index(virt_addr, level);
             let entry = table.entry_mut(index);
 
-            if !entry.is_present() {
-                // Intermediate table doesn't exist
-                return None;
+            if !entry.is_present() || entry.is_leaf() {
+                // Either a huge page, or the intermediate table doesn't exist
+                return (entry, level);
             }
 
-            let next_table_phys = entry.address()?;
-            let translator = AddressTranslator::current();
-            let next_table_virt_raw = translator.phys_to_virt(next_table_phys.as_usize());
-
-            // SAFETY: The entry contains a valid physical address of a page table.
-            // PageTable is repr(transparent) over the 512-entry array, so casting the
-            // HHDM virtual address to *mut PageTable is correct for both Limine-allocated
-            // and kernel-allocated sub-tables.
-            table = unsafe { &mut *(next_table_virt_raw as *mut PageTable) };
+            // SAFETY: The entry is present and not a leaf, so it points to a page table.
+            table = unsafe { Self::next_table(entry) };
         }
 
         let index = arch::page_index(virt_addr, 0);
-        Some(table.entry_mut(index))
+        (table.entry_mut(index), 0)
     }
 
-    /// Walks the page table hierarchy, creating intermediate tables as needed.
+    /// Walks the page table hierarchy down to `target_level`, creating intermediate tables as
+    /// needed.
     ///
-    /// Returns a mutable reference to the final page table entry for the given
-    /// virtual address.
-    fn walk_or_create(&mut self, virt: VirtualAddress) -> &mut PageEntry {
+    /// Returns a mutable reference to the entry for the given virtual address at that level.
+    ///
+    /// # Panics
+    /// Panics if a huge page is found above the target level.
+    fn walk_or_create(&mut self, virt: VirtualAddress, target_level: usize) -> &mut PageEntry {
         // SAFETY: root is a valid PageTable pointer (either owned or borrowed from Limine).
         let mut table = unsafe { &mut *self.root };
         let virt_addr = virt.as_usize();
 
-        // Walk through all levels except the last
-        for level in (1..arch::PAGE_TABLE_LEVELS).rev() {
+        // Walk through all levels above the target
+        for level in (target_level + 1..arch::page_table_levels()).rev() {
             let index = arch::page_index(virt_addr, level);
             let entry = table.entry_mut(index);
 
             if !entry.is_present() {
-                // Allocate a new page table.
-                // alloc_page_table() returns a *mut PageTable pointing to a zeroed,
-                // page-aligned allocation whose physical address (via virt_to_phys) is
-                // the address the CPU will use to walk the hierarchy.
-                let new_table_ptr = alloc_page_table();
-                let new_table_virt_raw = new_table_ptr as usize;
-
-                let translator = AddressTranslator::current();
-                let new_table_phys =
-                    PhysicalAddress::new(translator.virt_to_phys(new_table_virt_raw));
-
-                let mut flags = PageFlags::empty();
-                flags.set_present(true);
-                // Intermediate entries must be writable for writes to propagate through the
-                // hierarchy; x86_64 CR0.WP enforces the writable bit at every level.
-                flags.set_writable(true);
-
-                *entry = PageEntry::new(new_table_phys, flags);
+                *entry = Self::table_entry(alloc_page_table(), virt_addr);
             }
 
-            let next_table_phys = entry.address().expect("entry should be present");
-            let translator = AddressTranslator::current();
-            let next_table_virt_raw = translator.phys_to_virt(next_table_phys.as_usize());
+            assert!(
+                !entry.is_leaf(),
+                "virtual address {} is already covered by a huge page",
+                virt
+            );
 
-            // SAFETY: The entry contains a valid physical address of a page table.
-            // PageTable is repr(transparent) over the 512-entry array, so this cast is correct
-            // for both Limine-allocated and freshly-allocated sub-tables.
-            table = unsafe { &mut *(next_table_virt_raw as *mut PageTable) };
+            // SAFETY: The entry is present and not a leaf, so it points to a page table.
+            table = unsafe { Self::next_table(entry) };
         }
 
-        // Return the entry at level 0
-        let index = arch::page_index(virt_addr, 0);
+        // Return the entry at the target level
+        let index = arch::page_index(virt_addr, target_level);
         table.entry_mut(index)
     }
 }
@@ -321,4 +1030,982 @@ mod tests {
             dir.map(virt, phys, flags);
         }
     }
+
+    fn writable() -> PageFlags {
+        let mut flags = PageFlags::empty();
+        flags.set_present(true);
+        flags.set_writable(true);
+        flags
+    }
+
+    #[test]
+    fn page_sizes_follow_levels() {
+        assert_eq!(PageSize::Base.bytes(), arch::PAGE_SIZE);
+        assert_eq!(PageSize::Large.bytes(), arch::level_size(1));
+        assert_eq!(PageSize::Huge.bytes(), arch::level_size(2));
+    }
+
+    #[test]
+    fn map_huge_creates_leaf_entry() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let virt = VirtualAddress::new(PageSize::Large.bytes() * 3);
+        let phys = PhysicalAddress::new(PageSize::Large.bytes() * 5);
+        dir.map_huge(virt, phys, PageSize::Large, writable());
+
+        let (entry, level) = dir.walk(VirtualAddress::new(virt.as_usize() + arch::PAGE_SIZE));
+        assert_eq!(level, 1);
+        assert!(entry.is_leaf());
+        assert_eq!(entry.address(), Some(phys));
+    }
+
+    #[test]
+    fn unmap_inside_huge_page_removes_it() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let virt = VirtualAddress::new(PageSize::Huge.bytes());
+        let phys = PhysicalAddress::new(PageSize::Huge.bytes() * 2);
+        dir.map_huge(virt, phys, PageSize::Huge, writable());
+
+        let inner = VirtualAddress::new(virt.as_usize() + PageSize::Large.bytes());
+        assert_eq!(dir.unmap(inner), Some(phys));
+        assert_eq!(dir.unmap(virt), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "covered by a huge page")]
+    fn map_inside_huge_page_panics() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let virt = VirtualAddress::new(PageSize::Large.bytes());
+        dir.map_huge(virt, PhysicalAddress::new(0), PageSize::Large, writable());
+        dir.map(virt, PhysicalAddress::new(0), writable());
+    }
+
+    #[test]
+    #[should_panic(expected = "aligned to the page size")]
+    fn map_huge_rejects_unaligned_address() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        dir.map_huge(
+            VirtualAddress::new(arch::PAGE_SIZE),
+            PhysicalAddress::new(0),
+            PageSize::Large,
+            writable(),
+        );
+    }
+
+    #[test]
+    fn map_range_uses_largest_pages() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        // One base page, then a large page, then one more base page.
+        let large = PageSize::Large.bytes();
+        let virt = VirtualAddress::new(large - arch::PAGE_SIZE);
+        let phys = PhysicalAddress::new(2 * large - arch::PAGE_SIZE);
+        dir.map_range(virt, phys, large + 2 * arch::PAGE_SIZE, writable())
+            .unwrap()
+            .ignore();
+
+        let (entry, level) = dir.walk(virt);
+        assert_eq!(level, 0);
+        assert_eq!(entry.address(), Some(phys));
+
+        let (entry, level) = dir.walk(VirtualAddress::new(large));
+        assert_eq!(level, 1);
+        assert!(entry.is_leaf());
+        assert_eq!(entry.address(), Some(PhysicalAddress::new(2 * large)));
+
+        let (entry, level) = dir.walk(VirtualAddress::new(2 * large));
+        assert_eq!(level, 0);
+        assert_eq!(entry.address(), Some(PhysicalAddress::new(3 * large)));
+    }
+
+    #[test]
+    fn map_range_uses_huge_pages() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let huge = PageSize::Huge.bytes();
+        dir.map_range(
+            VirtualAddress::new(huge),
+            PhysicalAddress::new(huge),
+            huge,
+            writable(),
+        )
+        .unwrap()
+        .ignore();
+
+        let (entry, level) = dir.walk(VirtualAddress::new(huge));
+        assert_eq!(level, 2);
+        assert!(entry.is_leaf());
+    }
+
+    #[test]
+    fn map_range_keeps_existing_tables() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        // A base page forces a level 0 table under the first large page slot, and `unmap`
+        // leaves the emptied table in place.
+        let large = PageSize::Large.bytes();
+        dir.map(
+            VirtualAddress::new(large),
+            PhysicalAddress::new(0),
+            writable(),
+        );
+        dir.unmap(VirtualAddress::new(large));
+        dir.map_range(
+            VirtualAddress::new(large),
+            PhysicalAddress::new(large),
+            large,
+            writable(),
+        )
+        .unwrap()
+        .ignore();
+
+        let (entry, level) = dir.walk(VirtualAddress::new(large));
+        assert_eq!(level, 0);
+        assert_eq!(entry.address(), Some(PhysicalAddress::new(large)));
+        let (entry, level) = dir.walk(VirtualAddress::new(2 * large - arch::PAGE_SIZE));
+        assert_eq!(level, 0);
+        assert_eq!(
+            entry.address(),
+            Some(PhysicalAddress::new(2 * large - arch::PAGE_SIZE))
+        );
+    }
+
+    fn read_only() -> PageFlags {
+        let mut flags = PageFlags::empty();
+        flags.set_present(true);
+        flags.set_no_execute(true);
+        flags
+    }
+
+    #[test]
+    fn translate_unmapped_address() {
+        setup();
+        let dir = PageDirectory::new();
+
+        assert_eq!(dir.translate(VirtualAddress::new(0x0100)), None);
+    }
+
+    #[test]
+    fn translate_base_page() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        dir.map(
+            VirtualAddress::new(0x0100),
+            PhysicalAddress::new(0x0200),
+            writable(),
+        );
+
+        let (phys, flags, size) = dir.translate(VirtualAddress::new(0x0103)).unwrap();
+        assert_eq!(phys, PhysicalAddress::new(0x0203));
+        assert_eq!(flags, writable());
+        assert_eq!(size, PageSize::Base);
+        assert_eq!(dir.translate(VirtualAddress::new(0x0110)), None);
+    }
+
+    #[test]
+    fn translate_huge_page_includes_offset() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let large = PageSize::Large.bytes();
+        dir.map_huge(
+            VirtualAddress::new(large),
+            PhysicalAddress::new(4 * large),
+            PageSize::Large,
+            read_only(),
+        );
+
+        let (phys, flags, size) = dir.translate(VirtualAddress::new(large + 0x42)).unwrap();
+        assert_eq!(phys, PhysicalAddress::new(4 * large + 0x42));
+        assert_eq!(flags, read_only());
+        assert_eq!(size, PageSize::Large);
+    }
+
+    #[test]
+    fn translate_after_unmap() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let virt = VirtualAddress::new(0x0100);
+        dir.map(virt, PhysicalAddress::new(0x0200), writable());
+        dir.unmap(virt);
+
+        assert_eq!(dir.translate(virt), None);
+    }
+
+    #[test]
+    fn mappings_of_empty_directory() {
+        setup();
+        let dir = PageDirectory::new();
+
+        assert_eq!(dir.mappings().count(), 0);
+    }
+
+    #[test]
+    fn mappings_merge_contiguous_pages() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        // Crosses a level 0 table boundary and switches from base pages to a large page.
+        let large = PageSize::Large.bytes();
+        let virt = VirtualAddress::new(large - 2 * arch::PAGE_SIZE);
+        let phys = PhysicalAddress::new(2 * large - 2 * arch::PAGE_SIZE);
+        dir.map_range(virt, phys, large + 2 * arch::PAGE_SIZE, writable())
+            .unwrap()
+            .ignore();
+
+        let mappings: Vec<_> = dir.mappings().collect();
+        assert_eq!(
+            mappings,
+            [Mapping {
+                virt,
+                phys,
+                size: large + 2 * arch::PAGE_SIZE,
+                flags: writable(),
+            }]
+        );
+    }
+
+    #[test]
+    fn mappings_split_on_flags_and_discontinuities() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let page = arch::PAGE_SIZE;
+        dir.map(
+            VirtualAddress::new(0x0100),
+            PhysicalAddress::new(0x0400),
+            writable(),
+        );
+        dir.map(
+            VirtualAddress::new(0x0110),
+            PhysicalAddress::new(0x0410),
+            read_only(),
+        );
+        dir.map(
+            VirtualAddress::new(0x0120),
+            PhysicalAddress::new(0x0800),
+            read_only(),
+        );
+        dir.map(
+            VirtualAddress::new(0x0140),
+            PhysicalAddress::new(0x0820),
+            read_only(),
+        );
+
+        let mappings: Vec<_> = dir
+            .mappings()
+            .map(|m| (m.virt.as_usize(), m.phys.as_usize(), m.size))
+            .collect();
+        assert_eq!(
+            mappings,
+            [
+                (0x0100, 0x0400, page),
+                (0x0110, 0x0410, page),
+                (0x0120, 0x0800, page),
+                (0x0140, 0x0820, page),
+            ]
+        );
+    }
+
+    #[test]
+    fn mappings_report_canonical_upper_half() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let virt = VirtualAddress::new(arch::canonicalize_virtual(0x8000));
+        dir.map(virt, PhysicalAddress::new(0x0200), writable());
+
+        let mapping = dir.mappings().next().unwrap();
+        assert_eq!(mapping.virt, virt);
+        assert_eq!(
+            dir.translate(virt).map(|(phys, _, _)| phys),
+            Some(mapping.phys)
+        );
+    }
+
+    #[test]
+    fn map_range_rejects_existing_mappings() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let page = arch::PAGE_SIZE;
+        dir.map(
+            VirtualAddress::new(0x0120),
+            PhysicalAddress::new(0x0800),
+            writable(),
+        );
+
+        let result = dir.map_range(
+            VirtualAddress::new(0x0100),
+            PhysicalAddress::new(0x0400),
+            4 * page,
+            writable(),
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            MapError::AlreadyMapped(VirtualAddress::new(0x0120))
+        );
+        // Nothing else was mapped.
+        assert_eq!(dir.mappings().count(), 1);
+    }
+
+    #[test]
+    fn map_range_rejects_overlapping_huge_page() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let large = PageSize::Large.bytes();
+        dir.map_huge(
+            VirtualAddress::new(large),
+            PhysicalAddress::new(large),
+            PageSize::Large,
+            writable(),
+        );
+
+        let result = dir.map_range(
+            VirtualAddress::new(large - arch::PAGE_SIZE),
+            PhysicalAddress::new(0),
+            2 * arch::PAGE_SIZE,
+            writable(),
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            MapError::AlreadyMapped(VirtualAddress::new(large))
+        );
+    }
+
+    #[test]
+    fn map_range_rejects_ranges_leaving_the_address_space() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let result = dir.map_range(
+            VirtualAddress::new(arch::canonicalize_virtual(0x7FF0)),
+            PhysicalAddress::new(0),
+            2 * arch::PAGE_SIZE,
+            writable(),
+        );
+        assert_eq!(result.unwrap_err(), MapError::OutOfRange);
+    }
+
+    #[test]
+    fn unmap_range_removes_mappings() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let page = arch::PAGE_SIZE;
+        dir.map_range(
+            VirtualAddress::new(0x0100),
+            PhysicalAddress::new(0x0400),
+            8 * page,
+            writable(),
+        )
+        .unwrap()
+        .ignore();
+
+        dir.unmap_range(VirtualAddress::new(0x0120), 4 * page)
+            .unwrap()
+            .ignore();
+
+        let mappings: Vec<_> = dir
+            .mappings()
+            .map(|m| (m.virt.as_usize(), m.size))
+            .collect();
+        assert_eq!(mappings, [(0x0100, 2 * page), (0x0160, 2 * page)]);
+    }
+
+    #[test]
+    fn unmap_range_frees_empty_tables() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let virt = VirtualAddress::new(0x1100);
+        dir.map_range(
+            virt,
+            PhysicalAddress::new(0x0400),
+            4 * arch::PAGE_SIZE,
+            writable(),
+        )
+        .unwrap()
+        .ignore();
+        dir.unmap_range(virt, 4 * arch::PAGE_SIZE).unwrap().ignore();
+
+        // Both the level 0 and level 1 tables were emptied, so the root entry is gone.
+        let (entry, level) = dir.lookup(virt.as_usize());
+        assert_eq!(level, arch::page_table_levels() - 1);
+        assert!(!entry.is_present());
+    }
+
+    #[test]
+    fn unmap_range_keeps_tables_in_use() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        dir.map(
+            VirtualAddress::new(0x1100),
+            PhysicalAddress::new(0x0400),
+            writable(),
+        );
+        dir.map(
+            VirtualAddress::new(0x1200),
+            PhysicalAddress::new(0x0800),
+            writable(),
+        );
+        dir.unmap_range(VirtualAddress::new(0x1100), arch::PAGE_SIZE)
+            .unwrap()
+            .ignore();
+
+        // The level 1 table still maps 0x1200, only the level 0 table for 0x1100 is gone.
+        let (entry, level) = dir.lookup(0x1100);
+        assert_eq!(level, 1);
+        assert!(!entry.is_present());
+        assert!(dir.translate(VirtualAddress::new(0x1200)).is_some());
+    }
+
+    #[test]
+    fn unmap_range_never_frees_borrowed_tables() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let virt = VirtualAddress::new(0x1100);
+        dir.map(virt, PhysicalAddress::new(0x0400), writable());
+
+        // Pretend the level 1 table was set up by someone else.
+        let root = unsafe { &mut *dir.root };
+        let entry = root.entry_mut(arch::page_index(virt.as_usize(), 2));
+        *entry = PageEntry::new(entry.address().unwrap(), entry.flags());
+
+        dir.unmap_range(virt, arch::PAGE_SIZE).unwrap().ignore();
+
+        let (entry, level) = dir.lookup(virt.as_usize());
+        assert_eq!(level, 1);
+        assert!(!entry.is_present());
+    }
+
+    #[test]
+    fn unmap_range_removes_whole_huge_pages() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let large = PageSize::Large.bytes();
+        dir.map_huge(
+            VirtualAddress::new(large),
+            PhysicalAddress::new(large),
+            PageSize::Large,
+            writable(),
+        );
+        dir.unmap_range(VirtualAddress::new(large), large)
+            .unwrap()
+            .ignore();
+
+        assert_eq!(dir.mappings().count(), 0);
+    }
+
+    #[test]
+    fn unmap_range_rejects_partial_huge_page() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let large = PageSize::Large.bytes();
+        dir.map_huge(
+            VirtualAddress::new(large),
+            PhysicalAddress::new(large),
+            PageSize::Large,
+            writable(),
+        );
+
+        let result = dir.unmap_range(VirtualAddress::new(large), arch::PAGE_SIZE);
+        assert_eq!(
+            result.unwrap_err(),
+            MapError::PartialHugePage(VirtualAddress::new(large))
+        );
+        let result = dir.unmap_range(
+            VirtualAddress::new(large - arch::PAGE_SIZE),
+            2 * arch::PAGE_SIZE,
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            MapError::PartialHugePage(VirtualAddress::new(large))
+        );
+        assert_eq!(dir.mappings().count(), 1);
+    }
+
+    #[test]
+    fn split_huge_page_keeps_translations() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let large = PageSize::Large.bytes();
+        let virt = VirtualAddress::new(large);
+        let phys = PhysicalAddress::new(large * 2);
+        dir.map_huge(virt, phys, PageSize::Large, writable());
+
+        let inner = VirtualAddress::new(large + 3 * arch::PAGE_SIZE);
+        let flush = dir.split_huge_page(inner);
+        assert_eq!((flush.start, flush.size), (virt, large));
+        flush.ignore();
+
+        let (inner_phys, flags, size) = dir.translate(inner).unwrap();
+        assert_eq!(inner_phys.as_usize(), phys.as_usize() + 3 * arch::PAGE_SIZE);
+        assert_eq!(size, PageSize::Base);
+        assert!(flags.is_writable());
+        assert_eq!(
+            dir.mappings().collect::<Vec<_>>(),
+            [Mapping {
+                virt,
+                phys,
+                size: large,
+                flags: dir.translate(virt).unwrap().1,
+            }]
+        );
+
+        // The base page can now be unmapped on its own.
+        dir.unmap_range(inner, arch::PAGE_SIZE).unwrap().ignore();
+        assert_eq!(dir.translate(inner), None);
+        assert_eq!(dir.mappings().count(), 2);
+    }
+
+    #[test]
+    fn split_huge_page_splits_down_to_base_pages() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let huge = PageSize::Huge.bytes();
+        dir.map_huge(
+            VirtualAddress::new(huge),
+            PhysicalAddress::new(huge),
+            PageSize::Huge,
+            writable(),
+        );
+
+        let inner = VirtualAddress::new(huge + PageSize::Large.bytes() + arch::PAGE_SIZE);
+        dir.split_huge_page(inner).ignore();
+
+        let (phys, _, size) = dir.translate(inner).unwrap();
+        assert_eq!(phys.as_usize(), inner.as_usize());
+        assert_eq!(size, PageSize::Base);
+        let (_, _, size) = dir.translate(VirtualAddress::new(huge)).unwrap();
+        assert_eq!(size, PageSize::Large);
+
+        // Splitting a base page changes nothing.
+        let flush = dir.split_huge_page(inner);
+        assert_eq!(flush.size, 0);
+        flush.ignore();
+        assert_eq!(dir.translate(inner).unwrap().2, PageSize::Base);
+    }
+
+    #[test]
+    fn protect_range_changes_flags_of_mapped_pages() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let page = arch::PAGE_SIZE;
+        dir.map_range(
+            VirtualAddress::new(0x0100),
+            PhysicalAddress::new(0x0400),
+            4 * page,
+            writable(),
+        )
+        .unwrap()
+        .ignore();
+
+        let mut read_only = PageFlags::empty();
+        read_only.set_present(true);
+        dir.protect_range(VirtualAddress::new(0x0110), 8 * page, read_only)
+            .unwrap()
+            .ignore();
+
+        let (phys, flags, _) = dir.translate(VirtualAddress::new(0x0100)).unwrap();
+        assert_eq!(phys, PhysicalAddress::new(0x0400));
+        assert!(flags.is_writable());
+        for offset in 1..4 {
+            let virt = VirtualAddress::new(0x0100 + offset * page);
+            let (phys, flags, _) = dir.translate(virt).unwrap();
+            assert_eq!(phys, PhysicalAddress::new(0x0400 + offset * page));
+            assert!(!flags.is_writable());
+        }
+        assert!(
+            dir.translate(VirtualAddress::new(0x0100 + 4 * page))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn table_frames_lists_every_table() {
+        setup();
+        let mut dir = PageDirectory::new();
+        // SAFETY: The root is valid for the lifetime of the directory.
+        let root = unsafe { &*dir.root }.physical_address();
+        assert_eq!(dir.table_frames(), [root]);
+
+        dir.map_range(
+            VirtualAddress::new(0x0100),
+            PhysicalAddress::new(0x0400),
+            2 * arch::PAGE_SIZE,
+            writable(),
+        )
+        .unwrap()
+        .ignore();
+        dir.map_huge(
+            VirtualAddress::new(0x1000),
+            PhysicalAddress::new(0x1000),
+            PageSize::Large,
+            writable(),
+        );
+
+        let frames = dir.table_frames();
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[0], root);
+        assert!(frames.iter().all(|frame| frame.is_aligned(arch::PAGE_SIZE)));
+    }
+
+    #[test]
+    fn tlb_flush_picks_strategy_by_size() {
+        setup();
+        let mut dir = PageDirectory::new();
+
+        let small = FULL_FLUSH_THRESHOLD * arch::PAGE_SIZE;
+        let flush = dir
+            .map_range(
+                VirtualAddress::new(0),
+                PhysicalAddress::new(0),
+                small,
+                writable(),
+            )
+            .unwrap();
+        assert!(!flush.is_full());
+        flush.flush();
+
+        let flush = dir
+            .map_range(
+                VirtualAddress::new(0x1000),
+                PhysicalAddress::new(0),
+                small + arch::PAGE_SIZE,
+                writable(),
+            )
+            .unwrap();
+        assert!(flush.is_full());
+        flush.flush();
+    }
+
+    #[test]
+    fn two_level_paging_narrows_canonical_addresses() {
+        arch::set_page_table_levels(2);
+
+        // With two levels the address space is 4 KiB, split in halves at bit 11.
+        assert!(arch::validate_virtual(0x07f0));
+        assert!(!arch::validate_virtual(0x0800));
+        assert!(arch::validate_virtual(0xffff_ffff_ffff_f800));
+        assert_eq!(arch::canonicalize_virtual(0x0f00), 0xffff_ffff_ffff_ff00);
+
+        arch::set_page_table_levels(3);
+        assert!(arch::validate_virtual(0x0800));
+        assert_eq!(arch::canonicalize_virtual(0x0f00), 0x0f00);
+    }
+
+    #[test]
+    fn two_level_paging_walks_from_level_1() {
+        setup();
+        arch::set_page_table_levels(2);
+        let mut dir = PageDirectory::new();
+
+        let low = VirtualAddress::new(0x0340);
+        let high = VirtualAddress::new(0xffff_ffff_ffff_f900);
+        dir.map(low, PhysicalAddress::new(0x0400), writable());
+        dir.map(high, PhysicalAddress::new(0x0500), writable());
+
+        // The root table is indexed by bits 8-11 and points straight at level 0 tables.
+        let root = dir.root_table();
+        for virt in [low, high] {
+            let entry = root.entry(arch::page_index(virt.as_usize(), 1));
+            assert!(entry.is_present() && !entry.is_leaf());
+            assert_eq!(dir.lookup(virt.as_usize()).1, 0);
+        }
+        assert_eq!(
+            dir.translate(low + 4).map(|(phys, _, _)| phys),
+            Some(PhysicalAddress::new(0x0404))
+        );
+        assert_eq!(
+            dir.translate(high).map(|(phys, _, _)| phys),
+            Some(PhysicalAddress::new(0x0500))
+        );
+        assert_eq!(
+            dir.mappings()
+                .map(|mapping| mapping.virt)
+                .collect::<Vec<_>>(),
+            vec![low, high]
+        );
+
+        // Ranges may not cross from the lower into the upper half.
+        assert_eq!(
+            dir.map_range(
+                VirtualAddress::new(0x07f0),
+                PhysicalAddress::new(0x0600),
+                2 * arch::PAGE_SIZE,
+                writable(),
+            )
+            .unwrap_err(),
+            MapError::OutOfRange
+        );
+
+        let mut buf = [0; 4];
+        mmu()
+            .write(
+                dir.root_table(),
+                low,
+                &[1, 2, 3, 4],
+                arch::Privilege::Supervisor,
+            )
+            .unwrap();
+        mmu()
+            .read(dir.root_table(), low, &mut buf, arch::Privilege::Supervisor)
+            .unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+        assert_eq!(
+            mmu()
+                .read(dir.root_table(), high, &mut buf, arch::Privilege::User)
+                .unwrap_err()
+                .error_code,
+            arch::PageFaultErrorCode::PROTECTION_VIOLATION | arch::PageFaultErrorCode::USER_MODE
+        );
+    }
+
+    fn mmu() -> &'static arch::EmulatedMemory {
+        AddressTranslator::current().emulated_memory().unwrap()
+    }
+
+    /// Allocates `count` zeroed frames of emulated memory, away from any page tables.
+    fn data_frames(count: usize) -> PhysicalAddress {
+        let phys = AddressTranslator::current()
+            .allocate(count * arch::PAGE_SIZE, arch::PAGE_SIZE)
+            .unwrap();
+        PhysicalAddress::new(phys)
+    }
+
+    #[test]
+    fn mmu_reads_and_writes_through_mapping() {
+        setup();
+        let mut dir = PageDirectory::new();
+        let phys = data_frames(2);
+        let virt = VirtualAddress::new(0x1230);
+        dir.map_range(virt, phys, 2 * arch::PAGE_SIZE, writable())
+            .unwrap()
+            .flush();
+
+        // Straddle the two pages to check that each one is translated separately.
+        let data = *b"hello, paging!";
+        let start = VirtualAddress::new(virt.as_usize() + 9);
+        mmu()
+            .write(dir.root_table(), start, &data, arch::Privilege::Supervisor)
+            .unwrap();
+
+        let mut buf = [0u8; 14];
+        mmu()
+            .read(
+                dir.root_table(),
+                start,
+                &mut buf,
+                arch::Privilege::Supervisor,
+            )
+            .unwrap();
+        assert_eq!(buf, data);
+
+        // SAFETY: The frames were allocated above and hold the bytes written through the MMU.
+        let raw = unsafe { *mmu().translate(phys.as_usize() + 9) };
+        assert_eq!(raw, b'h');
+    }
+
+    #[test]
+    fn mmu_reports_not_present_faults() {
+        setup();
+        let dir = PageDirectory::new();
+        let virt = VirtualAddress::new(0x2340);
+
+        let fault = mmu()
+            .write(dir.root_table(), virt, &[1], arch::Privilege::User)
+            .unwrap_err();
+
+        assert_eq!(fault.address, virt);
+        assert_eq!(
+            fault.error_code,
+            arch::PageFaultErrorCode::CAUSED_BY_WRITE | arch::PageFaultErrorCode::USER_MODE
+        );
+    }
+
+    #[test]
+    fn mmu_enforces_writable_bit() {
+        setup();
+        let mut dir = PageDirectory::new();
+        let mut flags = PageFlags::empty();
+        flags.set_present(true);
+        let virt = VirtualAddress::new(0x3450);
+        dir.map(virt, data_frames(1), flags);
+
+        let mut buf = [0u8; 4];
+        mmu()
+            .read(
+                dir.root_table(),
+                virt,
+                &mut buf,
+                arch::Privilege::Supervisor,
+            )
+            .unwrap();
+        let fault = mmu()
+            .write(dir.root_table(), virt, &buf, arch::Privilege::Supervisor)
+            .unwrap_err();
+
+        assert_eq!(
+            fault.error_code,
+            arch::PageFaultErrorCode::PROTECTION_VIOLATION
+                | arch::PageFaultErrorCode::CAUSED_BY_WRITE
+        );
+    }
+
+    #[test]
+    fn mmu_enforces_user_bit() {
+        setup();
+        let mut dir = PageDirectory::new();
+        let kernel = VirtualAddress::new(0x4560);
+        let user = VirtualAddress::new(0x4570);
+        dir.map(kernel, data_frames(1), writable());
+        let mut flags = writable();
+        flags.set_user(true);
+        dir.map(user, data_frames(1), flags);
+
+        let mut buf = [0u8; 1];
+        mmu()
+            .read(dir.root_table(), user, &mut buf, arch::Privilege::User)
+            .unwrap();
+        mmu()
+            .read(
+                dir.root_table(),
+                kernel,
+                &mut buf,
+                arch::Privilege::Supervisor,
+            )
+            .unwrap();
+        let fault = mmu()
+            .read(dir.root_table(), kernel, &mut buf, arch::Privilege::User)
+            .unwrap_err();
+
+        assert_eq!(
+            fault.error_code,
+            arch::PageFaultErrorCode::PROTECTION_VIOLATION | arch::PageFaultErrorCode::USER_MODE
+        );
+    }
+
+    #[test]
+    fn mmu_enforces_no_execute_bit() {
+        setup();
+        let mut dir = PageDirectory::new();
+        let code = VirtualAddress::new(0x5670);
+        let data = VirtualAddress::new(0x5680);
+        dir.map(code, data_frames(1), writable());
+        let mut flags = writable();
+        flags.set_no_execute(true);
+        dir.map(data, data_frames(1), flags);
+
+        let mut buf = [0u8; 2];
+        mmu()
+            .fetch(
+                dir.root_table(),
+                code,
+                &mut buf,
+                arch::Privilege::Supervisor,
+            )
+            .unwrap();
+        mmu()
+            .read(
+                dir.root_table(),
+                data,
+                &mut buf,
+                arch::Privilege::Supervisor,
+            )
+            .unwrap();
+        let fault = mmu()
+            .fetch(
+                dir.root_table(),
+                data,
+                &mut buf,
+                arch::Privilege::Supervisor,
+            )
+            .unwrap_err();
+
+        assert_eq!(
+            fault.error_code,
+            arch::PageFaultErrorCode::PROTECTION_VIOLATION
+                | arch::PageFaultErrorCode::INSTRUCTION_FETCH
+        );
+    }
+
+    #[test]
+    fn mmu_translates_huge_pages() {
+        setup();
+        let mut dir = PageDirectory::new();
+        let size = PageSize::Large.bytes();
+        let phys = PhysicalAddress::new(AddressTranslator::current().allocate(size, size).unwrap());
+        let virt = VirtualAddress::new(0x6700);
+        dir.map_huge(virt, phys, PageSize::Large, writable());
+
+        let offset = 0x9A;
+        let translated = mmu()
+            .translate_virtual(
+                dir.root_table(),
+                VirtualAddress::new(virt.as_usize() + offset),
+                arch::Access::Write,
+                arch::Privilege::Supervisor,
+            )
+            .unwrap();
+
+        assert_eq!(translated.as_usize(), phys.as_usize() + offset);
+    }
+
+    #[test]
+    fn mmu_observes_unmap_and_protect() {
+        setup();
+        let mut dir = PageDirectory::new();
+        let virt = VirtualAddress::new(0x7800);
+        dir.map_range(virt, data_frames(2), 2 * arch::PAGE_SIZE, writable())
+            .unwrap()
+            .flush();
+
+        let mut read_only = PageFlags::empty();
+        read_only.set_present(true);
+        dir.protect_range(virt, arch::PAGE_SIZE, read_only)
+            .unwrap()
+            .flush();
+        let fault = mmu()
+            .write(dir.root_table(), virt, &[1], arch::Privilege::Supervisor)
+            .unwrap_err();
+        assert!(
+            fault
+                .error_code
+                .contains(arch::PageFaultErrorCode::PROTECTION_VIOLATION)
+        );
+
+        let second = VirtualAddress::new(virt.as_usize() + arch::PAGE_SIZE);
+        dir.unmap_range(second, arch::PAGE_SIZE).unwrap().flush();
+        let fault = mmu()
+            .write(dir.root_table(), second, &[1], arch::Privilege::Supervisor)
+            .unwrap_err();
+        assert_eq!(
+            fault,
+            arch::PageFault {
+                address: second,
+                error_code: arch::PageFaultErrorCode::CAUSED_BY_WRITE,
+            }
+        );
+    }
 }
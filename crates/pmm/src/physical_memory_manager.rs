@@ -3,11 +3,22 @@
 //! This module provides the main physical memory allocator for the kernel, based on Linux's
 //! buddy allocator design. It manages all physical frames in the system using an 11-order
 //! buddy system (orders 0-11), where order n represents blocks of 2^n contiguous frames.
+//!
+//! Physical memory is split into zones ([`Zone::Dma`], [`Zone::Dma32`] and [`Zone::Normal`]),
+//! each with its own set of free lists, so that devices with addressing limits can be served
+//! from the memory they can reach. Buddies are never merged across zone boundaries.
+//!
+//! With the `free-poisoning` feature, freed blocks are filled with
+//! [`FREE_POISON`](crate::FREE_POISON) and checked when they are allocated again, and any
+//! block written to while it was free is reported with the frame and order it was found at.
 
 use core::ptr::{self, NonNull};
-use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
-use crate::{FrameFlag, FrameNumber, MemoryMap, PhysicalAddress, arch};
+use crate::{
+    BlockAllocator, FrameFlag, FrameNumber, HandOffRange, MemoryMap, MemoryRegion, ORDER_NOT_BUDDY,
+    PhysicalAddress, PmmStats, arch,
+};
 
 use crate::VirtualAddress;
 
@@ -15,7 +26,13 @@ use crate::VirtualAddress;
 const MAX_ORDER: usize = 11;
 
 /// Number of free lists in the buddy allocator (orders 0 through MAX_ORDER inclusive).
-const NUM_FREE_LISTS: usize = MAX_ORDER + 1;
+pub(crate) const NUM_FREE_LISTS: usize = MAX_ORDER + 1;
+
+/// Number of physical memory zones.
+const NUM_ZONES: usize = 3;
+
+/// Divisor applied to a zone's managed frames to compute its `min` watermark.
+const WATERMARK_MIN_DIVISOR: usize = 256;
 
 /// Errors that can occur during physical memory allocation.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -28,6 +45,214 @@ pub enum AllocError {
     InvalidAlignment,
     /// Attempted to deallocate an invalid address or order.
     InvalidDeallocation,
+    /// The requested frame count is zero.
+    InvalidSize,
+}
+
+/// An invariant of the buddy allocator found broken by [`PhysicalMemoryManager::verify`].
+///
+/// Each variant names the free-list entry that broke the invariant by its zone, the order of
+/// the list it was found on, and its base address.
+#[cfg(any(test, debug_assertions, feature = "verify"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyError {
+    /// A free block extends past the limits of the zone whose list holds it.
+    OutsideZone {
+        zone: Zone,
+        order: usize,
+        addr: PhysicalAddress,
+    },
+    /// A free block is not aligned to its order.
+    Misaligned {
+        zone: Zone,
+        order: usize,
+        addr: PhysicalAddress,
+    },
+    /// A free block has no frame metadata in the memory map.
+    Untracked {
+        zone: Zone,
+        order: usize,
+        addr: PhysicalAddress,
+    },
+    /// The head frame of a free block is marked `Allocated` or `Reserved`.
+    MarkedInUse {
+        zone: Zone,
+        order: usize,
+        addr: PhysicalAddress,
+    },
+    /// The head frame of a free block records a different order than the list holding it.
+    OrderMismatch {
+        zone: Zone,
+        order: usize,
+        addr: PhysicalAddress,
+        recorded: u8,
+    },
+    /// Two free blocks share at least one frame.
+    Overlap {
+        first: PhysicalAddress,
+        first_order: usize,
+        second: PhysicalAddress,
+        second_order: usize,
+    },
+    /// A free block and its buddy are both free at the same order and were never merged.
+    UnmergedBuddies {
+        zone: Zone,
+        order: usize,
+        addr: PhysicalAddress,
+        buddy: PhysicalAddress,
+    },
+    /// Walking a free list found a different number of blocks than its count records.
+    ///
+    /// The walk stops one block past the recorded count, so `counted` is a lower bound when it
+    /// exceeds `recorded`.
+    CountMismatch {
+        zone: Zone,
+        order: usize,
+        counted: usize,
+        recorded: usize,
+    },
+}
+
+#[cfg(any(test, debug_assertions, feature = "verify"))]
+impl core::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            VerifyError::OutsideZone { zone, order, addr } => {
+                write!(
+                    f,
+                    "order-{order} free block at {addr} lies outside zone {zone:?}"
+                )
+            }
+            VerifyError::Misaligned { zone, order, addr } => {
+                write!(
+                    f,
+                    "order-{order} free block at {addr} in zone {zone:?} is misaligned"
+                )
+            }
+            VerifyError::Untracked { zone, order, addr } => write!(
+                f,
+                "order-{order} free block at {addr} in zone {zone:?} is not in the memory map"
+            ),
+            VerifyError::MarkedInUse { zone, order, addr } => write!(
+                f,
+                "order-{order} free block at {addr} in zone {zone:?} is marked allocated or reserved"
+            ),
+            VerifyError::OrderMismatch {
+                zone,
+                order,
+                addr,
+                recorded,
+            } => write!(
+                f,
+                "free block at {addr} is on the order-{order} list of zone {zone:?} but its frame records order {recorded}"
+            ),
+            VerifyError::Overlap {
+                first,
+                first_order,
+                second,
+                second_order,
+            } => write!(
+                f,
+                "order-{first_order} free block at {first} overlaps order-{second_order} free block at {second}"
+            ),
+            VerifyError::UnmergedBuddies {
+                zone,
+                order,
+                addr,
+                buddy,
+            } => write!(
+                f,
+                "order-{order} free block at {addr} and its buddy at {buddy} in zone {zone:?} are both free"
+            ),
+            VerifyError::CountMismatch {
+                zone,
+                order,
+                counted,
+                recorded,
+            } => write!(
+                f,
+                "order-{order} free list of zone {zone:?} holds {counted} blocks but counts {recorded}"
+            ),
+        }
+    }
+}
+
+/// A physical memory zone.
+///
+/// Zones partition physical memory by the addressing constraints of the devices that can use
+/// it. Zones are ordered from most to least constrained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Zone {
+    /// Memory below [`arch::ZONE_DMA_LIMIT`] (16 MiB on x86_64), reachable by legacy ISA DMA.
+    Dma = 0,
+    /// Memory below [`arch::ZONE_DMA32_LIMIT`] (4 GiB on x86_64), reachable by 32-bit DMA.
+    Dma32 = 1,
+    /// All remaining memory.
+    Normal = 2,
+}
+
+impl Zone {
+    /// All zones, from most to least constrained.
+    pub const ALL: [Zone; NUM_ZONES] = [Zone::Dma, Zone::Dma32, Zone::Normal];
+
+    /// Returns the zones to try, in order, when allocating for this zone.
+    ///
+    /// Allocations may fall back to more constrained zones, but never to less constrained
+    /// ones: a DMA32 request can be served from DMA memory, but not from Normal memory.
+    pub const fn fallbacks(self) -> &'static [Zone] {
+        match self {
+            Zone::Dma => &[Zone::Dma],
+            Zone::Dma32 => &[Zone::Dma32, Zone::Dma],
+            Zone::Normal => &[Zone::Normal, Zone::Dma32, Zone::Dma],
+        }
+    }
+
+    /// Returns the physical address range `[start, end)` covered by this zone.
+    pub const fn limits(self) -> (usize, usize) {
+        match self {
+            Zone::Dma => (0, arch::ZONE_DMA_LIMIT),
+            Zone::Dma32 => (arch::ZONE_DMA_LIMIT, arch::ZONE_DMA32_LIMIT),
+            Zone::Normal => (arch::ZONE_DMA32_LIMIT, usize::MAX),
+        }
+    }
+
+    /// Returns the zone containing the given physical address.
+    pub const fn containing(address: PhysicalAddress) -> Zone {
+        let addr = address.as_usize();
+        if addr < arch::ZONE_DMA_LIMIT {
+            Zone::Dma
+        } else if addr < arch::ZONE_DMA32_LIMIT {
+            Zone::Dma32
+        } else {
+            Zone::Normal
+        }
+    }
+}
+
+/// Free-frame thresholds for a zone.
+///
+/// - An allocation targeting a zone only succeeds while the zone keeps at least `min` free
+///   frames afterwards.
+/// - An allocation falling back from a less constrained zone only succeeds while the zone keeps
+///   at least `high` free frames afterwards, protecting scarce low memory from general use.
+/// - `low` sits between the two and marks the point where reclaim should start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Watermarks {
+    pub min: usize,
+    pub low: usize,
+    pub high: usize,
+}
+
+impl Watermarks {
+    /// Computes default watermarks for a zone managing the given number of frames.
+    pub const fn for_managed_frames(managed: usize) -> Self {
+        let min = managed / WATERMARK_MIN_DIVISOR;
+        Self {
+            min,
+            low: min + min / 4,
+            high: min + min / 2,
+        }
+    }
 }
 
 /// Node in an intrusive linked list for free blocks.
@@ -40,54 +265,46 @@ struct FreeBlock {
 }
 
 /// Free list for a specific order.
+///
+/// The list is only changed with its lock held, and so is the state marking a block as free:
+/// the head frame of a block records the list's order, without the `Allocated` flag, exactly
+/// while the block is on the list. Checking whether a buddy is free and taking it off its list
+/// is then a single step, so two CPUs can never both claim the same block, and a block cannot
+/// be popped, reused and pushed back between another CPU's reads of the list (the ABA problem
+/// of lock-free lists).
 struct FreeList {
-    head: AtomicPtr<FreeBlock>,
+    head: spin::Mutex<ListHead>,
+    /// Number of blocks on the list, readable without taking the lock.
     count: AtomicUsize,
 }
 
+/// The first block of a [`FreeList`].
+struct ListHead(*mut FreeBlock);
+
+// SAFETY: The blocks on a list are free memory owned by the allocator, and are only reached
+// through the list's lock.
+unsafe impl Send for ListHead {}
+
+/// A locked [`FreeList`].
+struct LockedFreeList<'a> {
+    head: spin::MutexGuard<'a, ListHead>,
+    count: &'a AtomicUsize,
+}
+
 impl FreeList {
     /// Creates an empty free list.
     const fn new() -> Self {
         Self {
-            head: AtomicPtr::new(ptr::null_mut()),
+            head: spin::Mutex::new(ListHead(ptr::null_mut())),
             count: AtomicUsize::new(0),
         }
     }
 
-    /// Pushes a block onto the free list.
-    fn push(&self, block: *mut FreeBlock) {
-        loop {
-            let head = self.head.load(Ordering::Acquire);
-            unsafe {
-                (*block).next = head;
-            }
-            if self
-                .head
-                .compare_exchange(head, block, Ordering::Release, Ordering::Acquire)
-                .is_ok()
-            {
-                self.count.fetch_add(1, Ordering::Release);
-                return;
-            }
-        }
-    }
-
-    /// Pops a block from the free list, returning None if empty.
-    fn pop(&self) -> Option<NonNull<FreeBlock>> {
-        loop {
-            let head = self.head.load(Ordering::Acquire);
-            if head.is_null() {
-                return None;
-            }
-            let next = unsafe { (*head).next };
-            if self
-                .head
-                .compare_exchange(head, next, Ordering::Release, Ordering::Acquire)
-                .is_ok()
-            {
-                self.count.fetch_sub(1, Ordering::Release);
-                return NonNull::new(head);
-            }
+    /// Locks the list.
+    fn lock(&self) -> LockedFreeList<'_> {
+        LockedFreeList {
+            head: self.head.lock(),
+            count: &self.count,
         }
     }
 
@@ -102,6 +319,158 @@ impl FreeList {
     }
 }
 
+impl LockedFreeList<'_> {
+    /// Pushes a block onto the free list.
+    fn push(&mut self, block: *mut FreeBlock) {
+        // SAFETY: The block is free memory of at least one frame, owned by this list from now on.
+        unsafe {
+            (*block).next = self.head.0;
+        }
+        self.head.0 = block;
+        self.count.fetch_add(1, Ordering::Release);
+    }
+
+    /// Pops a block from the free list, returning None if empty.
+    fn pop(&mut self) -> Option<NonNull<FreeBlock>> {
+        let head = NonNull::new(self.head.0)?;
+        // SAFETY: Blocks on the list are valid free blocks.
+        self.head.0 = unsafe { head.as_ref().next };
+        self.count.fetch_sub(1, Ordering::Release);
+        Self::unlinked(head.as_ptr());
+        Some(head)
+    }
+
+    /// Removes a block from the free list, returning false if it is not on the list.
+    ///
+    /// This is a slow O(n) operation that walks the list. It's only used during coalescing,
+    /// which is relatively rare compared to allocations.
+    fn remove(&mut self, block: *mut FreeBlock) -> bool {
+        let mut link: *mut *mut FreeBlock = &mut self.head.0;
+        // SAFETY: Every link is either the head or the `next` field of a block on the list.
+        unsafe {
+            while !(*link).is_null() {
+                if *link == block {
+                    *link = (*block).next;
+                    self.count.fetch_sub(1, Ordering::Release);
+                    Self::unlinked(block);
+                    return true;
+                }
+                link = &mut (**link).next;
+            }
+        }
+        false
+    }
+
+    /// Called on a block that has just been taken off the list.
+    ///
+    /// With free poisoning, the link the list kept in the block is poisoned like the rest of
+    /// it, so that the whole block holds the pattern while it is free and off every list.
+    fn unlinked(block: *mut FreeBlock) {
+        #[cfg(feature = "free-poisoning")]
+        // SAFETY: The block is free memory, and the list no longer refers to it.
+        unsafe {
+            crate::free_poison::fill(block.cast(), core::mem::size_of::<FreeBlock>());
+        }
+        #[cfg(not(feature = "free-poisoning"))]
+        let _ = block;
+    }
+
+    /// Returns an iterator over the blocks on the list.
+    fn iter(&self) -> impl Iterator<Item = NonNull<FreeBlock>> + '_ {
+        // SAFETY: Blocks on the list are valid free blocks, and stay on it while it is locked.
+        core::iter::successors(NonNull::new(self.head.0), |block| {
+            NonNull::new(unsafe { block.as_ref().next })
+        })
+    }
+}
+
+/// Buddy allocator state for a single zone.
+struct ZoneData {
+    /// Free lists for each order, holding only blocks inside this zone.
+    free_lists: [FreeList; NUM_FREE_LISTS],
+    /// First frame of the zone that is covered by the memory map.
+    start_frame: FrameNumber,
+    /// One past the last frame of the zone that is covered by the memory map.
+    end_frame: FrameNumber,
+    /// Number of usable (non-reserved) frames the memory map reports inside the zone.
+    present_frames: usize,
+    /// Number of frames handed to the zone via [`PhysicalMemoryManager::add_region`].
+    managed_frames: usize,
+    /// Free-frame thresholds for allocations from this zone.
+    watermarks: Watermarks,
+}
+
+impl ZoneData {
+    /// Creates an empty zone spanning the given frames.
+    const fn new(start_frame: FrameNumber, end_frame: FrameNumber, present_frames: usize) -> Self {
+        Self {
+            free_lists: [const { FreeList::new() }; NUM_FREE_LISTS],
+            start_frame,
+            end_frame,
+            present_frames,
+            managed_frames: 0,
+            watermarks: Watermarks {
+                min: 0,
+                low: 0,
+                high: 0,
+            },
+        }
+    }
+
+    /// Returns the number of free frames across all orders in this zone.
+    fn free_frames(&self) -> usize {
+        self.free_lists
+            .iter()
+            .enumerate()
+            .map(|(order, list)| list.count() * (1 << order))
+            .sum()
+    }
+
+    /// Finds the lowest order with available blocks that can satisfy the request.
+    fn find_free_order(&self, min_order: usize) -> Option<usize> {
+        (min_order..=MAX_ORDER).find(|&order| !self.free_lists[order].is_empty())
+    }
+}
+
+/// Running counters behind [`PhysicalMemoryManager::stats`].
+///
+/// The counters are updated independently of each other, so a snapshot taken while other CPUs
+/// allocate may be slightly inconsistent.
+#[derive(Default)]
+struct Counters {
+    /// Frames handed out and not yet returned.
+    allocated: AtomicUsize,
+    /// The largest value `allocated` has reached.
+    peak_allocated: AtomicUsize,
+    /// Frames flagged as reserved in the memory map.
+    reserved: AtomicUsize,
+    /// Poisoned frames withheld from the free lists.
+    poisoned: AtomicUsize,
+    /// Successful allocations per order.
+    allocations: [AtomicU64; NUM_FREE_LISTS],
+    /// Failed allocations per order.
+    failures: [AtomicU64; NUM_FREE_LISTS],
+    /// Blocks split in two while allocating.
+    splits: AtomicU64,
+    /// Buddy pairs merged while freeing.
+    merges: AtomicU64,
+    /// Blocks found written to while they were free.
+    overwritten: AtomicU64,
+}
+
+impl Counters {
+    /// Records `frames` frames as handed out.
+    fn allocate(&self, frames: usize) {
+        let allocated = self.allocated.fetch_add(frames, Ordering::Relaxed) + frames;
+        self.peak_allocated.fetch_max(allocated, Ordering::Relaxed);
+    }
+
+    /// Records `frames` frames as returned.
+    fn free(&self, frames: usize) {
+        self.allocated.fetch_sub(frames, Ordering::Relaxed);
+    }
+}
+
 /// Physical memory manager using a buddy allocator.
 ///
 /// Manages all physical memory frames in the system using an 11-order buddy allocation
@@ -114,70 +483,186 @@ impl FreeList {
 /// Memory is allocated by finding a free block of the requested order, splitting larger
 /// blocks if necessary. Memory is deallocated by returning blocks to free lists and
 /// coalescing with buddy blocks when possible.
+///
+/// Each [`Zone`] keeps its own free lists. [`allocate`](Self::allocate) serves requests from
+/// [`Zone::Normal`], falling back to the lower zones; [`allocate_in`](Self::allocate_in)
+/// targets a specific zone.
+///
+/// # Concurrency
+///
+/// Allocating and freeing take `&self`, so a shared allocator can serve every CPU at once.
+/// Each free list has its own lock, held only while blocks move on or off it, and frame
+/// metadata is updated atomically. Adding memory and changing watermarks take `&mut self`.
 pub struct PhysicalMemoryManager {
     memory_map: MemoryMap,
-    free_lists: [FreeList; NUM_FREE_LISTS],
+    zones: [ZoneData; NUM_ZONES],
     total_frames: usize,
+    counters: Counters,
 }
 
 impl PhysicalMemoryManager {
     /// Creates a new physical memory manager.
     ///
     /// The allocator takes ownership of the memory map and initializes all free lists as empty.
-    /// Memory must be added to the allocator by deallocating regions into it.
+    /// The span of each zone is derived from the frames covered by the memory map. Memory must
+    /// be added to the allocator with [`add_region`](Self::add_region) or
+    /// [`import_block_allocator`](Self::import_block_allocator), or by deallocating blocks
+    /// into it.
     pub fn new(memory_map: MemoryMap) -> Self {
         let total_frames = memory_map.allocated_frame_count();
+        let zones = Zone::ALL.map(|zone| Self::zone_from_memory_map(&memory_map, zone));
+        let present: usize = zones.iter().map(|zone| zone.present_frames).sum();
 
         Self {
             memory_map,
-            free_lists: [
-                FreeList::new(),
-                FreeList::new(),
-                FreeList::new(),
-                FreeList::new(),
-                FreeList::new(),
-                FreeList::new(),
-                FreeList::new(),
-                FreeList::new(),
-                FreeList::new(),
-                FreeList::new(),
-                FreeList::new(),
-                FreeList::new(),
-            ],
+            zones,
             total_frames,
+            counters: Counters {
+                reserved: AtomicUsize::new(total_frames - present),
+                ..Counters::default()
+            },
+        }
+    }
+
+    /// Adds a region of usable memory to the allocator.
+    ///
+    /// The region is trimmed to whole frames, split at zone boundaries and freed in the largest
+    /// naturally-aligned blocks possible. The frames count towards the managed memory of their
+    /// zones, and the zones' watermarks are recomputed.
+    pub fn add_region(&mut self, base: PhysicalAddress, size: usize) {
+        let start = base.align_up(arch::PAGE_SIZE).as_usize();
+        let end = (base.as_usize() + size) & !(arch::PAGE_SIZE - 1);
+
+        self.free_range(start, end);
+        self.manage_range(start, end);
+    }
+
+    /// Takes over the usable memory of the block allocator that served allocations before the
+    /// physical memory manager was ready.
+    ///
+    /// Memory the block allocator never handed out is added as with
+    /// [`add_region`](Self::add_region). Memory it did hand out, including memory reserved in
+    /// it, is marked allocated and tagged with [`FrameFlag::Boot`]: it counts towards the
+    /// managed and allocated frames of its zones, and stays allocated until it is freed with
+    /// [`deallocate_boot`](Self::deallocate_boot). This does not allocate, so it can be called
+    /// while the block allocator is still the global allocator.
+    pub fn import_block_allocator(&mut self, allocator: BlockAllocator) {
+        allocator.hand_off(|range| match range {
+            HandOffRange::Free(region) => self.add_region(region.base(), region.size()),
+            HandOffRange::Allocated(region) => self.add_boot_allocation(region),
+        });
+    }
+
+    /// Returns true if the frame containing `addr` was handed out by the block allocator and
+    /// has not been freed since (see [`import_block_allocator`](Self::import_block_allocator)).
+    pub fn is_boot_allocation(&self, addr: PhysicalAddress) -> bool {
+        self.memory_map
+            .frame(addr.frame_number())
+            .is_some_and(|frame| frame.flags.atomic_test(FrameFlag::Boot))
+    }
+
+    /// Frees `size` bytes at `base` that the block allocator handed out before the physical
+    /// memory manager took over.
+    ///
+    /// The range is widened to whole frames, like the block allocator's own allocations. It is
+    /// only freed if every frame in it is a boot allocation; otherwise nothing is freed.
+    pub fn deallocate_boot(&self, base: PhysicalAddress, size: usize) {
+        let start = base.align_down(arch::PAGE_SIZE).as_usize();
+        let end = (base.as_usize() + size).next_multiple_of(arch::PAGE_SIZE);
+        let frames = start / arch::PAGE_SIZE..end / arch::PAGE_SIZE;
+
+        if !frames.clone().all(|idx| {
+            self.memory_map
+                .frame(FrameNumber::new(idx))
+                .is_some_and(|frame| frame.flags.atomic_test(FrameFlag::Boot))
+        }) {
+            log::error!(
+                "refusing to free {} bytes at {}: not a boot allocation",
+                size,
+                base
+            );
+            return;
+        }
+
+        for idx in frames.clone() {
+            if let Some(frame) = self.memory_map.frame(FrameNumber::new(idx)) {
+                frame.flags.atomic_clear(FrameFlag::Boot);
+                frame.flags.atomic_clear(FrameFlag::Allocated);
+            }
+        }
+        self.counters.free(frames.len());
+
+        self.free_range(start, end);
+    }
+
+    /// Hands reserved memory that is no longer needed, such as bootloader data structures or
+    /// ACPI tables, to the allocator.
+    ///
+    /// Every reserved frame in the region loses its `Reserved` flag and is freed, except for
+    /// frames for which `keep` returns true, kernel image and locked frames, and frames without
+    /// metadata. The region must be covered by the memory map for anything to be reclaimed
+    /// (see [`BootMemoryRegion::is_reclaimable`](crate::BootMemoryRegion::is_reclaimable)).
+    /// Returns the number of frames reclaimed.
+    pub fn reclaim_region(
+        &mut self,
+        base: PhysicalAddress,
+        size: usize,
+        keep: impl Fn(PhysicalAddress) -> bool,
+    ) -> usize {
+        let start = base.align_up(arch::PAGE_SIZE).frame_number().as_usize();
+        let end = (base.as_usize() + size) / arch::PAGE_SIZE;
+
+        let mut reclaimed = 0;
+        let mut run_start = None;
+        for idx in start..=end {
+            let addr = PhysicalAddress::new(idx * arch::PAGE_SIZE);
+            let frame = (idx < end)
+                .then(|| self.memory_map.frame_mut(FrameNumber::new(idx)))
+                .flatten()
+                .filter(|frame| {
+                    frame.flags.atomic_test(FrameFlag::Reserved)
+                        && !frame.flags.atomic_test(FrameFlag::KernelImage)
+                        && !frame.flags.atomic_test(FrameFlag::Locked)
+                        && !keep(addr)
+                });
+
+            if let Some(frame) = frame {
+                frame.flags.clear(FrameFlag::Reserved);
+                self.counters.reserved.fetch_sub(1, Ordering::Relaxed);
+                run_start.get_or_insert(idx);
+            } else if let Some(run) = run_start.take() {
+                self.add_region(
+                    PhysicalAddress::new(run * arch::PAGE_SIZE),
+                    (idx - run) * arch::PAGE_SIZE,
+                );
+                reclaimed += idx - run;
+            }
         }
+        reclaimed
     }
 
     /// Allocates 2^order contiguous frames.
     ///
+    /// Serves the request from [`Zone::Normal`], falling back to [`Zone::Dma32`] and then
+    /// [`Zone::Dma`]. See [`allocate_in`](Self::allocate_in).
+    pub fn allocate(&self, order: usize) -> Result<PhysicalAddress, AllocError> {
+        self.allocate_in(Zone::Normal, order)
+    }
+
+    /// Allocates 2^order contiguous frames from the given zone.
+    ///
     /// Uses the buddy allocator splitting algorithm: if the requested order is not available,
     /// finds the next higher order with available blocks, splits it, and adds the buddy back
     /// to the appropriate free list.
-    pub fn allocate(&mut self, order: usize) -> Result<PhysicalAddress, AllocError> {
+    ///
+    /// If the zone cannot satisfy the request without dropping below its `min` watermark, the
+    /// zones in [`Zone::fallbacks`] are tried in turn, each of which must stay above its `high`
+    /// watermark.
+    pub fn allocate_in(&self, zone: Zone, order: usize) -> Result<PhysicalAddress, AllocError> {
         if order > MAX_ORDER {
             return Err(AllocError::OrderTooLarge);
         }
-
-        // Try to find a free block at this order or higher
-        let alloc_order = self.find_free_order(order)?;
-
-        // Pop the block from the free list
-        let block = self.free_lists[alloc_order]
-            .pop()
-            .ok_or(AllocError::OutOfMemory)?;
-        let addr = self.block_to_address(block);
-
-        // Split the block down to the requested order
-        self.split_block(addr, alloc_order, order);
-
-        // Mark the block as allocated
-        let frame_num = addr.frame_number();
-        if let Some(frame) = self.memory_map.frame_mut(frame_num) {
-            frame.flags.set(FrameFlag::Allocated);
-            frame.set_order(order as u8);
-        }
-
-        Ok(addr)
+        self.count_allocation(order, self.allocate_with_fallback(zone, order))
     }
 
     /// Allocates 2^order contiguous frames aligned to 2^align_order boundaries.
@@ -185,7 +670,7 @@ impl PhysicalMemoryManager {
     /// The alignment order must be >= the allocation order. Useful for large page support,
     /// DMA requirements, or other hardware constraints.
     pub fn allocate_aligned(
-        &mut self,
+        &self,
         order: usize,
         align_order: usize,
     ) -> Result<PhysicalAddress, AllocError> {
@@ -197,8 +682,12 @@ impl PhysicalMemoryManager {
         }
 
         // For aligned allocations, we allocate at the alignment order
-        // This ensures the block is naturally aligned
-        let addr = self.allocate(align_order)?;
+        // This ensures the block is naturally aligned. The allocation is counted at the
+        // requested order, which is what the caller ends up holding.
+        let addr = self.count_allocation(
+            order,
+            self.allocate_with_fallback(Zone::Normal, align_order),
+        )?;
 
         // If we allocated more than needed, split off the excess
         if align_order > order {
@@ -208,10 +697,11 @@ impl PhysicalMemoryManager {
                 let buddy_addr = PhysicalAddress::new(addr.as_usize() + buddy_size);
                 self.deallocate(buddy_addr, split_order);
             }
+            self.counters.free((1 << align_order) - (1 << order));
 
             // Update the order of the allocated block
             let frame_num = addr.frame_number();
-            if let Some(frame) = self.memory_map.frame_mut(frame_num) {
+            if let Some(frame) = self.memory_map.frame(frame_num) {
                 frame.set_order(order as u8);
             }
         }
@@ -219,51 +709,319 @@ impl PhysicalMemoryManager {
         Ok(addr)
     }
 
+    /// Allocates `frame_count` physically contiguous frames aligned to `align` bytes, lying
+    /// entirely at or below `max_address`.
+    ///
+    /// Unlike [`allocate`](Self::allocate), the frame count need not be a power of two and may
+    /// exceed the largest buddy block: the free lists are searched for a run of adjacent free
+    /// blocks, possibly spanning zones, that can hold the request. The blocks making up the
+    /// run are taken off their free lists and whatever lies outside the allocation is freed
+    /// back. Watermarks are not consulted.
+    ///
+    /// `align` must be a power of two; alignments below [`PAGE_SIZE`](arch::PAGE_SIZE) are
+    /// rounded up to it. The run must be freed with
+    /// [`deallocate_contiguous`](Self::deallocate_contiguous).
+    pub fn allocate_contiguous(
+        &self,
+        frame_count: usize,
+        align: usize,
+        max_address: PhysicalAddress,
+    ) -> Result<PhysicalAddress, AllocError> {
+        if frame_count == 0 {
+            return Err(AllocError::InvalidSize);
+        }
+        if !align.is_power_of_two() {
+            return Err(AllocError::InvalidAlignment);
+        }
+        let align = align.max(arch::PAGE_SIZE);
+        let size = frame_count * arch::PAGE_SIZE;
+        let limit = max_address.as_usize().saturating_add(1);
+
+        loop {
+            let Some((run_start, base)) = self.find_free_run(size, align, limit) else {
+                return Err(AllocError::OutOfMemory);
+            };
+            let end = base + size;
+
+            // Take every block overlapping the allocation off its free list. If another CPU
+            // took one since the run was found, free the blocks taken so far and start over.
+            let mut addr = run_start;
+            let mut taken_start = None;
+            let mut complete = true;
+            while addr < end {
+                let Some(order) = self.free_order_at(addr) else {
+                    complete = false;
+                    break;
+                };
+                let block_end = addr + (1 << order) * arch::PAGE_SIZE;
+                if block_end > base {
+                    if !self.take_free_block(PhysicalAddress::new(addr), order) {
+                        complete = false;
+                        break;
+                    }
+                    self.check_free_poison(PhysicalAddress::new(addr), order);
+                    taken_start.get_or_insert(addr);
+                }
+                addr = block_end;
+            }
+            let taken_start = taken_start.unwrap_or(addr);
+            if !complete {
+                self.free_range(taken_start, addr);
+                continue;
+            }
+
+            // Mark the allocation before freeing the leftovers so that they cannot merge back
+            // into it.
+            for idx in base / arch::PAGE_SIZE..end / arch::PAGE_SIZE {
+                if let Some(frame) = self.memory_map.frame(FrameNumber::new(idx)) {
+                    frame.set_order(ORDER_NOT_BUDDY);
+                }
+            }
+            if let Some(frame) = self
+                .memory_map
+                .frame(PhysicalAddress::new(base).frame_number())
+            {
+                frame.flags.atomic_set(FrameFlag::Allocated);
+                frame.set_ref_count(1);
+            }
+
+            if taken_start < base {
+                self.free_range(taken_start, base);
+            }
+            if addr > end {
+                self.free_range(end, addr);
+            }
+
+            self.counters.allocate(frame_count);
+            return Ok(PhysicalAddress::new(base));
+        }
+    }
+
+    /// Frees a run of frames allocated with [`allocate_contiguous`](Self::allocate_contiguous).
+    ///
+    /// `frame_count` must match the count the run was allocated with.
+    pub fn deallocate_contiguous(&self, base: PhysicalAddress, frame_count: usize) {
+        let Some(frame) = self.memory_map.frame(base.frame_number()) else {
+            return;
+        };
+        if frame.order() != ORDER_NOT_BUDDY
+            || frame.flags.atomic_test(FrameFlag::Boot)
+            || !frame.flags.atomic_test_and_clear(FrameFlag::Allocated)
+        {
+            log::error!(
+                "refusing to free {} frames at {}: not a contiguous allocation",
+                frame_count,
+                base
+            );
+            return;
+        }
+        frame.set_ref_count(0);
+        self.counters.free(frame_count);
+
+        let start = base.as_usize();
+        self.free_range(start, start + frame_count * arch::PAGE_SIZE);
+    }
+
     /// Deallocates 2^order frames starting at the given address.
     ///
-    /// Returns the frames to the free lists, attempting to coalesce with buddy blocks.
-    /// Coalescing proceeds recursively up through orders until a buddy is allocated or
-    /// MAX_ORDER is reached.
+    /// Returns the frames to the free lists of their zone, attempting to coalesce with buddy
+    /// blocks. Coalescing proceeds recursively up through orders until a buddy is allocated,
+    /// the buddy lies in a different zone, or MAX_ORDER is reached.
     ///
     /// This function is also used during initialization to add memory regions to the allocator.
-    pub fn deallocate(&mut self, base: PhysicalAddress, order: usize) {
+    pub fn deallocate(&self, base: PhysicalAddress, order: usize) {
         if order > MAX_ORDER {
             return;
         }
 
-        let mut current_order = order;
-        let mut current_addr = base;
-
-        // Mark the frame as free and set its order for coalescing
-        let frame_num = current_addr.frame_number();
-        if let Some(frame) = self.memory_map.frame_mut(frame_num) {
-            frame.flags.clear(FrameFlag::Allocated);
-            frame.set_order(order as u8);
+        let zone = Zone::containing(base);
+        debug_assert!(
+            Zone::containing(PhysicalAddress::new(
+                base.as_usize() + ((1 << order) - 1) * arch::PAGE_SIZE
+            )) == zone,
+            "block at {} of order {} straddles a zone boundary",
+            base,
+            order
+        );
+
+        if let Some(frame) = self.memory_map.frame(base.frame_number())
+            && (frame.flags.atomic_test(FrameFlag::Locked)
+                || frame.flags.atomic_test(FrameFlag::KernelImage))
+        {
+            log::error!(
+                "refusing to free locked or kernel image block at {} of order {}",
+                base,
+                order
+            );
+            return;
         }
 
-        // Try to coalesce with buddies
-        while current_order < MAX_ORDER {
-            let buddy_addr = self.buddy_address(current_addr, current_order);
+        if self.is_boot_allocation(base) {
+            log::error!(
+                "refusing to free block at {} of order {}: allocated before the PMM took over",
+                base,
+                order
+            );
+            return;
+        }
 
-            // Check if the buddy is free and at the same order
-            if !self.is_buddy_free(buddy_addr, current_order) {
-                break;
+        // Blocks handed out by the allocator carry their order on the head frame; memory that
+        // is freed into the allocator for the first time does not count as allocated. The order
+        // is cleared before the `Allocated` flag so that the block never looks free before it
+        // is on a free list.
+        if let Some(frame) = self.memory_map.frame(base.frame_number())
+            && frame.order() as usize == order
+            && frame.flags.atomic_test(FrameFlag::Allocated)
+        {
+            frame.set_order(ORDER_NOT_BUDDY);
+            if frame.flags.atomic_test_and_clear(FrameFlag::Allocated) {
+                self.counters.free(1 << order);
             }
+        }
+
+        if self.contains_poisoned(base, order) {
+            self.release_unpoisoned(base, order);
+            return;
+        }
+
+        if let Some(frame) = self.memory_map.frame(base.frame_number()) {
+            frame.flags.atomic_clear(FrameFlag::Allocated);
+            frame.flags.atomic_clear(FrameFlag::PageTable);
+            frame.flags.atomic_clear(FrameFlag::Slab);
+            frame.set_ref_count(0);
+        }
 
-            // Remove buddy from its free list
-            self.remove_from_free_list(buddy_addr, current_order);
+        #[cfg(feature = "free-poisoning")]
+        // SAFETY: The block has been freed, so nothing may access it any more.
+        unsafe {
+            crate::free_poison::fill(self.phys_to_ptr(base), (1 << order) * arch::PAGE_SIZE);
+        }
+
+        // Try to coalesce with buddies. Each order's list stays locked from checking the buddy
+        // until the block is either merged or pushed, so a buddy freed concurrently on another
+        // CPU always finds this block, or is found by it.
+        let mut current_order = order;
+        let mut current_addr = base;
+        loop {
+            let mut list = self.zones[zone as usize].free_lists[current_order].lock();
+
+            // Take the buddy off its list if it is free, at the same order and in the same zone
+            let buddy = (current_order < MAX_ORDER)
+                .then(|| self.buddy_address(current_addr, current_order))
+                .filter(|&buddy| {
+                    Zone::containing(buddy) == zone
+                        && self.take_locked(&mut list, buddy, current_order)
+                });
+            let Some(buddy_addr) = buddy else {
+                self.push_locked(&mut list, current_addr, current_order);
+                return;
+            };
+            drop(list);
 
-            // Merge with buddy - the merged block starts at the lower address
-            current_addr = if current_addr.as_usize() < buddy_addr.as_usize() {
-                current_addr
+            // Merge with buddy - the merged block starts at the lower address, and the upper
+            // half no longer heads a block
+            let (lower, upper) = if current_addr.as_usize() < buddy_addr.as_usize() {
+                (current_addr, buddy_addr)
             } else {
-                buddy_addr
+                (buddy_addr, current_addr)
             };
+            if let Some(frame) = self.memory_map.frame(upper.frame_number()) {
+                frame.set_order(ORDER_NOT_BUDDY);
+            }
+            current_addr = lower;
             current_order += 1;
+            self.counters.merges.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Allocates a single zeroed frame for use as a page table.
+    ///
+    /// The frame is tagged with [`FrameFlag::PageTable`] until it is freed.
+    pub fn allocate_page_table(&self) -> Result<PhysicalAddress, AllocError> {
+        let addr = self.allocate(0)?;
+        if let Some(frame) = self.memory_map.frame(addr.frame_number()) {
+            frame.flags.atomic_set(FrameFlag::PageTable);
+        }
+
+        // SAFETY: The frame was just allocated, so nothing else references it.
+        unsafe {
+            ptr::write_bytes(self.phys_to_ptr::<u8>(addr), 0, arch::PAGE_SIZE);
+        }
+        Ok(addr)
+    }
+
+    /// Takes an additional reference to the allocated block starting at `base`.
+    ///
+    /// Returns the new reference count, or `None` if `base` does not start an allocated block.
+    pub fn get_frame(&self, base: PhysicalAddress) -> Option<u32> {
+        self.allocated_block(base).map(crate::Frame::get)
+    }
+
+    /// Drops a reference to the allocated block starting at `base`.
+    ///
+    /// When the last reference is dropped the block is returned to the allocator. Returns the
+    /// remaining reference count, or `None` if `base` does not start an allocated block.
+    pub fn put_frame(&self, base: PhysicalAddress) -> Option<u32> {
+        let frame = self.allocated_block(base)?;
+        let order = frame.order() as usize;
+        let remaining = frame.put();
+        if remaining == 0 {
+            self.deallocate(base, order);
+        }
+        Some(remaining)
+    }
+
+    /// Returns the number of references to the allocated block starting at `base`, or zero if
+    /// `base` does not start an allocated block.
+    pub fn frame_references(&self, base: PhysicalAddress) -> u32 {
+        self.allocated_block(base)
+            .map_or(0, crate::Frame::ref_count)
+    }
+
+    /// Pins the allocated block starting at `base`, preventing it from being freed.
+    ///
+    /// Returns `false` if `base` does not start an allocated block.
+    pub fn lock_frame(&self, base: PhysicalAddress) -> bool {
+        self.allocated_block(base)
+            .map(|frame| frame.flags.atomic_set(FrameFlag::Locked))
+            .is_some()
+    }
+
+    /// Unpins a block previously pinned with [`lock_frame`](Self::lock_frame).
+    pub fn unlock_frame(&self, base: PhysicalAddress) {
+        if let Some(frame) = self.memory_map.frame(base.frame_number()) {
+            frame.flags.atomic_clear(FrameFlag::Locked);
+        }
+    }
+
+    /// Marks the frame at `addr` as faulty so it is never handed out again.
+    ///
+    /// If the frame is currently free, the free block containing it is split up and every other
+    /// frame is returned to the allocator. If it is allocated, it is withheld when its block is
+    /// freed.
+    pub fn poison_frame(&self, addr: PhysicalAddress) {
+        let Some(frame) = self.memory_map.frame(addr.frame_number()) else {
+            return;
+        };
+        frame.flags.atomic_set(FrameFlag::Poisoned);
+
+        if let Some((head, order)) = self.take_free_block_containing(addr) {
+            self.release_unpoisoned(head, order);
         }
+    }
 
-        // Add the (possibly coalesced) block to the free list
-        self.add_to_free_list(current_addr, current_order);
+    /// Marks the frames covering the kernel image as reserved so they are never freed.
+    pub fn mark_kernel_image(&mut self, base: PhysicalAddress, size: usize) {
+        let start = base.frame_number().as_usize();
+        let end = (base.as_usize() + size).div_ceil(arch::PAGE_SIZE);
+        for idx in start..end {
+            if let Some(frame) = self.memory_map.frame_mut(FrameNumber::new(idx)) {
+                frame.flags.set(FrameFlag::KernelImage);
+                if !frame.flags.test_and_set(FrameFlag::Reserved) {
+                    self.counters.reserved.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
     }
 
     /// Returns the total number of frames managed by this allocator.
@@ -273,11 +1031,7 @@ impl PhysicalMemoryManager {
 
     /// Returns the number of free frames across all orders.
     pub fn free_frames(&self) -> usize {
-        self.free_lists
-            .iter()
-            .enumerate()
-            .map(|(order, list)| list.count() * (1 << order))
-            .sum()
+        self.zones.iter().map(ZoneData::free_frames).sum()
     }
 
     /// Returns the number of free blocks at a specific order.
@@ -285,35 +1039,91 @@ impl PhysicalMemoryManager {
         if order > MAX_ORDER {
             return 0;
         }
-        self.free_lists[order].count()
-    }
-
-    /// Returns the number of allocated frames.
-    ///
-    /// Note: This counts frames marked as allocated in the memory map, which requires
-    /// iterating through all sections. For large memory maps, consider caching this value.
-    pub fn allocated_frames(&self) -> usize {
-        // This is a simplified count - in practice you'd want to track this
-        // We iterate through sections and count allocated frames
-        self.free_lists
+        self.zones
             .iter()
-            .enumerate()
-            .map(|(order, list)| {
-                // Each free block at order N represents 2^N frames that are NOT allocated
-                // Total frames - free frames = allocated frames, but we can't easily compute this
-                // without knowing the total. For now, return 0 if we haven't tracked allocations.
-                let _ = (order, list);
-                0
-            })
-            .sum::<usize>();
-        // For now, we don't have a good way to count allocated frames without
-        // iterating the entire memory map. Return total - free as an approximation.
-        self.total_frames.saturating_sub(self.free_frames())
+            .map(|zone| zone.free_lists[order].count())
+            .sum()
     }
 
-    /// Returns a reference to the frame metadata for the given frame number.
-    pub fn frame(&self, frame_number: FrameNumber) -> Option<&crate::Frame> {
-        self.memory_map.frame(frame_number)
+    /// Returns the number of free frames across all orders in a zone.
+    pub fn zone_free_frames(&self, zone: Zone) -> usize {
+        self.zones[zone as usize].free_frames()
+    }
+
+    /// Returns the number of free blocks at a specific order in a zone.
+    pub fn zone_free_blocks_at_order(&self, zone: Zone, order: usize) -> usize {
+        if order > MAX_ORDER {
+            return 0;
+        }
+        self.zones[zone as usize].free_lists[order].count()
+    }
+
+    /// Returns the number of usable frames the memory map reports inside a zone.
+    pub fn zone_present_frames(&self, zone: Zone) -> usize {
+        self.zones[zone as usize].present_frames
+    }
+
+    /// Returns the number of frames added to a zone via [`add_region`](Self::add_region).
+    pub fn zone_managed_frames(&self, zone: Zone) -> usize {
+        self.zones[zone as usize].managed_frames
+    }
+
+    /// Returns the frame range `[start, end)` of a zone covered by the memory map, if any.
+    pub fn zone_span(&self, zone: Zone) -> Option<(FrameNumber, FrameNumber)> {
+        let data = &self.zones[zone as usize];
+        (data.start_frame < data.end_frame).then_some((data.start_frame, data.end_frame))
+    }
+
+    /// Returns the watermarks of a zone.
+    pub fn watermarks(&self, zone: Zone) -> Watermarks {
+        self.zones[zone as usize].watermarks
+    }
+
+    /// Overrides the watermarks of a zone.
+    ///
+    /// Watermarks are recomputed whenever memory is added with [`add_region`](Self::add_region).
+    pub fn set_watermarks(&mut self, zone: Zone, watermarks: Watermarks) {
+        self.zones[zone as usize].watermarks = watermarks;
+    }
+
+    /// Returns the number of frames handed out by the allocator and not yet returned.
+    pub fn allocated_frames(&self) -> usize {
+        self.counters.allocated.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of frames flagged as reserved in the memory map.
+    pub fn reserved_frames(&self) -> usize {
+        self.counters.reserved.load(Ordering::Relaxed)
+    }
+
+    /// Returns a snapshot of the allocator's counters.
+    pub fn stats(&self) -> PmmStats {
+        let counters = &self.counters;
+        PmmStats {
+            total_frames: self.total_frames,
+            free_frames: self.free_frames(),
+            allocated_frames: counters.allocated.load(Ordering::Relaxed),
+            reserved_frames: counters.reserved.load(Ordering::Relaxed),
+            poisoned_frames: counters.poisoned.load(Ordering::Relaxed),
+            peak_allocated_frames: counters.peak_allocated.load(Ordering::Relaxed),
+            free_blocks: core::array::from_fn(|order| self.free_blocks_at_order(order)),
+            allocations: counters
+                .allocations
+                .each_ref()
+                .map(|count| count.load(Ordering::Relaxed)),
+            failures: counters
+                .failures
+                .each_ref()
+                .map(|count| count.load(Ordering::Relaxed)),
+            splits: counters.splits.load(Ordering::Relaxed),
+            merges: counters.merges.load(Ordering::Relaxed),
+            overwritten_blocks: counters.overwritten.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns a reference to the frame metadata for the given frame number.
+    pub fn frame(&self, frame_number: FrameNumber) -> Option<&crate::Frame> {
+        self.memory_map.frame(frame_number)
     }
 
     /// Returns a mutable reference to the frame metadata for the given frame number.
@@ -321,8 +1131,419 @@ impl PhysicalMemoryManager {
         self.memory_map.frame_mut(frame_number)
     }
 
+    /// Checks that the free lists and the frame metadata in the memory map agree.
+    ///
+    /// Walks every free list of every zone and returns the first broken invariant:
+    /// - each free block lies inside its zone, is aligned to its order, and has its head frame
+    ///   recorded at that order without the `Allocated` or `Reserved` flags;
+    /// - no two free blocks overlap;
+    /// - no two free buddies sit at the same order in the same zone (they should have merged);
+    /// - each list holds exactly as many blocks as its count says.
+    ///
+    /// This is `O(free blocks · log(free blocks))` and meant for tests and debug builds. Blocks
+    /// in the middle of being merged are on no list, so the result is only meaningful while no
+    /// other CPU is freeing memory.
+    #[cfg(any(test, debug_assertions, feature = "verify"))]
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        let mut blocks = alloc::vec::Vec::new();
+
+        for zone in Zone::ALL {
+            let (zone_start, zone_end) = zone.limits();
+            for (order, list) in self.zones[zone as usize].free_lists.iter().enumerate() {
+                let list = list.lock();
+                let recorded = list.count.load(Ordering::Acquire);
+                let block_size = (1 << order) * arch::PAGE_SIZE;
+
+                // Stop one block past the count, in case it is stale or the list has a cycle.
+                let mut counted = 0;
+                for block in list.iter().take(recorded + 1) {
+                    counted += 1;
+
+                    let addr = self.block_to_address(block);
+                    let start = addr.as_usize();
+                    if start < zone_start || start + block_size > zone_end {
+                        return Err(VerifyError::OutsideZone { zone, order, addr });
+                    }
+                    if !start.is_multiple_of(block_size) {
+                        return Err(VerifyError::Misaligned { zone, order, addr });
+                    }
+
+                    let Some(frame) = self.memory_map.frame(addr.frame_number()) else {
+                        return Err(VerifyError::Untracked { zone, order, addr });
+                    };
+                    if frame.flags.atomic_test(FrameFlag::Allocated)
+                        || frame.flags.atomic_test(FrameFlag::Reserved)
+                    {
+                        return Err(VerifyError::MarkedInUse { zone, order, addr });
+                    }
+                    if frame.order() as usize != order {
+                        return Err(VerifyError::OrderMismatch {
+                            zone,
+                            order,
+                            addr,
+                            recorded: frame.order(),
+                        });
+                    }
+
+                    blocks.push((start, order, zone));
+                }
+
+                if counted != recorded {
+                    return Err(VerifyError::CountMismatch {
+                        zone,
+                        order,
+                        counted,
+                        recorded,
+                    });
+                }
+            }
+        }
+
+        blocks.sort_unstable();
+
+        for pair in blocks.windows(2) {
+            let (first, first_order, _) = pair[0];
+            let (second, second_order, _) = pair[1];
+            if first + (1 << first_order) * arch::PAGE_SIZE > second {
+                return Err(VerifyError::Overlap {
+                    first: PhysicalAddress::new(first),
+                    first_order,
+                    second: PhysicalAddress::new(second),
+                    second_order,
+                });
+            }
+        }
+
+        for &(start, order, zone) in &blocks {
+            let buddy = start ^ ((1 << order) * arch::PAGE_SIZE);
+            if buddy > start
+                && Zone::containing(PhysicalAddress::new(buddy)) == zone
+                && blocks
+                    .binary_search_by(|&(other, other_order, _)| {
+                        (other, other_order).cmp(&(buddy, order))
+                    })
+                    .is_ok()
+            {
+                return Err(VerifyError::UnmergedBuddies {
+                    zone,
+                    order,
+                    addr: PhysicalAddress::new(start),
+                    buddy: PhysicalAddress::new(buddy),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
     // Private helper methods
 
+    /// Builds the zone state for `zone` from the frames covered by the memory map.
+    fn zone_from_memory_map(memory_map: &MemoryMap, zone: Zone) -> ZoneData {
+        let (zone_start, zone_end) = zone.limits();
+        let zone_start = zone_start / arch::PAGE_SIZE;
+        let zone_end = zone_end / arch::PAGE_SIZE;
+
+        let mut start = usize::MAX;
+        let mut end = 0;
+        let mut present = 0;
+        for (first, last) in memory_map
+            .sections()
+            .iter()
+            .filter_map(|section| section.frame_range())
+        {
+            let first = first.as_usize().max(zone_start);
+            let last = last.as_usize().min(zone_end);
+            if first >= last {
+                continue;
+            }
+
+            start = start.min(first);
+            end = end.max(last);
+            present += (first..last)
+                .filter_map(|idx| memory_map.frame(FrameNumber::new(idx)))
+                .filter(|frame| !frame.flags.atomic_test(FrameFlag::Reserved))
+                .count();
+        }
+
+        if start >= end {
+            return ZoneData::new(FrameNumber::new(0), FrameNumber::new(0), 0);
+        }
+        ZoneData::new(FrameNumber::new(start), FrameNumber::new(end), present)
+    }
+
+    /// Returns the frame heading the allocated block at `base`, if there is one.
+    fn allocated_block(&self, base: PhysicalAddress) -> Option<&crate::Frame> {
+        let frame = self.memory_map.frame(base.frame_number())?;
+        (frame.flags.atomic_test(FrameFlag::Allocated) && frame.order() as usize <= MAX_ORDER)
+            .then_some(frame)
+    }
+
+    /// Returns true if any frame in the block of 2^order frames at `base` is poisoned.
+    fn contains_poisoned(&self, base: PhysicalAddress, order: usize) -> bool {
+        let start = base.frame_number().as_usize();
+        (start..start + (1 << order)).any(|idx| {
+            self.memory_map
+                .frame(FrameNumber::new(idx))
+                .is_some_and(|frame| frame.flags.atomic_test(FrameFlag::Poisoned))
+        })
+    }
+
+    /// Checks that a block taken off the free lists still holds the free poison, reporting the
+    /// first byte written to while it was free. Does nothing without free poisoning.
+    fn check_free_poison(&self, base: PhysicalAddress, order: usize) {
+        #[cfg(feature = "free-poisoning")]
+        {
+            let size = (1 << order) * arch::PAGE_SIZE;
+            // SAFETY: The block is free and off every free list, so nothing else accesses it.
+            let overwritten =
+                unsafe { crate::free_poison::find_overwritten(self.phys_to_ptr(base), size) };
+            if let Some((offset, byte)) = overwritten {
+                let addr = PhysicalAddress::new(base.as_usize() + offset);
+                self.record_overwritten();
+                log::error!(
+                    "pmm: free order-{} block at {} was written to at {} ({:#04x}), frame {:?}",
+                    order,
+                    base,
+                    addr,
+                    byte,
+                    self.memory_map.frame(addr.frame_number())
+                );
+            }
+        }
+        #[cfg(not(feature = "free-poisoning"))]
+        let _ = (base, order);
+    }
+
+    /// Counts a free block or slab object found written to while it was free.
+    #[cfg(feature = "free-poisoning")]
+    pub(crate) fn record_overwritten(&self) {
+        self.counters.overwritten.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Frees every healthy frame of a block individually, withholding the poisoned ones.
+    ///
+    /// Poisoned frames are left marked as allocated so that they never coalesce with their
+    /// buddies.
+    fn release_unpoisoned(&self, base: PhysicalAddress, order: usize) {
+        let start = base.frame_number().as_usize();
+        for idx in start..start + (1 << order) {
+            let Some(frame) = self.memory_map.frame(FrameNumber::new(idx)) else {
+                continue;
+            };
+
+            if frame.flags.atomic_test(FrameFlag::Poisoned) {
+                if !frame.flags.atomic_test_and_set(FrameFlag::Allocated) {
+                    self.counters.poisoned.fetch_add(1, Ordering::Relaxed);
+                }
+                frame.set_ref_count(0);
+                frame.set_order(ORDER_NOT_BUDDY);
+            } else {
+                self.deallocate(PhysicalAddress::new(idx * arch::PAGE_SIZE), 0);
+            }
+        }
+    }
+
+    /// Takes the free block containing `addr` off its free list, returning its base address
+    /// and order.
+    fn take_free_block_containing(
+        &self,
+        addr: PhysicalAddress,
+    ) -> Option<(PhysicalAddress, usize)> {
+        (0..=MAX_ORDER).find_map(|order| {
+            let head = addr.align_down((1 << order) * arch::PAGE_SIZE);
+            self.take_free_block(head, order).then_some((head, order))
+        })
+    }
+
+    /// Finds a run of adjacent free blocks that can hold `size` bytes aligned to `align` below
+    /// `limit`, returning the start of the run and the aligned base of the allocation.
+    ///
+    /// The free lists are walked in place under their locks. From each block, the frame
+    /// metadata leads to the free blocks following it; nothing is allocated, so the search is
+    /// safe to run from inside a heap built on this allocator.
+    fn find_free_run(&self, size: usize, align: usize, limit: usize) -> Option<(usize, usize)> {
+        for data in &self.zones {
+            for list in &data.free_lists {
+                let list = list.lock();
+                for block in list.iter() {
+                    let run_start = self.block_to_address(block).as_usize();
+                    let base = run_start.next_multiple_of(align);
+                    let end = base + size;
+                    if end > limit {
+                        continue;
+                    }
+
+                    let mut run_end = run_start;
+                    while run_end < end {
+                        let Some(order) = self.free_order_at(run_end) else {
+                            break;
+                        };
+                        run_end += (1 << order) * arch::PAGE_SIZE;
+                    }
+                    if run_end >= end {
+                        return Some((run_start, base));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the order of the free block starting at `addr`, if there is one.
+    fn free_order_at(&self, addr: usize) -> Option<usize> {
+        let addr = PhysicalAddress::new(addr);
+        let order = self.memory_map.frame(addr.frame_number())?.order() as usize;
+        self.is_buddy_free(addr, order).then_some(order)
+    }
+
+    /// Counts the frames in `[start, end)` towards the managed memory of their zones and
+    /// recomputes the zones' watermarks.
+    fn manage_range(&mut self, start: usize, end: usize) {
+        for zone in Zone::ALL {
+            let (zone_start, zone_end) = zone.limits();
+            let region_start = start.max(zone_start);
+            let region_end = end.min(zone_end);
+            if region_start >= region_end {
+                continue;
+            }
+
+            let data = &mut self.zones[zone as usize];
+            data.managed_frames += (region_end - region_start) / arch::PAGE_SIZE;
+            data.watermarks = Watermarks::for_managed_frames(data.managed_frames);
+        }
+    }
+
+    /// Marks every frame of a region the block allocator handed out as an allocated boot frame.
+    fn add_boot_allocation(&mut self, region: MemoryRegion) {
+        let start = region.base().as_usize();
+        let end = region.end().as_usize();
+
+        let mut marked = 0;
+        for idx in start / arch::PAGE_SIZE..end / arch::PAGE_SIZE {
+            if let Some(frame) = self.memory_map.frame_mut(FrameNumber::new(idx)) {
+                frame.flags.set(FrameFlag::Allocated);
+                frame.flags.set(FrameFlag::Boot);
+                frame.set_order(ORDER_NOT_BUDDY);
+                marked += 1;
+            }
+        }
+        self.manage_range(start, end);
+        self.counters.allocate(marked);
+    }
+
+    /// Frees the frames in `[start, end)` in the largest naturally aligned blocks possible,
+    /// splitting at zone boundaries.
+    fn free_range(&self, start: usize, end: usize) {
+        for zone in Zone::ALL {
+            let (zone_start, zone_end) = zone.limits();
+            let mut addr = start.max(zone_start);
+            let end = end.min(zone_end);
+            while addr < end {
+                let order = Self::largest_order_at(addr, end - addr);
+                self.deallocate(PhysicalAddress::new(addr), order);
+                addr += (1 << order) * arch::PAGE_SIZE;
+            }
+        }
+    }
+
+    /// Returns the largest order of a naturally aligned block at `addr` fitting in `size` bytes.
+    fn largest_order_at(addr: usize, size: usize) -> usize {
+        let frame = addr / arch::PAGE_SIZE;
+        let frames = size / arch::PAGE_SIZE;
+        let align_order = if frame == 0 {
+            MAX_ORDER
+        } else {
+            frame.trailing_zeros() as usize
+        };
+        let size_order = frames.ilog2() as usize;
+        align_order.min(size_order).min(MAX_ORDER)
+    }
+
+    /// Allocates 2^order frames from `zone` or its fallbacks, as described in
+    /// [`allocate_in`](Self::allocate_in), without counting the allocation.
+    fn allocate_with_fallback(&self, zone: Zone, order: usize) -> Option<PhysicalAddress> {
+        zone.fallbacks()
+            .iter()
+            .enumerate()
+            .find_map(|(index, &candidate)| {
+                let watermarks = self.zones[candidate as usize].watermarks;
+                let floor = if index == 0 {
+                    watermarks.min
+                } else {
+                    watermarks.high
+                };
+                self.allocate_from_zone(candidate, order, floor)
+            })
+    }
+
+    /// Records the outcome of an allocation at `order` in the per-order counters.
+    fn count_allocation(
+        &self,
+        order: usize,
+        addr: Option<PhysicalAddress>,
+    ) -> Result<PhysicalAddress, AllocError> {
+        match addr {
+            Some(addr) => {
+                self.counters.allocations[order].fetch_add(1, Ordering::Relaxed);
+                Ok(addr)
+            }
+            None => {
+                self.counters.failures[order].fetch_add(1, Ordering::Relaxed);
+                Err(AllocError::OutOfMemory)
+            }
+        }
+    }
+
+    /// Pops and splits a block from a single zone, keeping at least `floor` frames free.
+    fn allocate_from_zone(
+        &self,
+        zone: Zone,
+        order: usize,
+        floor: usize,
+    ) -> Option<PhysicalAddress> {
+        let data = &self.zones[zone as usize];
+        if data.free_frames() < floor + (1 << order) {
+            return None;
+        }
+
+        // Pop a block at this order or higher. Another CPU may empty a list between finding it
+        // and locking it, in which case the search moves on to the next order up.
+        let mut min_order = order;
+        let (addr, alloc_order) = loop {
+            let alloc_order = data.find_free_order(min_order)?;
+            if let Some(block) = data.free_lists[alloc_order].lock().pop() {
+                let addr = self.block_to_address(block);
+                if let Some(frame) = self.memory_map.frame(addr.frame_number()) {
+                    frame.set_order(ORDER_NOT_BUDDY);
+                }
+                break (addr, alloc_order);
+            }
+            min_order = alloc_order + 1;
+        };
+
+        // Split the block down to the requested order
+        self.split_block(zone, addr, alloc_order, order);
+        self.check_free_poison(addr, order);
+
+        // Mark the block as allocated, holding a single reference. The order is set last so
+        // that the block never looks free to a CPU checking for buddies.
+        if let Some(frame) = self.memory_map.frame(addr.frame_number()) {
+            debug_assert!(
+                !frame.flags.atomic_test(FrameFlag::Poisoned)
+                    && !frame.flags.atomic_test(FrameFlag::Reserved),
+                "free list contained a poisoned or reserved frame at {}",
+                addr
+            );
+            frame.flags.atomic_set(FrameFlag::Allocated);
+            frame.set_ref_count(1);
+            frame.set_order(order as u8);
+        }
+
+        self.counters.allocate(1 << order);
+        Some(addr)
+    }
+
     /// Translates a physical address to a writable pointer.
     ///
     /// In software emulation mode, this uses the emulated memory region.
@@ -340,23 +1561,16 @@ impl PhysicalMemoryManager {
         PhysicalAddress::from_direct_mapped(virt)
     }
 
-    /// Finds the lowest order with available blocks that can satisfy the request.
-    fn find_free_order(&self, min_order: usize) -> Result<usize, AllocError> {
-        for order in min_order..=MAX_ORDER {
-            if !self.free_lists[order].is_empty() {
-                return Ok(order);
-            }
-        }
-        Err(AllocError::OutOfMemory)
-    }
-
     /// Splits a block from `from_order` down to `to_order`, adding buddies to free lists.
-    fn split_block(&mut self, addr: PhysicalAddress, from_order: usize, to_order: usize) {
+    fn split_block(&self, zone: Zone, addr: PhysicalAddress, from_order: usize, to_order: usize) {
         let current_addr = addr;
+        self.counters
+            .splits
+            .fetch_add((from_order - to_order) as u64, Ordering::Relaxed);
         for order in (to_order..from_order).rev() {
             let buddy_size = (1 << order) * arch::PAGE_SIZE;
             let buddy_addr = PhysicalAddress::new(current_addr.as_usize() + buddy_size);
-            self.add_to_free_list(buddy_addr, order);
+            self.add_to_free_list(zone, buddy_addr, order);
         }
     }
 
@@ -381,65 +1595,52 @@ impl PhysicalMemoryManager {
         }
     }
 
-    /// Adds a block to the free list at the given order.
-    fn add_to_free_list(&mut self, addr: PhysicalAddress, order: usize) {
-        let block_ptr = self.phys_to_ptr::<FreeBlock>(addr);
+    /// Adds a block to the free list of a zone at the given order.
+    fn add_to_free_list(&self, zone: Zone, addr: PhysicalAddress, order: usize) {
+        let mut list = self.zones[zone as usize].free_lists[order].lock();
+        self.push_locked(&mut list, addr, order);
+    }
 
-        // Mark the frame with the order
-        let frame_num = addr.frame_number();
-        if let Some(frame) = self.memory_map.frame_mut(frame_num) {
+    /// Pushes a block onto a locked free list of the given order.
+    ///
+    /// The head frame records the order while the list is locked, so the block is free exactly
+    /// while it is on the list.
+    fn push_locked(&self, list: &mut LockedFreeList<'_>, addr: PhysicalAddress, order: usize) {
+        if let Some(frame) = self.memory_map.frame(addr.frame_number()) {
             frame.set_order(order as u8);
         }
-
-        self.free_lists[order].push(block_ptr);
+        list.push(self.phys_to_ptr(addr));
     }
 
-    /// Removes a block from the free list at the given order.
+    /// Takes the block at `addr` off a locked free list of the given order, if it is free.
     ///
-    /// This is a slow O(n) operation that walks the list. It's only used during coalescing,
-    /// which is relatively rare compared to allocations.
-    fn remove_from_free_list(&mut self, addr: PhysicalAddress, order: usize) {
-        let target_ptr = self.phys_to_ptr::<FreeBlock>(addr);
-
-        let list = &self.free_lists[order];
-
-        loop {
-            let head = list.head.load(Ordering::Acquire);
-            if head.is_null() {
-                return;
-            }
+    /// Returns true if the block was on the list.
+    fn take_locked(
+        &self,
+        list: &mut LockedFreeList<'_>,
+        addr: PhysicalAddress,
+        order: usize,
+    ) -> bool {
+        if !self.is_buddy_free(addr, order) {
+            return false;
+        }
 
-            if head == target_ptr {
-                // Target is at the head
-                let next = unsafe { (*head).next };
-                if list
-                    .head
-                    .compare_exchange(head, next, Ordering::Release, Ordering::Acquire)
-                    .is_ok()
-                {
-                    list.count.fetch_sub(1, Ordering::Release);
-                    return;
-                }
-            } else {
-                // Walk the list to find the target
-                let mut prev = head;
-                loop {
-                    let current = unsafe { (*prev).next };
-                    if current.is_null() {
-                        return;
-                    }
-                    if current == target_ptr {
-                        let next = unsafe { (*current).next };
-                        unsafe {
-                            (*prev).next = next;
-                        }
-                        list.count.fetch_sub(1, Ordering::Release);
-                        return;
-                    }
-                    prev = current;
-                }
-            }
+        let removed = list.remove(self.phys_to_ptr(addr));
+        debug_assert!(
+            removed,
+            "free block at {} of order {} was not on its free list",
+            addr, order
+        );
+        if let Some(frame) = self.memory_map.frame(addr.frame_number()) {
+            frame.set_order(ORDER_NOT_BUDDY);
         }
+        removed
+    }
+
+    /// Takes the block at `addr` off the free list of its zone, if it is free at `order`.
+    fn take_free_block(&self, addr: PhysicalAddress, order: usize) -> bool {
+        let mut list = self.zones[Zone::containing(addr) as usize].free_lists[order].lock();
+        self.take_locked(&mut list, addr, order)
     }
 
     /// Converts a FreeBlock pointer to a physical address.
@@ -448,15 +1649,55 @@ impl PhysicalMemoryManager {
     }
 }
 
+/// A source of single frames for populating virtual memory.
+///
+/// [`PhysicalMemoryManager`] implements this directly. Callers whose page tables are allocated
+/// from the same memory (such as a kernel heap built on the PMM) can implement it with a type
+/// that only holds their lock for the duration of each call, so that an address space can
+/// allocate page tables while it populates a range.
+pub trait FrameAllocator {
+    /// Allocates a single frame, returning `None` when memory is exhausted.
+    fn allocate_frame(&mut self) -> Option<PhysicalAddress>;
+
+    /// Drops a reference to a frame, freeing it once no references remain.
+    fn release_frame(&mut self, frame: PhysicalAddress);
+
+    /// Takes an additional reference to an allocated frame, so that it can be shared.
+    fn share_frame(&mut self, frame: PhysicalAddress);
+
+    /// Returns the number of references held to an allocated frame, or 0 if it isn't one.
+    fn frame_references(&mut self, frame: PhysicalAddress) -> u32;
+}
+
+impl FrameAllocator for PhysicalMemoryManager {
+    fn allocate_frame(&mut self) -> Option<PhysicalAddress> {
+        self.allocate(0).ok()
+    }
+
+    fn release_frame(&mut self, frame: PhysicalAddress) {
+        self.put_frame(frame);
+    }
+
+    fn share_frame(&mut self, frame: PhysicalAddress) {
+        self.get_frame(frame);
+    }
+
+    fn frame_references(&mut self, frame: PhysicalAddress) -> u32 {
+        PhysicalMemoryManager::frame_references(self, frame)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::BootMemoryRegion;
+    use alloc::vec::Vec;
 
     /// Test implementation of BootMemoryRegion.
     struct TestRegion {
         base: PhysicalAddress,
         size: usize,
+        reclaimable: bool,
     }
 
     impl TestRegion {
@@ -464,6 +1705,14 @@ mod tests {
             Self {
                 base: PhysicalAddress::new(base),
                 size,
+                reclaimable: false,
+            }
+        }
+
+        fn reclaimable(base: usize, size: usize) -> Self {
+            Self {
+                reclaimable: true,
+                ..Self::new(base, size)
             }
         }
     }
@@ -478,7 +1727,11 @@ mod tests {
         }
 
         fn is_usable(&self) -> bool {
-            true
+            !self.reclaimable
+        }
+
+        fn is_reclaimable(&self) -> bool {
+            self.reclaimable
         }
     }
 
@@ -504,7 +1757,7 @@ mod tests {
     #[test]
     fn deallocates_single_frame() {
         let memmap = setup_test_memmap(4096);
-        let mut pmm = PhysicalMemoryManager::new(memmap);
+        let pmm = PhysicalMemoryManager::new(memmap);
 
         // Deallocate a single frame at order 0
         pmm.deallocate(PhysicalAddress::new(0), 0);
@@ -516,7 +1769,7 @@ mod tests {
     #[test]
     fn allocates_single_frame() {
         let memmap = setup_test_memmap(4096);
-        let mut pmm = PhysicalMemoryManager::new(memmap);
+        let pmm = PhysicalMemoryManager::new(memmap);
 
         // Add a frame
         pmm.deallocate(PhysicalAddress::new(0), 0);
@@ -531,7 +1784,7 @@ mod tests {
     #[test]
     fn coalesces_buddies() {
         let memmap = setup_test_memmap(4096);
-        let mut pmm = PhysicalMemoryManager::new(memmap);
+        let pmm = PhysicalMemoryManager::new(memmap);
 
         let frame_size = arch::PAGE_SIZE;
 
@@ -543,12 +1796,13 @@ mod tests {
         assert_eq!(pmm.free_blocks_at_order(0), 0);
         assert_eq!(pmm.free_blocks_at_order(1), 1);
         assert_eq!(pmm.free_frames(), 2);
+        pmm.verify().unwrap();
     }
 
     #[test]
     fn splits_larger_blocks() {
         let memmap = setup_test_memmap(4096);
-        let mut pmm = PhysicalMemoryManager::new(memmap);
+        let pmm = PhysicalMemoryManager::new(memmap);
 
         // Deallocate an order-2 block (4 frames)
         pmm.deallocate(PhysicalAddress::new(0), 2);
@@ -560,4 +1814,1125 @@ mod tests {
         // Should have split: used 1 frame, have 3 left
         assert_eq!(pmm.free_frames(), 3);
     }
+
+    fn frames(count: usize) -> usize {
+        count * arch::PAGE_SIZE
+    }
+
+    #[test]
+    fn zone_containing_address() {
+        assert_eq!(Zone::containing(PhysicalAddress::new(0)), Zone::Dma);
+        assert_eq!(
+            Zone::containing(PhysicalAddress::new(arch::ZONE_DMA_LIMIT - 1)),
+            Zone::Dma
+        );
+        assert_eq!(
+            Zone::containing(PhysicalAddress::new(arch::ZONE_DMA_LIMIT)),
+            Zone::Dma32
+        );
+        assert_eq!(
+            Zone::containing(PhysicalAddress::new(arch::ZONE_DMA32_LIMIT)),
+            Zone::Normal
+        );
+    }
+
+    #[test]
+    fn zone_spans_follow_memory_map() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+
+        let dma_frames = arch::ZONE_DMA_LIMIT / arch::PAGE_SIZE;
+        let dma32_frames = arch::ZONE_DMA32_LIMIT / arch::PAGE_SIZE;
+        assert_eq!(
+            pmm.zone_span(Zone::Dma),
+            Some((FrameNumber::new(0), FrameNumber::new(dma_frames)))
+        );
+        assert_eq!(
+            pmm.zone_span(Zone::Dma32),
+            Some((FrameNumber::new(dma_frames), FrameNumber::new(dma32_frames)))
+        );
+        assert_eq!(
+            pmm.zone_span(Zone::Normal),
+            Some((FrameNumber::new(dma32_frames), FrameNumber::new(4096)))
+        );
+        assert_eq!(pmm.zone_present_frames(Zone::Dma), dma_frames);
+        assert_eq!(pmm.zone_managed_frames(Zone::Dma), 0);
+    }
+
+    #[test]
+    fn add_region_splits_at_zone_boundaries() {
+        let memmap = setup_test_memmap(4096);
+        let mut pmm = PhysicalMemoryManager::new(memmap);
+
+        pmm.add_region(PhysicalAddress::new(0), frames(4096));
+
+        let dma_frames = arch::ZONE_DMA_LIMIT / arch::PAGE_SIZE;
+        let dma32_frames = arch::ZONE_DMA32_LIMIT / arch::PAGE_SIZE;
+        assert_eq!(pmm.free_frames(), 4096);
+        assert_eq!(pmm.zone_free_frames(Zone::Dma), dma_frames);
+        assert_eq!(pmm.zone_free_frames(Zone::Dma32), dma32_frames - dma_frames);
+        assert_eq!(pmm.zone_free_frames(Zone::Normal), 4096 - dma32_frames);
+        assert_eq!(pmm.zone_managed_frames(Zone::Normal), 4096 - dma32_frames);
+        pmm.verify().unwrap();
+    }
+
+    #[test]
+    fn add_region_trims_unaligned_edges() {
+        let memmap = setup_test_memmap(4096);
+        let mut pmm = PhysicalMemoryManager::new(memmap);
+
+        // Starts just inside frame 1 and ends half-way into frame 8.
+        pmm.add_region(
+            PhysicalAddress::new(frames(1) + 1),
+            frames(7) + arch::PAGE_SIZE / 2 - 1,
+        );
+
+        // Only frames 2 through 7 are whole: an order-1 block at 2, an order-2 block at 4.
+        assert_eq!(pmm.free_frames(), 6);
+        assert_eq!(pmm.free_blocks_at_order(0), 0);
+        assert_eq!(pmm.free_blocks_at_order(1), 1);
+        assert_eq!(pmm.free_blocks_at_order(2), 1);
+    }
+
+    #[test]
+    fn allocate_in_respects_zone() {
+        let memmap = setup_test_memmap(4096);
+        let mut pmm = PhysicalMemoryManager::new(memmap);
+        pmm.add_region(PhysicalAddress::new(0), frames(4096));
+
+        for zone in Zone::ALL {
+            let addr = pmm.allocate_in(zone, 0).unwrap();
+            assert_eq!(Zone::containing(addr), zone);
+        }
+    }
+
+    #[test]
+    fn allocate_falls_back_to_lower_zones() {
+        let memmap = setup_test_memmap(4096);
+        let mut pmm = PhysicalMemoryManager::new(memmap);
+
+        // Only DMA memory is available.
+        pmm.add_region(PhysicalAddress::new(0), arch::ZONE_DMA_LIMIT);
+        pmm.set_watermarks(Zone::Dma, Watermarks::default());
+
+        let addr = pmm.allocate(0).unwrap();
+        assert_eq!(Zone::containing(addr), Zone::Dma);
+        let addr = pmm.allocate_in(Zone::Dma32, 0).unwrap();
+        assert_eq!(Zone::containing(addr), Zone::Dma);
+    }
+
+    #[test]
+    fn allocate_never_falls_back_to_higher_zones() {
+        let memmap = setup_test_memmap(4096);
+        let mut pmm = PhysicalMemoryManager::new(memmap);
+
+        // Only Normal memory is available.
+        pmm.add_region(
+            PhysicalAddress::new(arch::ZONE_DMA32_LIMIT),
+            frames(4096) - arch::ZONE_DMA32_LIMIT,
+        );
+
+        assert_eq!(pmm.allocate_in(Zone::Dma, 0), Err(AllocError::OutOfMemory));
+        assert_eq!(
+            pmm.allocate_in(Zone::Dma32, 0),
+            Err(AllocError::OutOfMemory)
+        );
+        assert!(pmm.allocate_in(Zone::Normal, 0).is_ok());
+    }
+
+    #[test]
+    fn buddies_do_not_merge_across_zones() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+
+        // The last DMA block and the first DMA32 block are buddies by address.
+        let order = (arch::ZONE_DMA_LIMIT / arch::PAGE_SIZE).ilog2() as usize;
+        pmm.deallocate(PhysicalAddress::new(0), order);
+        pmm.deallocate(PhysicalAddress::new(arch::ZONE_DMA_LIMIT), order);
+
+        assert_eq!(pmm.free_blocks_at_order(order), 2);
+        assert_eq!(pmm.free_blocks_at_order(order + 1), 0);
+        assert_eq!(pmm.zone_free_blocks_at_order(Zone::Dma, order), 1);
+        assert_eq!(pmm.zone_free_blocks_at_order(Zone::Dma32, order), 1);
+        pmm.verify().unwrap();
+    }
+
+    #[test]
+    fn watermarks_scale_with_managed_frames() {
+        let memmap = setup_test_memmap(4096);
+        let mut pmm = PhysicalMemoryManager::new(memmap);
+        pmm.add_region(PhysicalAddress::new(0), frames(4096));
+
+        let managed = pmm.zone_managed_frames(Zone::Normal);
+        let watermarks = pmm.watermarks(Zone::Normal);
+        assert_eq!(watermarks, Watermarks::for_managed_frames(managed));
+        assert!(watermarks.min <= watermarks.low && watermarks.low <= watermarks.high);
+    }
+
+    #[test]
+    fn fallback_stops_at_high_watermark() {
+        let memmap = setup_test_memmap(4096);
+        let mut pmm = PhysicalMemoryManager::new(memmap);
+
+        let dma_frames = arch::ZONE_DMA_LIMIT / arch::PAGE_SIZE;
+        pmm.add_region(PhysicalAddress::new(0), arch::ZONE_DMA_LIMIT);
+        pmm.set_watermarks(
+            Zone::Dma,
+            Watermarks {
+                min: 0,
+                low: dma_frames - 2,
+                high: dma_frames - 1,
+            },
+        );
+
+        // A Normal allocation may take DMA frames only while `high` frames remain.
+        assert!(pmm.allocate(0).is_ok());
+        assert_eq!(pmm.allocate(0), Err(AllocError::OutOfMemory));
+
+        // A DMA allocation only has to respect `min`.
+        assert!(pmm.allocate_in(Zone::Dma, 0).is_ok());
+    }
+
+    #[test]
+    fn allocation_holds_one_reference() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.deallocate(PhysicalAddress::new(0), 2);
+
+        let addr = pmm.allocate(1).unwrap();
+        let frame = pmm.frame(addr.frame_number()).unwrap();
+        assert_eq!(frame.ref_count(), 1);
+    }
+
+    #[test]
+    fn put_frees_when_last_reference_dropped() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.deallocate(PhysicalAddress::new(0), 2);
+
+        let addr = pmm.allocate(2).unwrap();
+        assert_eq!(pmm.get_frame(addr), Some(2));
+        assert_eq!(pmm.put_frame(addr), Some(1));
+        assert_eq!(pmm.free_frames(), 0);
+
+        assert_eq!(pmm.put_frame(addr), Some(0));
+        assert_eq!(pmm.free_frames(), 4);
+        assert_eq!(pmm.free_blocks_at_order(2), 1);
+    }
+
+    #[test]
+    fn get_and_put_reject_free_frames() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.deallocate(PhysicalAddress::new(0), 2);
+
+        assert_eq!(pmm.get_frame(PhysicalAddress::new(0)), None);
+        assert_eq!(pmm.put_frame(PhysicalAddress::new(0)), None);
+        assert_eq!(pmm.free_frames(), 4);
+    }
+
+    #[test]
+    fn merged_blocks_only_have_one_head() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+
+        pmm.deallocate(PhysicalAddress::new(arch::PAGE_SIZE), 0);
+        pmm.deallocate(PhysicalAddress::new(0), 0);
+
+        assert_eq!(pmm.frame(FrameNumber::new(0)).unwrap().order(), 1);
+        assert_eq!(
+            pmm.frame(FrameNumber::new(1)).unwrap().order(),
+            ORDER_NOT_BUDDY
+        );
+    }
+
+    #[test]
+    fn locked_blocks_are_not_freed() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.deallocate(PhysicalAddress::new(0), 0);
+
+        let addr = pmm.allocate(0).unwrap();
+        assert!(pmm.lock_frame(addr));
+        pmm.deallocate(addr, 0);
+        assert_eq!(pmm.free_frames(), 0);
+
+        pmm.unlock_frame(addr);
+        pmm.deallocate(addr, 0);
+        assert_eq!(pmm.free_frames(), 1);
+    }
+
+    #[test]
+    fn kernel_image_is_never_freed() {
+        let memmap = setup_test_memmap(4096);
+        let mut pmm = PhysicalMemoryManager::new(memmap);
+
+        pmm.mark_kernel_image(PhysicalAddress::new(0), frames(2));
+        pmm.deallocate(PhysicalAddress::new(0), 0);
+        pmm.deallocate(PhysicalAddress::new(arch::PAGE_SIZE), 0);
+        pmm.deallocate(PhysicalAddress::new(frames(2)), 0);
+
+        assert_eq!(pmm.free_frames(), 1);
+        let frame = pmm.frame(FrameNumber::new(0)).unwrap();
+        assert!(frame.flags.atomic_test(FrameFlag::KernelImage));
+        assert!(frame.flags.atomic_test(FrameFlag::Reserved));
+    }
+
+    /// Builds a PMM over 256 frames and a block allocator over the same memory, which has
+    /// handed out the frames at 0..3 and 0x10..0x12 and had the frames at 0x20..0x24 reserved.
+    fn pmm_with_block_allocator() -> (PhysicalMemoryManager, BlockAllocator) {
+        let pmm = PhysicalMemoryManager::new(setup_test_memmap(256));
+        let mut block = BlockAllocator::new();
+        block.add(PhysicalAddress::new(0), frames(256)).unwrap();
+        block
+            .reserve(PhysicalAddress::new(frames(0x20)), frames(4))
+            .unwrap();
+        block.allocate_raw(frames(3), arch::PAGE_SIZE).unwrap();
+        block.allocate_raw(frames(2), frames(0x10)).unwrap();
+        (pmm, block)
+    }
+
+    #[test]
+    fn imports_block_allocations_as_allocated() {
+        let (mut pmm, block) = pmm_with_block_allocator();
+        pmm.import_block_allocator(block);
+
+        assert_eq!(pmm.free_frames(), 256 - 9);
+        assert_eq!(pmm.allocated_frames(), 9);
+        assert_eq!(
+            pmm.zone_managed_frames(Zone::containing(PhysicalAddress::new(0))),
+            256
+        );
+        for idx in [0, 2, 0x10, 0x11, 0x20, 0x23] {
+            assert!(pmm.is_boot_allocation(PhysicalAddress::new(frames(idx))));
+        }
+        for idx in [3, 0x12, 0x24] {
+            assert!(!pmm.is_boot_allocation(PhysicalAddress::new(frames(idx))));
+        }
+        pmm.verify().unwrap();
+
+        // None of the imported frames is handed out again.
+        while let Ok(addr) = pmm.allocate(0) {
+            assert!(
+                !pmm.is_boot_allocation(addr),
+                "{} was allocated twice",
+                addr
+            );
+        }
+    }
+
+    #[test]
+    fn boot_allocations_are_freed_with_deallocate_boot() {
+        let (mut pmm, block) = pmm_with_block_allocator();
+        pmm.import_block_allocator(block);
+
+        // Freeing with a guessed order or as a contiguous run is refused.
+        pmm.deallocate(PhysicalAddress::new(0), 2);
+        pmm.deallocate_contiguous(PhysicalAddress::new(frames(0x10)), 2);
+        assert_eq!(pmm.allocated_frames(), 9);
+        assert!(pmm.is_boot_allocation(PhysicalAddress::new(0)));
+
+        // So is freeing a range that runs past the boot allocation.
+        pmm.deallocate_boot(PhysicalAddress::new(0), frames(4));
+        assert_eq!(pmm.allocated_frames(), 9);
+
+        pmm.deallocate_boot(PhysicalAddress::new(0), frames(2) + 1);
+        pmm.deallocate_boot(PhysicalAddress::new(frames(0x10)), frames(2));
+        assert_eq!(pmm.allocated_frames(), 4);
+        assert_eq!(pmm.free_frames(), 256 - 4);
+        assert!(!pmm.is_boot_allocation(PhysicalAddress::new(0)));
+        pmm.verify().unwrap();
+    }
+
+    #[test]
+    fn reclaim_region_frees_reserved_frames() {
+        setup_test_memmap(128);
+        let boot_map = [
+            TestRegion::new(0, frames(64)),
+            TestRegion::reclaimable(frames(64), frames(64)),
+        ];
+        let mut pmm = PhysicalMemoryManager::new(MemoryMap::from_boot_map(&boot_map));
+        pmm.add_region(PhysicalAddress::new(0), frames(64));
+        pmm.mark_kernel_image(PhysicalAddress::new(frames(64)), frames(1));
+        assert_eq!(pmm.free_frames(), 64);
+
+        let kept = PhysicalAddress::new(frames(100));
+        let reclaimed = pmm.reclaim_region(PhysicalAddress::new(frames(64)), frames(64), |addr| {
+            addr == kept
+        });
+
+        assert_eq!(reclaimed, 62);
+        assert_eq!(pmm.free_frames(), 126);
+        assert_eq!(pmm.reserved_frames(), 2);
+        assert_eq!(pmm.zone_managed_frames(Zone::Dma), 126);
+        let frame = pmm.frame(FrameNumber::new(70)).unwrap();
+        assert!(!frame.flags.atomic_test(FrameFlag::Reserved));
+        let frame = pmm.frame(kept.frame_number()).unwrap();
+        assert!(frame.flags.atomic_test(FrameFlag::Reserved));
+
+        // Reclaimed frames are no longer reserved, so reclaiming again frees nothing twice.
+        let reclaimed = pmm.reclaim_region(PhysicalAddress::new(frames(64)), frames(64), |_| false);
+        assert_eq!(reclaimed, 1);
+        assert_eq!(pmm.free_frames(), 127);
+    }
+
+    #[test]
+    fn poisoning_a_free_frame_isolates_it() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.deallocate(PhysicalAddress::new(0), 2);
+
+        pmm.poison_frame(PhysicalAddress::new(frames(1)));
+
+        assert_eq!(pmm.free_frames(), 3);
+        for _ in 0..3 {
+            let addr = pmm.allocate(0).unwrap();
+            assert_ne!(addr, PhysicalAddress::new(frames(1)));
+        }
+        assert_eq!(pmm.allocate(0), Err(AllocError::OutOfMemory));
+    }
+
+    #[test]
+    fn poisoned_frames_are_withheld_on_free() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.deallocate(PhysicalAddress::new(0), 2);
+
+        let addr = pmm.allocate(2).unwrap();
+        pmm.poison_frame(PhysicalAddress::new(addr.as_usize() + frames(3)));
+        pmm.deallocate(addr, 2);
+
+        assert_eq!(pmm.free_frames(), 3);
+        assert_eq!(pmm.free_blocks_at_order(1), 1);
+        assert_eq!(pmm.free_blocks_at_order(0), 1);
+        assert_eq!(pmm.allocated_frames(), 0);
+        assert_eq!(pmm.stats().poisoned_frames, 1);
+    }
+
+    #[test]
+    fn page_table_frames_are_tagged_and_zeroed() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.deallocate(PhysicalAddress::new(0), 0);
+
+        let ptr = VirtualAddress::direct_mapped(PhysicalAddress::new(0)).as_mut_ptr::<u8>();
+        unsafe { ptr::write_bytes(ptr, 0xAA, arch::PAGE_SIZE) };
+
+        let addr = pmm.allocate_page_table().unwrap();
+        let frame = pmm.frame(addr.frame_number()).unwrap();
+        assert!(frame.flags.atomic_test(FrameFlag::PageTable));
+        let contents = unsafe { core::slice::from_raw_parts(ptr, arch::PAGE_SIZE) };
+        assert!(contents.iter().all(|&b| b == 0));
+
+        pmm.deallocate(addr, 0);
+        let frame = pmm.frame(addr.frame_number()).unwrap();
+        assert!(!frame.flags.atomic_test(FrameFlag::PageTable));
+    }
+
+    #[cfg(feature = "free-poisoning")]
+    #[test]
+    fn allocations_hold_the_free_poison() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.deallocate(PhysicalAddress::new(0), 2);
+
+        let addr = pmm.allocate(0).unwrap();
+        let ptr = VirtualAddress::direct_mapped(addr).as_mut_ptr::<u8>();
+        let contents = unsafe { core::slice::from_raw_parts(ptr, arch::PAGE_SIZE) };
+        assert!(contents.iter().all(|&b| b == crate::FREE_POISON));
+
+        pmm.deallocate(addr, 0);
+        assert_eq!(pmm.allocate(2), Ok(PhysicalAddress::new(0)));
+        assert_eq!(pmm.stats().overwritten_blocks, 0);
+    }
+
+    #[cfg(feature = "free-poisoning")]
+    #[test]
+    fn writes_to_free_blocks_are_reported() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.deallocate(PhysicalAddress::new(0), 1);
+
+        let stale = PhysicalAddress::new(frames(1) + 8);
+        unsafe { *VirtualAddress::direct_mapped(stale).as_mut_ptr::<u8>() = 0 };
+
+        assert_eq!(pmm.allocate(1), Ok(PhysicalAddress::new(0)));
+        assert_eq!(pmm.stats().overwritten_blocks, 1);
+    }
+
+    #[test]
+    fn verify_accepts_mixed_operations() {
+        let memmap = setup_test_memmap(4096);
+        let mut pmm = PhysicalMemoryManager::new(memmap);
+        pmm.add_region(PhysicalAddress::new(0), frames(4096));
+
+        let mut held = Vec::new();
+        for order in [0, 3, 1, 0, 5, 2, 0] {
+            held.push((pmm.allocate(order).unwrap(), order));
+        }
+        pmm.verify().unwrap();
+
+        for (addr, order) in held.drain(..).step_by(2) {
+            pmm.deallocate(addr, order);
+        }
+        pmm.verify().unwrap();
+    }
+
+    #[test]
+    fn verify_reports_allocated_free_block() {
+        let memmap = setup_test_memmap(4096);
+        let mut pmm = PhysicalMemoryManager::new(memmap);
+        pmm.deallocate(PhysicalAddress::new(0), 2);
+
+        pmm.frame_mut(FrameNumber::new(0))
+            .unwrap()
+            .flags
+            .set(FrameFlag::Allocated);
+
+        assert_eq!(
+            pmm.verify(),
+            Err(VerifyError::MarkedInUse {
+                zone: Zone::Dma,
+                order: 2,
+                addr: PhysicalAddress::new(0),
+            })
+        );
+    }
+
+    #[test]
+    fn verify_reports_misaligned_block() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.add_to_free_list(Zone::Dma, PhysicalAddress::new(frames(1)), 1);
+
+        assert_eq!(
+            pmm.verify(),
+            Err(VerifyError::Misaligned {
+                zone: Zone::Dma,
+                order: 1,
+                addr: PhysicalAddress::new(frames(1)),
+            })
+        );
+    }
+
+    #[test]
+    fn verify_reports_overlapping_blocks() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.add_to_free_list(Zone::Dma, PhysicalAddress::new(0), 2);
+        pmm.add_to_free_list(Zone::Dma, PhysicalAddress::new(frames(2)), 0);
+
+        assert_eq!(
+            pmm.verify(),
+            Err(VerifyError::Overlap {
+                first: PhysicalAddress::new(0),
+                first_order: 2,
+                second: PhysicalAddress::new(frames(2)),
+                second_order: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn verify_reports_unmerged_buddies() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.add_to_free_list(Zone::Dma, PhysicalAddress::new(0), 0);
+        pmm.add_to_free_list(Zone::Dma, PhysicalAddress::new(frames(1)), 0);
+
+        assert_eq!(
+            pmm.verify(),
+            Err(VerifyError::UnmergedBuddies {
+                zone: Zone::Dma,
+                order: 0,
+                addr: PhysicalAddress::new(0),
+                buddy: PhysicalAddress::new(frames(1)),
+            })
+        );
+    }
+
+    #[test]
+    fn verify_reports_count_mismatch() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.deallocate(PhysicalAddress::new(0), 3);
+        pmm.zones[Zone::Dma as usize].free_lists[3]
+            .count
+            .fetch_add(1, Ordering::Release);
+
+        assert_eq!(
+            pmm.verify(),
+            Err(VerifyError::CountMismatch {
+                zone: Zone::Dma,
+                order: 3,
+                counted: 1,
+                recorded: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn stats_count_allocations_splits_and_merges() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.deallocate(PhysicalAddress::new(0), 3);
+        assert_eq!(pmm.allocated_frames(), 0);
+
+        let first = pmm.allocate(0).unwrap();
+        let second = pmm.allocate(1).unwrap();
+        assert_eq!(pmm.allocated_frames(), 3);
+
+        pmm.deallocate(first, 0);
+        pmm.deallocate(second, 1);
+
+        let stats = pmm.stats();
+        assert_eq!(stats.allocated_frames, 0);
+        assert_eq!(stats.peak_allocated_frames, 3);
+        assert_eq!(stats.free_frames, 8);
+        assert_eq!(stats.free_blocks[3], 1);
+        assert_eq!(stats.allocations[0], 1);
+        assert_eq!(stats.allocations[1], 1);
+        assert_eq!(stats.splits, 3);
+        assert_eq!(stats.merges, 3);
+    }
+
+    #[test]
+    fn stats_count_failures_per_order() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.deallocate(PhysicalAddress::new(0), 1);
+
+        assert_eq!(pmm.allocate(2), Err(AllocError::OutOfMemory));
+        assert_eq!(pmm.allocate(2), Err(AllocError::OutOfMemory));
+        pmm.allocate(0).unwrap();
+
+        let stats = pmm.stats();
+        assert_eq!(stats.failures[2], 2);
+        assert_eq!(stats.allocations[0], 1);
+        assert_eq!(stats.allocations[2], 0);
+    }
+
+    #[test]
+    fn stats_count_reserved_frames() {
+        setup_test_memmap(128);
+        let boot_map = [
+            TestRegion::new(0, frames(64)),
+            TestRegion::reclaimable(frames(64), frames(64)),
+        ];
+        let mut pmm = PhysicalMemoryManager::new(MemoryMap::from_boot_map(&boot_map));
+        assert_eq!(pmm.reserved_frames(), 64);
+
+        pmm.mark_kernel_image(PhysicalAddress::new(0), frames(2));
+        assert_eq!(pmm.reserved_frames(), 66);
+    }
+
+    #[test]
+    fn allocate_aligned_counts_only_the_requested_frames() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.deallocate(PhysicalAddress::new(0), 4);
+
+        let addr = pmm.allocate_aligned(1, 3).unwrap();
+        assert_eq!(pmm.allocated_frames(), 2);
+        let allocations = pmm.stats().allocations;
+        assert_eq!(allocations[1], 1);
+        assert_eq!(allocations.iter().sum::<u64>(), 1);
+
+        pmm.deallocate(addr, 1);
+        assert_eq!(pmm.allocated_frames(), 0);
+        assert_eq!(pmm.free_frames(), 16);
+    }
+
+    #[test]
+    fn allocate_contiguous_frees_the_tail_back() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.deallocate(PhysicalAddress::new(0), 2);
+
+        let max = PhysicalAddress::new(0xFFFF);
+        let addr = pmm.allocate_contiguous(3, arch::PAGE_SIZE, max).unwrap();
+
+        assert_eq!(addr, PhysicalAddress::new(0));
+        assert_eq!(pmm.free_frames(), 1);
+        assert_eq!(pmm.allocated_frames(), 3);
+        assert_eq!(pmm.allocate(0), Ok(PhysicalAddress::new(frames(3))));
+        pmm.deallocate(PhysicalAddress::new(frames(3)), 0);
+        pmm.verify().unwrap();
+
+        pmm.deallocate_contiguous(addr, 3);
+        assert_eq!(pmm.allocated_frames(), 0);
+        assert_eq!(pmm.free_blocks_at_order(2), 1);
+        pmm.verify().unwrap();
+    }
+
+    #[test]
+    fn allocate_contiguous_spans_buddy_blocks_and_zones() {
+        let memmap = setup_test_memmap(4096);
+        let mut pmm = PhysicalMemoryManager::new(memmap);
+        pmm.add_region(PhysicalAddress::new(0), frames(4096));
+
+        // Larger than the biggest buddy block, so it must span several of them.
+        let count = (1 << MAX_ORDER) + 1000;
+        let addr = pmm
+            .allocate_contiguous(count, arch::PAGE_SIZE, PhysicalAddress::new(0xFFFF))
+            .unwrap();
+
+        assert_eq!(addr, PhysicalAddress::new(0));
+        assert_eq!(pmm.free_frames(), 4096 - count);
+        pmm.verify().unwrap();
+
+        pmm.deallocate_contiguous(addr, count);
+        assert_eq!(pmm.free_frames(), 4096);
+        pmm.verify().unwrap();
+    }
+
+    #[test]
+    fn allocate_contiguous_respects_alignment() {
+        let memmap = setup_test_memmap(4096);
+        let mut pmm = PhysicalMemoryManager::new(memmap);
+        pmm.add_region(PhysicalAddress::new(frames(1)), frames(15));
+
+        let addr = pmm
+            .allocate_contiguous(5, frames(4), PhysicalAddress::new(0xFFFF))
+            .unwrap();
+
+        assert_eq!(addr, PhysicalAddress::new(frames(4)));
+        assert_eq!(pmm.free_frames(), 10);
+        pmm.verify().unwrap();
+    }
+
+    #[test]
+    fn allocate_contiguous_respects_max_address() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.deallocate(PhysicalAddress::new(0), 1);
+        pmm.deallocate(PhysicalAddress::new(frames(8)), 3);
+
+        let max = PhysicalAddress::new(frames(12) - 1);
+        assert_eq!(
+            pmm.allocate_contiguous(5, arch::PAGE_SIZE, max),
+            Err(AllocError::OutOfMemory)
+        );
+        assert_eq!(
+            pmm.allocate_contiguous(4, arch::PAGE_SIZE, max),
+            Ok(PhysicalAddress::new(frames(8)))
+        );
+        pmm.verify().unwrap();
+    }
+
+    #[test]
+    fn allocate_contiguous_rejects_invalid_requests() {
+        let memmap = setup_test_memmap(4096);
+        let pmm = PhysicalMemoryManager::new(memmap);
+        pmm.deallocate(PhysicalAddress::new(0), 2);
+        let max = PhysicalAddress::new(0xFFFF);
+
+        assert_eq!(
+            pmm.allocate_contiguous(0, arch::PAGE_SIZE, max),
+            Err(AllocError::InvalidSize)
+        );
+        assert_eq!(
+            pmm.allocate_contiguous(1, 3 * arch::PAGE_SIZE, max),
+            Err(AllocError::InvalidAlignment)
+        );
+        assert_eq!(
+            pmm.allocate_contiguous(5, arch::PAGE_SIZE, max),
+            Err(AllocError::OutOfMemory)
+        );
+        assert_eq!(pmm.free_frames(), 4);
+    }
+
+    #[test]
+    fn concurrent_allocations_keep_the_allocator_consistent() {
+        const THREADS: u64 = 4;
+        const ROUNDS: usize = 2000;
+
+        let memmap = setup_test_memmap(4096);
+        let mut pmm = PhysicalMemoryManager::new(memmap);
+        pmm.add_region(PhysicalAddress::new(0), frames(4096));
+        for zone in Zone::ALL {
+            pmm.set_watermarks(zone, Watermarks::default());
+        }
+        let free_before = pmm.free_frames();
+        let memory = crate::AddressTranslator::current()
+            .emulated_memory()
+            .unwrap();
+
+        std::thread::scope(|scope| {
+            for thread in 1..=THREADS {
+                let memory = memory.share();
+                let pmm = &pmm;
+                scope.spawn(move || {
+                    crate::AddressTranslator::set_current(crate::AddressTranslator::Emulated(
+                        memory,
+                    ));
+
+                    let mut state = thread.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+                    let mut below = |bound: usize| {
+                        state ^= state << 13;
+                        state ^= state >> 7;
+                        state ^= state << 17;
+                        (state % bound as u64) as usize
+                    };
+
+                    // Each held block is tagged with a value unique to it, so a block handed to
+                    // two threads at once shows up as a clobbered tag. Runs from
+                    // `allocate_contiguous` are held with their frame count.
+                    let release = |addr: PhysicalAddress, run: Option<usize>| match run {
+                        Some(count) => pmm.deallocate_contiguous(addr, count),
+                        None => {
+                            pmm.put_frame(addr);
+                        }
+                    };
+                    let mut held = Vec::new();
+                    for round in 0..ROUNDS {
+                        if held.len() < 16 && below(3) != 0 {
+                            let run = (below(4) == 0).then(|| 1 + below(5));
+                            let result = match run {
+                                Some(count) => {
+                                    pmm.allocate_contiguous(count, 1, PhysicalAddress::new(0xFFFF))
+                                }
+                                None => pmm.allocate(below(4)),
+                            };
+                            let Ok(addr) = result else {
+                                continue;
+                            };
+                            let tag = thread << 32 | round as u64;
+                            // SAFETY: The block was just allocated to this thread.
+                            unsafe { pmm.phys_to_ptr::<u64>(addr).write(tag) };
+                            held.push((addr, run, tag));
+                        } else if !held.is_empty() {
+                            let (addr, run, tag) = held.swap_remove(below(held.len()));
+                            // SAFETY: The block is still allocated to this thread.
+                            assert_eq!(unsafe { pmm.phys_to_ptr::<u64>(addr).read() }, tag);
+                            release(addr, run);
+                        }
+                    }
+                    for (addr, run, _) in held {
+                        release(addr, run);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(pmm.allocated_frames(), 0);
+        assert_eq!(pmm.free_frames(), free_before);
+        pmm.verify().unwrap();
+    }
+
+    /// Randomized tests that check the allocator against a bitmap model after every operation.
+    mod model {
+        use super::*;
+        use alloc::format;
+        use alloc::string::String;
+
+        /// Number of frames in the modeled memory, spanning all three zones.
+        const MODEL_FRAMES: usize = 4096;
+
+        /// An operation applied to both the allocator and the model.
+        #[derive(Debug, Clone, Copy)]
+        enum Op {
+            Allocate {
+                order: usize,
+            },
+            AllocateAligned {
+                order: usize,
+                align_order: usize,
+            },
+            /// Frees the live allocation at `index % live.len()`, if there is one.
+            Deallocate {
+                index: usize,
+            },
+        }
+
+        /// A xorshift generator, so that every failure can be replayed from its seed.
+        struct Rng(u64);
+
+        impl Rng {
+            fn below(&mut self, bound: usize) -> usize {
+                self.0 ^= self.0 << 13;
+                self.0 ^= self.0 >> 7;
+                self.0 ^= self.0 << 17;
+                (self.0 % bound as u64) as usize
+            }
+        }
+
+        /// Generates `len` operations, biased towards small orders and roughly balanced between
+        /// allocating and freeing.
+        fn generate(seed: u64, len: usize) -> Vec<Op> {
+            let mut rng = Rng(seed);
+            (0..len)
+                .map(|_| {
+                    let order = rng.below(MAX_ORDER + 1).min(rng.below(MAX_ORDER + 1));
+                    match rng.below(8) {
+                        0..=2 => Op::Allocate { order },
+                        3 => Op::AllocateAligned {
+                            order,
+                            align_order: order + rng.below(MAX_ORDER + 1 - order),
+                        },
+                        _ => Op::Deallocate {
+                            index: rng.below(usize::MAX),
+                        },
+                    }
+                })
+                .collect()
+        }
+
+        /// Reference model: one bit per frame, set while the frame is allocated.
+        struct Model {
+            allocated: Vec<bool>,
+            live: Vec<(PhysicalAddress, usize)>,
+        }
+
+        impl Model {
+            fn frames(addr: PhysicalAddress, order: usize) -> core::ops::Range<usize> {
+                let start = addr.frame_number().as_usize();
+                start..start + (1 << order)
+            }
+
+            fn free_frames(&self) -> usize {
+                self.allocated
+                    .iter()
+                    .filter(|&&allocated| !allocated)
+                    .count()
+            }
+
+            /// Returns true if some naturally aligned block of the given order inside a single
+            /// zone is entirely free, in which case the buddy allocator must be able to serve it.
+            fn has_free_block(&self, order: usize) -> bool {
+                let size = 1 << order;
+                (0..MODEL_FRAMES).step_by(size).any(|start| {
+                    let first = PhysicalAddress::new(start * arch::PAGE_SIZE);
+                    let last = PhysicalAddress::new((start + size - 1) * arch::PAGE_SIZE);
+                    Zone::containing(first) == Zone::containing(last)
+                        && self.allocated[start..start + size].iter().all(|&a| !a)
+                })
+            }
+
+            fn take(&mut self, addr: PhysicalAddress, order: usize) -> Result<(), String> {
+                if !addr.is_aligned((1 << order) * arch::PAGE_SIZE) {
+                    return Err(format!("order-{order} block at {addr} is misaligned"));
+                }
+                let frames = Self::frames(addr, order);
+                if frames.end > MODEL_FRAMES {
+                    return Err(format!("order-{order} block at {addr} is out of range"));
+                }
+                if let Some(idx) = frames.clone().find(|&idx| self.allocated[idx]) {
+                    return Err(format!(
+                        "order-{order} block at {addr} overlaps allocated frame {idx}"
+                    ));
+                }
+                self.allocated[frames].fill(true);
+                self.live.push((addr, order));
+                Ok(())
+            }
+        }
+
+        fn new_allocator() -> PhysicalMemoryManager {
+            let memmap = setup_test_memmap(MODEL_FRAMES);
+            let mut pmm = PhysicalMemoryManager::new(memmap);
+            pmm.add_region(PhysicalAddress::new(0), frames(MODEL_FRAMES));
+            // Watermarks would make allocations fail while the model still has room.
+            for zone in Zone::ALL {
+                pmm.set_watermarks(zone, Watermarks::default());
+            }
+            pmm
+        }
+
+        /// Applies `ops` to a fresh allocator and the model, returning the first divergence.
+        fn run(ops: &[Op]) -> Result<(), String> {
+            let pmm = new_allocator();
+            let mut model = Model {
+                allocated: alloc::vec![false; MODEL_FRAMES],
+                live: Vec::new(),
+            };
+
+            for (step, &op) in ops.iter().enumerate() {
+                let result = match op {
+                    Op::Allocate { order } => Some((pmm.allocate(order), order, order)),
+                    Op::AllocateAligned { order, align_order } => {
+                        Some((pmm.allocate_aligned(order, align_order), order, align_order))
+                    }
+                    Op::Deallocate { index } => {
+                        if !model.live.is_empty() {
+                            let (addr, order) = model.live.swap_remove(index % model.live.len());
+                            model.allocated[Model::frames(addr, order)].fill(false);
+                            pmm.deallocate(addr, order);
+                        }
+                        None
+                    }
+                };
+
+                let checked = match result {
+                    Some((Ok(addr), order, _)) => model.take(addr, order),
+                    Some((Err(error), _, search_order)) => {
+                        if error != AllocError::OutOfMemory {
+                            Err(format!("unexpected error {error:?}"))
+                        } else if model.has_free_block(search_order) {
+                            Err(format!(
+                                "order-{search_order} request failed with memory free"
+                            ))
+                        } else {
+                            Ok(())
+                        }
+                    }
+                    None => Ok(()),
+                };
+
+                let checked = checked
+                    .and_then(|()| {
+                        let expected = model.free_frames();
+                        match pmm.free_frames() {
+                            free if free == expected => Ok(()),
+                            free => Err(format!("{free} frames free, model has {expected}")),
+                        }
+                    })
+                    .and_then(|()| {
+                        let expected = MODEL_FRAMES - model.free_frames();
+                        match pmm.allocated_frames() {
+                            allocated if allocated == expected => Ok(()),
+                            allocated => Err(format!(
+                                "{allocated} frames allocated, model has {expected}"
+                            )),
+                        }
+                    })
+                    .and_then(|()| pmm.verify().map_err(|error| format!("{error}")));
+
+                checked.map_err(|error| format!("step {step} ({op:?}): {error}"))?;
+            }
+
+            // Once everything is freed, every zone must be back to its fully coalesced layout.
+            for (addr, order) in model.live.drain(..) {
+                pmm.deallocate(addr, order);
+            }
+            let pristine = new_allocator();
+            for zone in Zone::ALL {
+                for order in 0..=MAX_ORDER {
+                    let blocks = pmm.zone_free_blocks_at_order(zone, order);
+                    let expected = pristine.zone_free_blocks_at_order(zone, order);
+                    if blocks != expected {
+                        return Err(format!(
+                            "after freeing everything zone {zone:?} has {blocks} order-{order} \
+                             blocks, expected {expected}"
+                        ));
+                    }
+                }
+            }
+            pmm.verify()
+                .map_err(|error| format!("after freeing everything: {error}"))
+        }
+
+        /// Shrinks a failing sequence to a locally minimal one that still fails.
+        ///
+        /// Removes progressively smaller chunks of operations, then lowers the orders of the
+        /// remaining ones, keeping every change that preserves the failure.
+        fn shrink(
+            mut ops: Vec<Op>,
+            run: impl Fn(&[Op]) -> Result<(), String>,
+        ) -> (Vec<Op>, String) {
+            let mut error = run(&ops).unwrap_err();
+
+            let mut chunk = ops.len() / 2;
+            while chunk > 0 {
+                let mut start = 0;
+                while start < ops.len() {
+                    let mut candidate = ops.clone();
+                    candidate.drain(start..(start + chunk).min(ops.len()));
+                    match run(&candidate) {
+                        Err(e) => {
+                            ops = candidate;
+                            error = e;
+                        }
+                        Ok(()) => start += chunk,
+                    }
+                }
+                chunk /= 2;
+            }
+
+            for idx in 0..ops.len() {
+                loop {
+                    let mut candidate = ops.clone();
+                    candidate[idx] = match ops[idx] {
+                        Op::Allocate { order } if order > 0 => Op::Allocate { order: order - 1 },
+                        Op::AllocateAligned { order, align_order } if align_order > order => {
+                            Op::AllocateAligned {
+                                order,
+                                align_order: align_order - 1,
+                            }
+                        }
+                        Op::AllocateAligned { order, .. } => Op::Allocate { order },
+                        Op::Deallocate { index } if index > 0 => Op::Deallocate { index: 0 },
+                        _ => break,
+                    };
+                    match run(&candidate) {
+                        Err(e) => {
+                            ops = candidate;
+                            error = e;
+                        }
+                        Ok(()) => break,
+                    }
+                }
+            }
+
+            (ops, error)
+        }
+
+        fn check(seed: u64, len: usize) {
+            let ops = generate(seed, len);
+            if run(&ops).is_ok() {
+                return;
+            }
+            let (minimal, error) = shrink(ops, run);
+            panic!(
+                "seed {seed:#x} fails; minimal reproducer ({} ops): {minimal:?}\n{error}",
+                minimal.len()
+            );
+        }
+
+        #[test]
+        fn random_sequences_match_bitmap_model() {
+            for seed in 1..=24u64 {
+                check(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15), 400);
+            }
+        }
+
+        #[test]
+        fn long_random_sequence_matches_bitmap_model() {
+            check(0x5EED, 4000);
+        }
+
+        #[test]
+        fn shrinking_finds_minimal_reproducer() {
+            // A sequence that fails whenever it requests an order-3 block shrinks down to a
+            // single plain allocation of that order.
+            let ops = alloc::vec![
+                Op::Allocate { order: 1 },
+                Op::Deallocate { index: 5 },
+                Op::AllocateAligned {
+                    order: 3,
+                    align_order: 6,
+                },
+                Op::Allocate { order: 0 },
+            ];
+            let failing = |ops: &[Op]| {
+                ops.iter().any(|op| {
+                    matches!(
+                        op,
+                        Op::Allocate { order: 3 } | Op::AllocateAligned { order: 3, .. }
+                    )
+                })
+            };
+            assert!(failing(&ops));
+
+            let (minimal, _) = shrink(ops, |ops| {
+                if failing(ops) {
+                    Err(String::from("order 3"))
+                } else {
+                    Ok(())
+                }
+            });
+            assert_eq!(minimal.len(), 1);
+            assert!(matches!(minimal[0], Op::Allocate { order: 3 }));
+        }
+    }
 }
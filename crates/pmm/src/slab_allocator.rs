@@ -0,0 +1,652 @@
+//! Slab allocator for small kernel heap objects.
+//!
+//! This module provides a size-class based slab allocator, loosely modeled on Linux's SLUB
+//! allocator. Small allocations are rounded up to a power-of-two size class, and each class
+//! carves fixed-size objects out of slabs: naturally-aligned buddy blocks obtained from the
+//! [`PhysicalMemoryManager`].
+//!
+//! Each slab starts with a [`Slab`] header followed by its objects. Free objects are chained
+//! through an intrusive list stored in the objects themselves. Every size class keeps its slabs
+//! on one of three lists:
+//!
+//! - **partial**: slabs with both free and allocated objects (allocations are served from here)
+//! - **full**: slabs with no free objects
+//! - **empty**: slabs with no allocated objects, cached to avoid thrashing the buddy allocator
+//!
+//! Allocations larger than [`MAX_SLAB_OBJECT_SIZE`] are not handled here and should go directly
+//! to the buddy allocator.
+//!
+//! With the `free-poisoning` feature, freed objects are filled with
+//! [`FREE_POISON`](crate::FREE_POISON) past their free-list link, and checked when they are
+//! allocated again. New slabs come from the buddy allocator already poisoned.
+
+use core::alloc::Layout;
+use core::ptr::{self, NonNull};
+
+use crate::physical_memory_manager::AllocError;
+use crate::{FrameFlag, PhysicalAddress, PhysicalMemoryManager, VirtualAddress, arch};
+
+/// Log2 of the smallest size class (8 bytes, enough to hold a free-list pointer).
+const MIN_CLASS_SHIFT: usize = 3;
+
+/// Log2 of the largest size class.
+const MAX_CLASS_SHIFT: usize = 11;
+
+/// Number of size classes (8, 16, 32, ..., 2048 bytes).
+const NUM_CLASSES: usize = MAX_CLASS_SHIFT - MIN_CLASS_SHIFT + 1;
+
+/// Largest object size (in bytes) served by the slab allocator.
+pub const MAX_SLAB_OBJECT_SIZE: usize = 1 << MAX_CLASS_SHIFT;
+
+/// Minimum number of objects each slab should hold.
+///
+/// Slabs are sized to the smallest buddy order that fits the header plus this many objects.
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+
+/// Number of empty slabs each size class keeps cached before returning them to the PMM.
+const MAX_EMPTY_SLABS: usize = 1;
+
+/// Magic value written into every slab header, used to catch frees of foreign pointers.
+const SLAB_MAGIC: u32 = 0x51AB_CAFE;
+
+/// Node in the intrusive free-object list of a slab.
+#[repr(C)]
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// Header stored at the start of every slab.
+#[repr(C)]
+struct Slab {
+    /// Next slab in the list this slab is on.
+    next: *mut Slab,
+    /// Previous slab in the list this slab is on.
+    prev: *mut Slab,
+    /// Head of this slab's free-object list.
+    free: *mut FreeObject,
+    /// Number of objects currently allocated from this slab.
+    in_use: usize,
+    /// Total number of objects in this slab.
+    capacity: usize,
+    /// Must equal [`SLAB_MAGIC`] for a valid slab.
+    magic: u32,
+    /// Index of the size class that owns this slab.
+    class: u32,
+}
+
+/// Intrusive doubly-linked list of slabs.
+struct SlabList {
+    head: *mut Slab,
+    len: usize,
+}
+
+impl SlabList {
+    /// Creates an empty slab list.
+    const fn new() -> Self {
+        Self {
+            head: ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    /// Returns true if the list has no slabs.
+    fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    /// Pushes a slab onto the front of the list.
+    ///
+    /// # Safety
+    /// `slab` must point to a valid slab header that is not on any list.
+    unsafe fn push(&mut self, slab: *mut Slab) {
+        unsafe {
+            (*slab).prev = ptr::null_mut();
+            (*slab).next = self.head;
+            if !self.head.is_null() {
+                (*self.head).prev = slab;
+            }
+        }
+        self.head = slab;
+        self.len += 1;
+    }
+
+    /// Removes a slab from the list.
+    ///
+    /// # Safety
+    /// `slab` must point to a valid slab header that is currently on this list.
+    unsafe fn remove(&mut self, slab: *mut Slab) {
+        unsafe {
+            let next = (*slab).next;
+            let prev = (*slab).prev;
+            if prev.is_null() {
+                self.head = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+            (*slab).next = ptr::null_mut();
+            (*slab).prev = ptr::null_mut();
+        }
+        self.len -= 1;
+    }
+
+    /// Pops the first slab from the list, returning None if empty.
+    fn pop(&mut self) -> Option<*mut Slab> {
+        if self.is_empty() {
+            return None;
+        }
+        let slab = self.head;
+        // SAFETY: head is a valid slab on this list.
+        unsafe { self.remove(slab) };
+        Some(slab)
+    }
+}
+
+/// Slabs and bookkeeping for a single size class.
+struct SlabCache {
+    partial: SlabList,
+    full: SlabList,
+    empty: SlabList,
+}
+
+impl SlabCache {
+    /// Creates an empty cache.
+    const fn new() -> Self {
+        Self {
+            partial: SlabList::new(),
+            full: SlabList::new(),
+            empty: SlabList::new(),
+        }
+    }
+}
+
+/// A slab allocator with power-of-two size classes from 8 bytes to [`MAX_SLAB_OBJECT_SIZE`].
+///
+/// The allocator does not own any memory itself: slabs are allocated from, and returned to, the
+/// [`PhysicalMemoryManager`] passed to each call. Objects are addressed through the direct map.
+pub struct SlabAllocator {
+    caches: [SlabCache; NUM_CLASSES],
+}
+
+// SAFETY: The raw pointers inside SlabAllocator point into direct-mapped physical memory owned
+// by the allocator; access is serialized by the caller (e.g. the kernel allocator's lock).
+unsafe impl Send for SlabAllocator {}
+
+impl SlabAllocator {
+    /// Creates a new slab allocator with no slabs.
+    pub const fn new() -> Self {
+        Self {
+            caches: [const { SlabCache::new() }; NUM_CLASSES],
+        }
+    }
+
+    /// Returns true if allocations with the given layout are served by the slab allocator.
+    pub fn handles(layout: Layout) -> bool {
+        Self::size_class(layout).is_some()
+    }
+
+    /// Allocates an object for the given layout.
+    ///
+    /// Serves the allocation from a partially-used slab if one exists, then from a cached empty
+    /// slab, and finally by allocating a new slab from the PMM.
+    ///
+    /// Returns [`AllocError::OrderTooLarge`] if the layout is too large for the slab allocator.
+    pub fn allocate(
+        &mut self,
+        pmm: &PhysicalMemoryManager,
+        layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let class = Self::size_class(layout).ok_or(AllocError::OrderTooLarge)?;
+        let cache = &mut self.caches[class];
+
+        let slab = match cache.partial.pop() {
+            Some(slab) => slab,
+            None => match cache.empty.pop() {
+                Some(slab) => slab,
+                None => Self::new_slab(pmm, class)?,
+            },
+        };
+
+        // SAFETY: slab was taken from one of our lists (or freshly created) and has a free object.
+        unsafe {
+            let object = (*slab).free;
+            debug_assert!(
+                !object.is_null(),
+                "slab on partial/empty list has no free objects"
+            );
+            (*slab).free = (*object).next;
+            (*slab).in_use += 1;
+            Self::check_free_poison(pmm, object as *mut u8, class);
+
+            if (*slab).in_use == (*slab).capacity {
+                cache.full.push(slab);
+            } else {
+                cache.partial.push(slab);
+            }
+
+            Ok(NonNull::new_unchecked(object as *mut u8))
+        }
+    }
+
+    /// Returns an object to its slab.
+    ///
+    /// Slabs that become empty are cached (up to a small limit) and otherwise returned to the PMM.
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by [`allocate`](Self::allocate) on this allocator with the
+    /// same `layout`, and must not have been freed already.
+    pub unsafe fn deallocate(
+        &mut self,
+        pmm: &PhysicalMemoryManager,
+        ptr: NonNull<u8>,
+        layout: Layout,
+    ) {
+        let Some(class) = Self::size_class(layout) else {
+            log::error!("slab: deallocation of {:?} with unsupported layout", ptr);
+            return;
+        };
+
+        let slab = Self::slab_for(ptr, class);
+        let slab_frame =
+            PhysicalAddress::from_direct_mapped(VirtualAddress::from_ptr(slab)).frame_number();
+        let is_slab = pmm
+            .frame(slab_frame)
+            .is_some_and(|frame| frame.flags.atomic_test(FrameFlag::Slab));
+
+        // SAFETY: The caller guarantees ptr came from a slab of this class, so slab points at a
+        // valid header. We still check the frame flag and magic to catch pointers that were not.
+        unsafe {
+            if !is_slab || (*slab).magic != SLAB_MAGIC || (*slab).class as usize != class {
+                log::error!(
+                    "slab: {:?} is not a slab object of class {} (leaking it)",
+                    ptr,
+                    class
+                );
+                return;
+            }
+
+            let cache = &mut self.caches[class];
+            let was_full = (*slab).in_use == (*slab).capacity;
+
+            #[cfg(feature = "free-poisoning")]
+            crate::free_poison::fill(ptr.as_ptr(), Self::class_size(class));
+
+            let object = ptr.as_ptr() as *mut FreeObject;
+            (*object).next = (*slab).free;
+            (*slab).free = object;
+            (*slab).in_use -= 1;
+
+            if was_full {
+                cache.full.remove(slab);
+            } else {
+                cache.partial.remove(slab);
+            }
+
+            if (*slab).in_use > 0 {
+                cache.partial.push(slab);
+            } else if cache.empty.len < MAX_EMPTY_SLABS {
+                cache.empty.push(slab);
+            } else {
+                Self::free_slab(pmm, slab, class);
+            }
+        }
+    }
+
+    /// Returns all cached empty slabs to the PMM.
+    pub fn release_empty(&mut self, pmm: &PhysicalMemoryManager) {
+        for class in 0..NUM_CLASSES {
+            while let Some(slab) = self.caches[class].empty.pop() {
+                Self::free_slab(pmm, slab, class);
+            }
+        }
+    }
+
+    /// Returns the object size in bytes for the size class serving `layout`.
+    pub fn object_size(layout: Layout) -> Option<usize> {
+        Self::size_class(layout).map(Self::class_size)
+    }
+
+    /// Returns the number of slabs (partial, full and empty) held by the allocator.
+    pub fn slab_count(&self) -> usize {
+        self.caches
+            .iter()
+            .map(|c| c.partial.len + c.full.len + c.empty.len)
+            .sum()
+    }
+
+    // Private helper methods
+
+    /// Returns the size class index for a layout, or None if it is too large.
+    fn size_class(layout: Layout) -> Option<usize> {
+        let size = layout
+            .size()
+            .max(layout.align())
+            .max(1 << MIN_CLASS_SHIFT)
+            .next_power_of_two();
+        if size > MAX_SLAB_OBJECT_SIZE {
+            return None;
+        }
+        Some(size.trailing_zeros() as usize - MIN_CLASS_SHIFT)
+    }
+
+    /// Returns the object size in bytes for a size class index.
+    const fn class_size(class: usize) -> usize {
+        1 << (class + MIN_CLASS_SHIFT)
+    }
+
+    /// Returns the offset of the first object in a slab of the given class.
+    ///
+    /// Objects are naturally aligned to their size, so the header is padded up to one object.
+    const fn first_object_offset(class: usize) -> usize {
+        let size = Self::class_size(class);
+        let header = core::mem::size_of::<Slab>();
+        header.div_ceil(size) * size
+    }
+
+    /// Returns the buddy order of the slabs used by a size class.
+    const fn slab_order(class: usize) -> usize {
+        let needed =
+            Self::first_object_offset(class) + MIN_OBJECTS_PER_SLAB * Self::class_size(class);
+        let mut order = 0;
+        while (arch::PAGE_SIZE << order) < needed {
+            order += 1;
+        }
+        order
+    }
+
+    /// Returns the size in bytes of the slabs used by a size class.
+    const fn slab_size(class: usize) -> usize {
+        arch::PAGE_SIZE << Self::slab_order(class)
+    }
+
+    /// Finds the slab header for an object.
+    ///
+    /// Slabs are naturally aligned buddy blocks, so the header is found by aligning the
+    /// object's physical address down to the slab size.
+    fn slab_for(ptr: NonNull<u8>, class: usize) -> *mut Slab {
+        let phys = PhysicalAddress::from_direct_mapped(VirtualAddress::from_ptr(ptr.as_ptr()));
+        let slab_phys = phys.align_down(Self::slab_size(class));
+        VirtualAddress::direct_mapped(slab_phys).as_mut_ptr()
+    }
+
+    /// Allocates a new slab for a size class from the PMM and initializes its free list.
+    fn new_slab(pmm: &PhysicalMemoryManager, class: usize) -> Result<*mut Slab, AllocError> {
+        let order = Self::slab_order(class);
+        let phys = pmm.allocate(order)?;
+        if let Some(frame) = pmm.frame(phys.frame_number()) {
+            frame.flags.atomic_set(FrameFlag::Slab);
+        }
+        let base = VirtualAddress::direct_mapped(phys).as_mut_ptr::<u8>();
+
+        let size = Self::class_size(class);
+        let first = Self::first_object_offset(class);
+        let capacity = (Self::slab_size(class) - first) / size;
+
+        // Thread the free list through the objects, lowest address first.
+        let mut free: *mut FreeObject = ptr::null_mut();
+        for index in (0..capacity).rev() {
+            // SAFETY: The object lies within the freshly allocated slab.
+            unsafe {
+                let object = base.add(first + index * size) as *mut FreeObject;
+                (*object).next = free;
+                free = object;
+            }
+        }
+
+        let slab = base as *mut Slab;
+        // SAFETY: The slab is at least one page and the header fits before the first object.
+        unsafe {
+            slab.write(Slab {
+                next: ptr::null_mut(),
+                prev: ptr::null_mut(),
+                free,
+                in_use: 0,
+                capacity,
+                magic: SLAB_MAGIC,
+                class: class as u32,
+            });
+        }
+
+        log::trace!(
+            "slab: new {}-byte slab at {} holding {} objects of {} bytes",
+            Self::slab_size(class),
+            phys,
+            capacity,
+            size
+        );
+
+        Ok(slab)
+    }
+
+    /// Checks that a free object just taken off its slab's free list still holds the free
+    /// poison past its link, reporting the first byte written to while it was free. Does
+    /// nothing without free poisoning.
+    fn check_free_poison(pmm: &PhysicalMemoryManager, object: *mut u8, class: usize) {
+        #[cfg(feature = "free-poisoning")]
+        {
+            let link = core::mem::size_of::<FreeObject>();
+            // SAFETY: The object lies within a slab, and was free until now.
+            let overwritten = unsafe {
+                crate::free_poison::find_overwritten(
+                    object.add(link),
+                    Self::class_size(class) - link,
+                )
+            };
+            if let Some((offset, byte)) = overwritten {
+                let addr = PhysicalAddress::from_direct_mapped(VirtualAddress::from_ptr(object))
+                    + (link + offset);
+                pmm.record_overwritten();
+                log::error!(
+                    "slab: free {}-byte object {:?} was written to at {} ({:#04x}), frame {:?}",
+                    Self::class_size(class),
+                    object,
+                    addr,
+                    byte,
+                    pmm.frame(addr.frame_number())
+                );
+            }
+        }
+        #[cfg(not(feature = "free-poisoning"))]
+        let _ = (pmm, object, class);
+    }
+
+    /// Returns a slab's memory to the PMM.
+    fn free_slab(pmm: &PhysicalMemoryManager, slab: *mut Slab, class: usize) {
+        // SAFETY: slab is a valid slab header that is no longer on any list.
+        unsafe { (*slab).magic = 0 };
+        let phys = PhysicalAddress::from_direct_mapped(VirtualAddress::from_ptr(slab));
+        pmm.deallocate(phys, Self::slab_order(class));
+    }
+}
+
+impl Default for SlabAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BootMemoryRegion, MemoryMap};
+
+    /// Test implementation of BootMemoryRegion.
+    struct TestRegion {
+        base: PhysicalAddress,
+        size: usize,
+    }
+
+    impl BootMemoryRegion for TestRegion {
+        fn base(&self) -> PhysicalAddress {
+            self.base
+        }
+
+        fn size(&self) -> usize {
+            self.size
+        }
+
+        fn is_usable(&self) -> bool {
+            true
+        }
+    }
+
+    /// Creates a PMM over emulated memory with all 4096 frames free.
+    fn setup_pmm() -> PhysicalMemoryManager {
+        let num_frames = 4096;
+        if crate::AddressTranslator::try_current().is_none() {
+            crate::AddressTranslator::set_current(crate::AddressTranslator::emulated(
+                num_frames * arch::PAGE_SIZE,
+            ));
+        }
+
+        let boot_map = [TestRegion {
+            base: PhysicalAddress::new(0),
+            size: num_frames * arch::PAGE_SIZE,
+        }];
+        let mut pmm = PhysicalMemoryManager::new(MemoryMap::from_boot_map(&boot_map));
+        pmm.add_region(PhysicalAddress::new(0), num_frames * arch::PAGE_SIZE);
+        pmm
+    }
+
+    #[test]
+    fn size_classes() {
+        assert_eq!(SlabAllocator::object_size(Layout::new::<u8>()), Some(8));
+        assert_eq!(
+            SlabAllocator::object_size(Layout::new::<[u8; 9]>()),
+            Some(16)
+        );
+        assert_eq!(
+            SlabAllocator::object_size(Layout::from_size_align(4, 32).unwrap()),
+            Some(32)
+        );
+        assert_eq!(
+            SlabAllocator::object_size(Layout::from_size_align(2048, 8).unwrap()),
+            Some(2048)
+        );
+        assert!(!SlabAllocator::handles(
+            Layout::from_size_align(2049, 8).unwrap()
+        ));
+    }
+
+    #[test]
+    fn allocates_distinct_aligned_objects() {
+        let pmm = setup_pmm();
+        let mut slab = SlabAllocator::new();
+        let layout = Layout::from_size_align(16, 16).unwrap();
+
+        let mut objects = Vec::new();
+        for _ in 0..32 {
+            let ptr = slab.allocate(&pmm, layout).unwrap();
+            assert_eq!(ptr.as_ptr() as usize % 16, 0);
+            objects.push(ptr.as_ptr() as usize);
+        }
+
+        objects.sort();
+        objects.dedup();
+        assert_eq!(objects.len(), 32);
+    }
+
+    #[test]
+    fn small_objects_share_a_slab() {
+        let pmm = setup_pmm();
+        let mut slab = SlabAllocator::new();
+        let layout = Layout::new::<u64>();
+
+        let free_before = pmm.free_frames();
+        let a = slab.allocate(&pmm, layout).unwrap();
+        let b = slab.allocate(&pmm, layout).unwrap();
+
+        assert_eq!(slab.slab_count(), 1);
+        assert_eq!(
+            free_before - pmm.free_frames(),
+            1 << SlabAllocator::slab_order(0)
+        );
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn reuses_freed_objects() {
+        let pmm = setup_pmm();
+        let mut slab = SlabAllocator::new();
+        let layout = Layout::new::<u64>();
+
+        let keep = slab.allocate(&pmm, layout).unwrap();
+        let a = slab.allocate(&pmm, layout).unwrap();
+        unsafe { slab.deallocate(&pmm, a, layout) };
+        let b = slab.allocate(&pmm, layout).unwrap();
+
+        assert_eq!(a, b);
+        assert_ne!(keep, b);
+    }
+
+    #[cfg(feature = "free-poisoning")]
+    #[test]
+    fn writes_to_freed_objects_are_reported() {
+        let pmm = setup_pmm();
+        let mut slab = SlabAllocator::new();
+        let layout = Layout::new::<[u64; 4]>();
+
+        let a = slab.allocate(&pmm, layout).unwrap();
+        unsafe { slab.deallocate(&pmm, a, layout) };
+        assert_eq!(pmm.stats().overwritten_blocks, 0);
+
+        // A write past the free-list link, as a stale pointer into the object would make.
+        unsafe { a.as_ptr().add(16).write(0) };
+        let b = slab.allocate(&pmm, layout).unwrap();
+
+        assert_eq!(a, b);
+        assert_eq!(pmm.stats().overwritten_blocks, 1);
+    }
+
+    #[test]
+    fn grows_when_slab_is_full() {
+        let pmm = setup_pmm();
+        let mut slab = SlabAllocator::new();
+        let layout = Layout::new::<u64>();
+
+        let order = SlabAllocator::slab_order(0);
+        let capacity = ((arch::PAGE_SIZE << order) - SlabAllocator::first_object_offset(0)) / 8;
+
+        let objects: Vec<_> = (0..=capacity)
+            .map(|_| slab.allocate(&pmm, layout).unwrap())
+            .collect();
+
+        assert_eq!(slab.slab_count(), 2);
+        for ptr in objects {
+            unsafe { slab.deallocate(&pmm, ptr, layout) };
+        }
+    }
+
+    #[test]
+    fn returns_empty_slabs_to_pmm() {
+        let pmm = setup_pmm();
+        let mut slab = SlabAllocator::new();
+        let free_before = pmm.free_frames();
+
+        let small = Layout::new::<u64>();
+        let large = Layout::from_size_align(64, 8).unwrap();
+        let a = slab.allocate(&pmm, small).unwrap();
+        let b = slab.allocate(&pmm, large).unwrap();
+        unsafe {
+            slab.deallocate(&pmm, a, small);
+            slab.deallocate(&pmm, b, large);
+        }
+
+        // Empty slabs are cached until released.
+        assert_eq!(slab.slab_count(), 2);
+        slab.release_empty(&pmm);
+        assert_eq!(slab.slab_count(), 0);
+        assert_eq!(pmm.free_frames(), free_before);
+    }
+
+    #[test]
+    fn rejects_large_layouts() {
+        let pmm = setup_pmm();
+        let mut slab = SlabAllocator::new();
+        let layout = Layout::from_size_align(MAX_SLAB_OBJECT_SIZE + 1, 8).unwrap();
+
+        assert_eq!(slab.allocate(&pmm, layout), Err(AllocError::OrderTooLarge));
+    }
+}
@@ -0,0 +1,166 @@
+//! Allocation and fragmentation statistics for the physical memory manager.
+
+use core::fmt;
+
+use crate::physical_memory_manager::NUM_FREE_LISTS;
+use crate::{HumanSize, arch};
+
+/// A snapshot of the counters kept by the [`PhysicalMemoryManager`].
+///
+/// Frame counts are exact: every frame covered by the memory map is either free, allocated,
+/// reserved, poisoned, or has not been handed to the allocator yet.
+///
+/// The [`Display`](fmt::Display) implementation prints a multi-line summary with sizes
+/// formatted as [`HumanSize`].
+///
+/// [`PhysicalMemoryManager`]: crate::PhysicalMemoryManager
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PmmStats {
+    /// Frames covered by the memory map.
+    pub total_frames: usize,
+    /// Frames sitting in the free lists.
+    pub free_frames: usize,
+    /// Frames handed out by the allocator and not yet returned.
+    pub allocated_frames: usize,
+    /// Frames flagged as reserved in the memory map, including the kernel image.
+    pub reserved_frames: usize,
+    /// Poisoned frames withheld from the free lists.
+    pub poisoned_frames: usize,
+    /// The largest value `allocated_frames` has reached.
+    pub peak_allocated_frames: usize,
+    /// Number of free blocks at each order.
+    pub free_blocks: [usize; NUM_FREE_LISTS],
+    /// Number of successful allocations at each order.
+    pub allocations: [u64; NUM_FREE_LISTS],
+    /// Number of failed allocations at each order.
+    pub failures: [u64; NUM_FREE_LISTS],
+    /// Number of times a free block was split in two to serve a smaller allocation.
+    pub splits: u64,
+    /// Number of times a freed block was merged with its buddy.
+    pub merges: u64,
+    /// Number of free blocks and slab objects found written to when they were allocated
+    /// again. Always zero without the `free-poisoning` feature.
+    pub overwritten_blocks: u64,
+}
+
+impl PmmStats {
+    /// Returns the fragmentation index for allocations of the given order, in thousandths.
+    ///
+    /// This follows Linux's `extfrag_index`: values near 0 mean an allocation of this order
+    /// would fail for lack of memory, values near 1000 mean it would fail because free memory
+    /// is split into blocks that are too small. Returns `None` when a large enough free block
+    /// exists, since the allocation would then succeed.
+    pub fn fragmentation_index(&self, order: usize) -> Option<u16> {
+        if order >= NUM_FREE_LISTS || self.free_blocks[order..].iter().any(|&count| count > 0) {
+            return None;
+        }
+
+        let blocks: usize = self.free_blocks.iter().sum();
+        if blocks == 0 {
+            return Some(0);
+        }
+
+        let requested = 1 << order;
+        let index = 1000usize.saturating_sub((1000 + self.free_frames * 1000 / requested) / blocks);
+        Some(index as u16)
+    }
+}
+
+impl fmt::Display for PmmStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let size = |frames: usize| HumanSize(frames * arch::PAGE_SIZE);
+
+        writeln!(
+            f,
+            "total {}, free {}, allocated {} (peak {}), reserved {}, poisoned {}",
+            size(self.total_frames),
+            size(self.free_frames),
+            size(self.allocated_frames),
+            size(self.peak_allocated_frames),
+            size(self.reserved_frames),
+            size(self.poisoned_frames)
+        )?;
+        writeln!(
+            f,
+            "splits {}, merges {}, overwritten free blocks {}",
+            self.splits, self.merges, self.overwritten_blocks
+        )?;
+        for order in 0..NUM_FREE_LISTS {
+            write!(
+                f,
+                "order {:2} ({}): {} free, {} allocations, {} failures",
+                order,
+                HumanSize((1 << order) * arch::PAGE_SIZE),
+                self.free_blocks[order],
+                self.allocations[order],
+                self.failures[order]
+            )?;
+            match self.fragmentation_index(order) {
+                Some(index) => writeln!(f, ", fragmentation {}.{:03}", index / 1000, index % 1000)?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    fn stats_with_free_blocks(free_blocks: &[usize]) -> PmmStats {
+        let mut stats = PmmStats::default();
+        for (order, &count) in free_blocks.iter().enumerate() {
+            stats.free_blocks[order] = count;
+            stats.free_frames += count << order;
+        }
+        stats
+    }
+
+    #[test]
+    fn fragmentation_index_is_none_when_allocation_would_succeed() {
+        let stats = stats_with_free_blocks(&[0, 0, 1]);
+
+        assert_eq!(stats.fragmentation_index(0), None);
+        assert_eq!(stats.fragmentation_index(2), None);
+    }
+
+    #[test]
+    fn fragmentation_index_is_zero_without_free_memory() {
+        let stats = stats_with_free_blocks(&[]);
+
+        assert_eq!(stats.fragmentation_index(3), Some(0));
+    }
+
+    #[test]
+    fn fragmentation_index_grows_with_scattered_free_frames() {
+        let few = stats_with_free_blocks(&[1]);
+        let many = stats_with_free_blocks(&[64]);
+
+        assert_eq!(few.fragmentation_index(3), Some(0));
+        assert_eq!(many.fragmentation_index(3), Some(860));
+    }
+
+    #[test]
+    fn display_reports_sizes() {
+        let mut stats = stats_with_free_blocks(&[0, 2]);
+        stats.total_frames = 8;
+        stats.allocated_frames = 4;
+        stats.peak_allocated_frames = 6;
+
+        let text = format!("{}", stats);
+        let first = text.lines().next().unwrap();
+        assert_eq!(
+            first,
+            format!(
+                "total {}, free {}, allocated {} (peak {}), reserved 0B, poisoned 0B",
+                HumanSize(8 * arch::PAGE_SIZE),
+                HumanSize(4 * arch::PAGE_SIZE),
+                HumanSize(4 * arch::PAGE_SIZE),
+                HumanSize(6 * arch::PAGE_SIZE)
+            )
+        );
+        assert_eq!(text.lines().count(), 2 + NUM_FREE_LISTS);
+    }
+}